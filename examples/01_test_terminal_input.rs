@@ -15,8 +15,8 @@ fn main() -> io::Result<()> {
 
     terminal_start!();
 
-    println! {"Press Ctrl+C to Exit"};
-    println! {"Terminal size: {:?} \n", terminal::size().unwrap()};
+    println!("Press Ctrl+C to Exit");
+    println!("Terminal size: {:?} \n", terminal::size().unwrap());
 
     serial_buffer.push("------- Start --------\n");
     serial_buffer.push("Greetings\n ");
@@ -53,7 +53,7 @@ fn main() -> io::Result<()> {
             let serial_msg = serial_buffer[msg_index];
 
             // Write buffer
-            stdout.write(serial_msg.as_bytes()).ok();
+            stdout.write_all(serial_msg.as_bytes()).ok();
 
             msg_index = if msg_index == serial_buffer.len() - 1 { 0 } else { msg_index + 1 };
             last_print = Instant::now();
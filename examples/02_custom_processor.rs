@@ -0,0 +1,50 @@
+//! Host terminal with a data processor of its own
+//! run with: `cargo run --example 02_custom_processor -- [port] [options]`
+//!
+//! Counts the records of each kind. `:count` prints the counts, `:clear` restarts them.
+
+use std::collections::BTreeMap;
+
+use anyhow::Result as AnyResult;
+use mxs_serial_link::app;
+use mxs_serial_link::app::processors::{Console, Sample};
+use mxs_serial_link::mxs_processor::{MxsDataProcessor, MxsFlow};
+
+struct RecordCounter {
+    counts:  BTreeMap<&'static str, u64>,
+    console: Console,
+}
+
+impl MxsDataProcessor<Sample> for RecordCounter {
+    fn name(&self) -> &str {
+        "Record counter"
+    }
+
+    fn process(&mut self, sample: &mut Sample) -> AnyResult<MxsFlow> {
+        *self.counts.entry(sample.record.name()).or_default() += 1;
+        Ok(MxsFlow::Continue)
+    }
+
+    fn command(&mut self, command: &str) -> AnyResult<bool> {
+        match command {
+            "count" => {
+                for (name, count) in &self.counts {
+                    self.console.print(format!("{}: {}", name, count));
+                }
+            }
+            "clear" => self.counts.clear(),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+fn main() {
+    app::run_with(|chain, console| {
+        chain.register(RecordCounter {
+            counts:  BTreeMap::new(),
+            console: console.clone(),
+        });
+        Ok(())
+    });
+}
@@ -0,0 +1,159 @@
+//! Derive macros for MXS Serial Link
+//!
+//! Use through `mxs_serial_link::mxs_record::MxsRecord` with the `derive` feature.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{Data, DeriveInput, Fields, LitInt, parse_macro_input};
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                           MXS Record
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+/// Implement `MxsRecord` for a struct
+///
+/// `#[mxs(id = N)]` sets the record id (0 to 255), which is not checked against other records, see
+/// `mxs_record_ids_unique`. `#[mxs(size = N)]` optionally pins the encoded size, so a layout
+/// change on either end fails to compile. Records that don't fit in a Data packet next to their
+/// record id, `MAX_DATA_LEN - RECORD_ID_LEN` bytes, fail to compile.
+#[proc_macro_derive(MxsRecord, attributes(mxs))]
+pub fn derive_mxs_record(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (id, size) = parse_attributes(input)?;
+
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(&input.generics, "MxsRecord structs can't be generic"));
+    }
+
+    let Data::Struct(data) = &input.data
+    else {
+        return Err(syn::Error::new_spanned(name, "MxsRecord can only be derived for structs"));
+    };
+
+    // ---- Field accessors and types, in declaration order
+    let (members, types): (Vec<_>, Vec<_>) = match &data.fields {
+        Fields::Named(fields) => fields
+            .named
+            .iter()
+            .map(|f| {
+                let ident = f.ident.as_ref().unwrap();
+                (quote!(#ident), &f.ty)
+            })
+            .unzip(),
+        Fields::Unnamed(fields) => fields
+            .unnamed
+            .iter()
+            .enumerate()
+            .map(|(i, f)| {
+                let index = syn::Index::from(i);
+                (quote!(#index), &f.ty)
+            })
+            .unzip(),
+        Fields::Unit => (Vec::new(), Vec::new()),
+    };
+
+    let locals: Vec<_> = (0..members.len())
+        .map(|i| format_ident!("field_{}", i))
+        .collect();
+
+    let construct = match &data.fields {
+        Fields::Named(_) => quote!(Self { #(#members: #locals),* }),
+        Fields::Unnamed(_) => quote!(Self(#(#locals),*)),
+        Fields::Unit => quote!(Self),
+    };
+
+    let krate = quote!(::mxs_serial_link::mxs_record);
+
+    let size_check = size.map(|size| {
+        let msg = format!("{} is not {} bytes long", name, size);
+        quote! {
+            const _: () = assert!(<#name as #krate::MxsRecord>::SIZE == #size, #msg);
+        }
+    });
+    let max_msg = format!(
+        "{} and its record id are larger than MAX_DATA_LEN, the largest payload of a Data packet",
+        name
+    );
+
+    Ok(quote! {
+        impl #krate::MxsRecord for #name {
+            const RECORD_ID: u8 = #id;
+            const SIZE: usize = 0 #(+ <#types as #krate::MxsField>::SIZE)*;
+
+            #[allow(unused_assignments, unused_mut, unused_variables)]
+            fn encode(&self, buf: &mut [u8]) {
+                assert_eq!(buf.len(), Self::SIZE, "Record buffer size");
+
+                let mut pos = 0;
+                #(
+                    let end = pos + <#types as #krate::MxsField>::SIZE;
+                    #krate::MxsField::write_le(&self.#members, &mut buf[pos..end]);
+                    pos = end;
+                )*
+            }
+
+            #[allow(unused_assignments, unused_mut, unused_variables)]
+            fn decode(buf: &[u8]) -> Option<Self> {
+                if buf.len() != Self::SIZE {
+                    return None;
+                }
+
+                let mut pos = 0;
+                #(
+                    let end = pos + <#types as #krate::MxsField>::SIZE;
+                    let #locals = <#types as #krate::MxsField>::read_le(&buf[pos..end]);
+                    pos = end;
+                )*
+
+                Some(#construct)
+            }
+        }
+
+        const _: () = assert!(
+            <#name as #krate::MxsRecord>::SIZE + #krate::RECORD_ID_LEN <= #krate::MAX_DATA_LEN,
+            #max_msg
+        );
+        #size_check
+    })
+}
+
+/// Read `#[mxs(id = N, size = N)]`
+fn parse_attributes(input: &DeriveInput) -> syn::Result<(u8, Option<usize>)> {
+    let mut id = None;
+    let mut size = None;
+
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("mxs")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("id") {
+                let lit: LitInt = meta.value()?.parse()?;
+                id = Some(lit.base10_parse::<u8>()?);
+                Ok(())
+            }
+            else if meta.path.is_ident("size") {
+                let lit: LitInt = meta.value()?.parse()?;
+                size = Some(lit.base10_parse::<usize>()?);
+                Ok(())
+            }
+            else {
+                Err(meta.error("Expected `id` or `size`"))
+            }
+        })?;
+    }
+
+    let Some(id) = id
+    else {
+        return Err(syn::Error::new_spanned(&input.ident, "Missing record id, add #[mxs(id = N)]"));
+    };
+
+    Ok((id, size))
+}
@@ -0,0 +1,1410 @@
+//! Host terminal
+//!
+//! The `mxs` program as a library. `run` is what the binary does; `run_with` adds processors of
+//! your own to the data processor chain, without changing this crate:
+//!
+//! ```no_run
+//! # use anyhow::Result as AnyResult;
+//! # use mxs_serial_link::app::processors::Sample;
+//! # use mxs_serial_link::mxs_processor::{MxsDataProcessor, MxsFlow};
+//! # struct MyProcessor;
+//! # impl MxsDataProcessor<Sample> for MyProcessor {
+//! #     fn name(&self) -> &str { "My processor" }
+//! #     fn process(&mut self, _sample: &mut Sample) -> AnyResult<MxsFlow> { Ok(MxsFlow::Continue) }
+//! # }
+//! use mxs_serial_link::app;
+//!
+//! fn main() {
+//!     app::run_with(|chain, _console| {
+//!         chain.register(MyProcessor);
+//!         Ok(())
+//!     });
+//! }
+//! ```
+//!
+//! See `examples/02_custom_processor.rs`.
+
+pub mod alarm;
+mod capture;
+mod cli;
+mod config;
+pub mod data;
+mod pcapng;
+mod plot;
+pub mod processors;
+mod replay;
+pub mod schema;
+mod sink;
+mod stats;
+mod usb;
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock, mpsc};
+use std::thread::{self, JoinHandle, sleep};
+use std::time::Instant;
+use std::{env, fs};
+
+use crate::mxs_decoder::*;
+use crate::mxs_processor::*;
+use crate::mxs_reassembler::*;
+use crate::mxs_registry::*;
+use crate::mxs_reliable::*;
+use crate::stdio_helper::*;
+use crate::{terminal_exit, terminal_start};
+use alarm::{AlarmEvent, AlarmRule, check_alarm_fields};
+use capture::{CaptureHeader, CaptureMode, CaptureTap, CaptureWriter};
+use cli::{CliArgs, CliCommand, SerialSettings};
+use data::*;
+use pcapng::{MXS_DISSECTOR, export_pcapng_file};
+use plot::Plot;
+use processors::{Console,
+                 ProcessorRegistration,
+                 Sample,
+                 UserProcessor,
+                 register_processors,
+                 register_user_processors};
+use replay::ReplayPort;
+use schema::DataSchema;
+use serialport::SerialPort;
+use sink::SinkConfig;
+use stats::DataStats;
+use usb::{UsbMatch, describe_port};
+
+use anyhow::{Context, Result as AnyResult};
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                             Globals
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+const READ_BUFFER_SIZE: usize = 2000;
+
+/// Longest wait for the next fragment of a message
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Input lines starting with this prefix are sent as reliable MXS commands
+const COMMAND_PREFIX: char = '!';
+/// Input lines starting with this prefix are handled by the program
+const LOCAL_COMMAND_PREFIX: char = ':';
+
+/// Minimum time between panel redraws
+const PANEL_REFRESH: Duration = Duration::from_millis(50);
+/// Time between statistics updates from the data thread
+const STATS_REFRESH: Duration = Duration::from_millis(250);
+/// Longest time between data processor polls
+const DATA_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Direct mode skips MXS packet filtering
+static DIRECT_MODE: OnceLock<bool> = OnceLock::new();
+
+/// MXS protocol settings selected on the command line
+static LINK_CONFIG: OnceLock<MxsConfig> = OnceLock::new();
+
+/// Baud rate, framing and control lines of the serial port
+static SERIAL_SETTINGS: OnceLock<SerialSettings> = OnceLock::new();
+
+/// Application packet types
+static PACKET_TYPES: OnceLock<MxsTypeRegistry> = OnceLock::new();
+
+/// Ack timeout and retries of the reliable commands
+static RELIABLE_CONFIG: OnceLock<MxsReliableConfig> = OnceLock::new();
+
+/// Layout of Data packet payloads
+static DATA_SCHEMA: OnceLock<DataSchema> = OnceLock::new();
+
+/// Record logging, disabled when `None`
+static DATA_SINK: OnceLock<Option<SinkConfig>> = OnceLock::new();
+
+/// Rules checked against every decoded record
+static ALARM_RULES: OnceLock<Vec<AlarmRule>> = OnceLock::new();
+
+/// Input lines of the profile macros, by name
+static MACROS: OnceLock<BTreeMap<String, Vec<String>>> = OnceLock::new();
+
+/// Records are also sent to this address as JSON datagrams
+static FORWARD_ADDR: OnceLock<Option<SocketAddr>> = OnceLock::new();
+
+/// Raw traffic capture, disabled when `None`
+static CAPTURE_PATH: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// Records are plotted instead of printed while set
+static PLOT_VISIBLE: AtomicBool = AtomicBool::new(false);
+
+/// Field statistics are sent to the main thread while set
+static STATS_VISIBLE: AtomicBool = AtomicBool::new(false);
+
+/// Processors added through `run_with`
+static USER_PROCESSORS: OnceLock<Vec<UserProcessor>> = OnceLock::new();
+
+/// Main thread of the current connection, for `Console`
+static CONSOLE_TX: Mutex<Option<mpsc::Sender<ThreadMsg>>> = Mutex::new(None);
+
+#[cfg(target_os = "linux")]
+type PortType = serialport::TTYPort;
+#[cfg(windows)]
+type PortType = serialport::COMPort;
+
+/// Port the serial thread talks to, a serial port or a capture replay
+trait LinkPort: Read + Write + Send + 'static {
+    /// Read timeout wanted by the serial thread, ports without one ignore it
+    fn set_read_timeout(&mut self, timeout: Duration) {}
+}
+
+impl LinkPort for PortType {
+    fn set_read_timeout(&mut self, timeout: Duration) {
+        if self.timeout() != timeout {
+            self.set_timeout(timeout).ok();
+        }
+    }
+}
+
+impl LinkPort for ReplayPort {}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Main
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+/// Run the terminal with the processors given by `register` added to the chain
+///
+/// `register` is called once at startup. Its processors run in the chain of every connection,
+/// after the statistics and alarm checks and before the records are logged, forwarded, plotted and
+/// printed, and keep their state across reconnects.
+pub fn run_with(
+    register: impl FnOnce(&mut MxsProcessorChain<Sample>, &Console) -> AnyResult<()> + Send + 'static,
+) {
+    run_terminal(Some(Box::new(register)));
+}
+
+/// Run the terminal with the arguments of the process, see `mxs help`
+pub fn run() {
+    run_terminal(None);
+}
+
+fn run_terminal(register: Option<ProcessorRegistration>) {
+    terminal_start!();
+
+    // —————————————————————————————————————————— Args —————————————————————————————————————————————
+
+    let cli = match CliArgs::parse(env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("{:#}\nSee `mxs help` for the options", e);
+            terminal_exit!(1);
+        }
+    };
+
+    // Print Help
+    if cli.command == CliCommand::Help {
+        print!(
+            r#" 
+  MXS Serial Link - Serial Communication Program for Embedded Applications
+
+    Usage: mxs [port] [@profile] [options]
+           mxs vid=<hex> pid=<hex> [@profile] [options]
+           mxs replay <file> [speed=<factor>] [options]
+           mxs export <file> [out=<file>] [dissector=<file>]
+
+      Arguments:
+
+        [port]         - port name. Defaults to largest port 
+        @<profile>     - apply a profile from mxs.toml or ~/.config/mxs/config.toml. 
+                         Options given here replace the profile ones 
+        direct         - direct mode. Skips MXP packet filtering 
+        stuffed        - byte stuffed MXS framing. Must match the device 
+        plain          - plain MXS framing (default) 
+        schema=<file>  - Data payload layout (TOML). Defaults to three i16 
+        log=<file>     - log decoded records to a .csv or .jsonl file 
+        rotate=<limit> - start a new log file after a size (10MB) or time (15min) 
+        alarm=<rule>   - e.g. "field0 > 800 for 50ms => !stop". Can be repeated 
+        alarms=<file>  - alarm rules, one per line 
+        forward=<addr> - send decoded records as JSON over UDP, e.g. localhost:9000 
+        capture=<file> - record the raw serial traffic of each connection 
+        help           - displays this message 
+
+      Serial:
+
+        baud=<rate>         - baud rate. Defaults to 115200 
+        databits=<5-8>      - data bits. Defaults to 8, below 8 needs direct mode 
+        parity=<parity>     - none (default), odd or even 
+        stopbits=<1|2>      - stop bits. Defaults to 1 
+        flow=<control>      - none (default), rts-cts or xon-xoff. xon-xoff needs direct mode 
+        timeout=<duration>  - longest a read blocks, e.g. 200ms. Defaults to 500ms 
+        dtr=<on|off>        - DTR level when opening the port. Defaults to on 
+        rts=<on|off>        - RTS level after opening. Not with flow=rts-cts 
+
+      USB Match:
+
+        vid=<hex>            - USB vendor id, e.g. 0483 
+        pid=<hex>            - USB product id, e.g. 5740 
+        sn=<text>            - USB serial number 
+        manufacturer=<text>  - USB manufacturer string 
+        product=<text>       - USB product string 
+                               Selects the port instead of [port]. Case-insensitive, 
+                               * and ? wildcards. The largest port number when several match 
+
+      Replay:
+
+        <file>          - capture to feed through the decoder instead of a port. 
+                          The framing is taken from the capture 
+        speed=<factor>  - 1 is real time (default), 2 twice as fast, max as fast as possible 
+
+      Export:
+
+        <file>           - capture to convert to pcapng for Wireshark (user DLT 147) 
+        out=<file>       - output file. Defaults to the capture name with .pcapng 
+        dissector=<file> - also write the MXS Lua dissector for Wireshark 
+
+      Commands:
+
+        acktimeout=<duration> - wait for a command Ack before retransmitting. Defaults to 300ms 
+        retries=<n>           - retransmissions before reporting a failure. Defaults to 3 
+
+      Input:
+
+        !<command> - sends a reliable MXS command. Retransmitted until the
+                     device answers with Ack, reported as failed otherwise
+        :reset     - restarts the field statistics
+        :<macro>   - sends the lines of a profile macro
+
+      Keys:
+
+        F2 - show or hide the live plot of Data fields
+        F3 - pause or resume the plot
+        F4 - switch between sparkline and braille charts
+        F5 - show or hide min, max, mean, stddev and rate of Data fields
+           "#
+        );
+        terminal_exit!();
+    }
+
+    // Convert a capture to pcapng
+    if let CliCommand::Export { file, out, dissector } = &cli.command {
+        let out = out.clone().unwrap_or_else(|| file.with_extension("pcapng"));
+
+        match export_pcapng_file(file, &out) {
+            Ok(packets) => println!("Exported {} packets to {}", packets, out.display()),
+            Err(e) => {
+                eprintln!("{:#}", e);
+                terminal_exit!(1);
+            }
+        }
+
+        if let Some(dissector) = dissector {
+            if let Err(e) = fs::write(dissector, MXS_DISSECTOR) {
+                eprintln!("Couldn't write {}: {}", dissector.display(), e);
+                terminal_exit!(1);
+            }
+            println!("Wireshark dissector written to {}", dissector.display());
+        }
+        terminal_exit!();
+    }
+
+    // Replay a capture instead of connecting
+    let replay = match &cli.command {
+        CliCommand::Replay { file, speed } => match ReplayPort::open(file, *speed) {
+            Ok(port) => Some((file.display().to_string(), port)),
+            Err(e) => {
+                eprintln!("{:#}", e);
+                terminal_exit!(1);
+            }
+        },
+        _ => None,
+    };
+
+    // A replay decodes the capture the way it was recorded. The device side is negotiated again
+    // from the captured Start packet
+    let (direct, framing, protocol) = match &replay {
+        Some((_, port)) => {
+            let mode = port.header().mode;
+            let framing = if mode == CaptureMode::Stuffed {
+                MxsFraming::Stuffed
+            }
+            else {
+                MxsFraming::Plain
+            };
+            (mode == CaptureMode::Direct, framing, port.header().host_protocol)
+        }
+        None => {
+            let framing = if cli.stuffed { MxsFraming::Stuffed } else { MxsFraming::Plain };
+            (cli.direct, framing, MXS_PROTOCOL_VERSION)
+        }
+    };
+    DIRECT_MODE.set(direct).unwrap();
+    LINK_CONFIG
+        .set(MxsConfig::for_version(protocol, framing))
+        .unwrap();
+    SERIAL_SETTINGS.set(cli.serial).unwrap();
+    RELIABLE_CONFIG.set(cli.reliable).unwrap();
+
+    let mut registry = MxsTypeRegistry::new();
+    if let Err(e) = register_packet_types(&mut registry) {
+        eprintln!("Packet type registration failed: {}", e);
+        terminal_exit!(1);
+    }
+    PACKET_TYPES.set(registry).unwrap();
+
+    let schema = match &cli.schema {
+        Some(path) => match DataSchema::load(path) {
+            Ok(schema) => schema,
+            Err(e) => {
+                eprintln!("{:#}", e);
+                terminal_exit!(1);
+            }
+        },
+        None => DataSchema::default(),
+    };
+    DATA_SCHEMA.set(schema).unwrap();
+
+    let sink = match &cli.log {
+        Some(path) => match SinkConfig::new(path, cli.rotate) {
+            Ok(config) => Some(config),
+            Err(e) => {
+                eprintln!("{:#}", e);
+                terminal_exit!(1);
+            }
+        },
+        None => None,
+    };
+    DATA_SINK.set(sink).unwrap();
+
+    let mut alarm_rules = cli.alarms.clone();
+    for path in &cli.alarm_files {
+        match AlarmRule::load(path) {
+            Ok(rules) => alarm_rules.extend(rules),
+            Err(e) => {
+                eprintln!("{:#}", e);
+                terminal_exit!(1);
+            }
+        }
+    }
+    let fields = Record::numeric_field_names(DATA_SCHEMA.get().unwrap());
+    if let Err(e) = check_alarm_fields(&alarm_rules, &fields) {
+        eprintln!("{:#}", e);
+        terminal_exit!(1);
+    }
+    ALARM_RULES.set(alarm_rules).unwrap();
+
+    let user_processors = match register.map(register_user_processors) {
+        Some(Ok(processors)) => processors,
+        Some(Err(e)) => {
+            eprintln!("{:#}", e);
+            terminal_exit!(1);
+        }
+        None => Vec::new(),
+    };
+    USER_PROCESSORS.set(user_processors).ok();
+
+    let forward = match &cli.forward {
+        Some(addr) => match addr.to_socket_addrs().map(|mut addrs| addrs.next()) {
+            Ok(Some(addr)) => Some(addr),
+            Ok(None) => {
+                eprintln!("Forward address {} did not resolve", addr);
+                terminal_exit!(1);
+            }
+            Err(e) => {
+                eprintln!("Invalid forward address {}: {}", addr, e);
+                terminal_exit!(1);
+            }
+        },
+        None => None,
+    };
+    FORWARD_ADDR.set(forward).unwrap();
+
+    CAPTURE_PATH.set(cli.capture.clone()).unwrap();
+    MACROS.set(cli.macros.clone()).unwrap();
+
+    let mut input_port_name = cli.port.clone().unwrap_or_default();
+
+    // ————————————————————————————————————————   Main  ——————————————————————————————————————————
+
+    println!("\n=== Serial Link Started ===");
+
+    // Direct mode skips MXS packet filtering
+    println!(
+        "{}",
+        if direct {
+            "        Direct mode \n"
+        }
+        else if framing == MxsFraming::Stuffed {
+            "     with MXS Protocol \n       Stuffed framing \n"
+        }
+        else {
+            "     with MXS Protocol \n"
+        }
+    );
+
+    if let Some(profile) = &cli.profile {
+        println!("Profile @{}", profile);
+        if !cli.macros.is_empty() {
+            let macros: Vec<String> = cli
+                .macros
+                .keys()
+                .map(|name| format!("{}{}", LOCAL_COMMAND_PREFIX, name))
+                .collect();
+            println!("Macros: {}", macros.join(" "));
+        }
+    }
+
+    // —————————————————————————————————————————— Replay —————————————————————————————————————————
+
+    if let Some((path, port)) = replay {
+        println!("\nReplaying {}", path.clone().red());
+        println!("{}\n", port.header());
+
+        if let Err(e) = handle_connection(port, format!("replay {}", path)) {
+            eprintln!("\n\nError: {}", e);
+            terminal_exit!(1);
+        }
+        println!("\nReplay finished");
+        terminal_exit!();
+    }
+
+    'main: loop {
+        // —————————————————————————————————————— Find Port ————————————————————————————————————————
+
+        println!("\nAvailable Ports");
+        println!("==============");
+        if let Ok(ports) = serialport::available_ports() {
+            for port in &ports {
+                println!(
+                    "{} {}",
+                    port.port_name.clone().dark_blue(),
+                    describe_port(port).dark_grey()
+                );
+            }
+        }
+        else {
+            println!("{}", "No ports".red())
+        }
+        println!("______________");
+
+        if !cli.usb.is_empty() {
+            println!("\nUSB Match");
+            println!("==============");
+            println!("{}", cli.usb.to_string().red());
+        }
+        else if input_port_name.is_empty() {
+            println!("\nPort not provided. Connecting to largest port number.");
+        }
+        else {
+            println!("\nInput Port");
+            println!("==============");
+            println!("{}", input_port_name.to_owned().red());
+        }
+
+        print!("\nSearching for port ...");
+        io::stdout().flush().unwrap();
+
+        let port_name = match find_port(&input_port_name, &cli.usb) {
+            Ok(name) => {
+                println!();
+                name
+            }
+            Err(e) => {
+                eprintln!("\n{}", e);
+                continue 'main;
+            }
+        };
+
+        let serial_port = match connect_to_port(&port_name) {
+            Ok(p) => {
+                println!("\n\nConnected!");
+                println!("==============\n");
+                p
+            }
+            Err(e) => {
+                eprintln!("\n{}\n", e);
+                continue 'main;
+            }
+        };
+
+        // Reconnect to the same port, a USB match is looked up again as the name can change
+        let port_name = serial_port.name().unwrap();
+        if cli.usb.is_empty() {
+            input_port_name = port_name.clone();
+        }
+
+        // —————————————————————————————————— Handle Connection ————————————————————————————————————
+
+        if let Err(e) = handle_connection(serial_port, port_name) {
+            eprintln!("\n\nError: {}", e);
+            eprintln!("Disconnected. Retrying Connection...\n");
+            continue 'main;
+        }
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                        Handle Connection
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+fn handle_connection(serial_port: impl LinkPort, port_name: String) -> AnyResult<()> {
+    let (main_thread_tx, main_thread_rx) = mpsc::channel::<ThreadMsg>();
+    let (serial_thread_tx, serial_thread_rx) = mpsc::channel::<SerialMsg>();
+    let (data_thread_tx, data_thread_rx) = mpsc::channel::<DataMsg>();
+    *CONSOLE_TX.lock().unwrap() = Some(main_thread_tx.clone());
+
+    spawn_serial_thread(serial_port, port_name.clone(), main_thread_tx.clone(), serial_thread_rx);
+    let data_thread = spawn_data_thread(main_thread_tx.clone(), data_thread_rx);
+
+    let mut stdout = std::io::stdout();
+    let mut std_output = String::new();
+    let mut std_input = String::new();
+    let mut link_stats: Option<MxsLinkStats> = None;
+
+    let mut plot = Plot::new();
+    let mut data_stats = DataStats::new();
+    let mut last_panel_draw = Instant::now();
+    PLOT_VISIBLE.store(false, Ordering::Relaxed);
+    STATS_VISIBLE.store(false, Ordering::Relaxed);
+
+    'main_rx: loop {
+        let msg_result = main_thread_rx.recv_timeout(Duration::from_millis(10));
+
+        if let Ok(msg) = msg_result {
+            match msg {
+                ThreadMsg::Print(s) => {
+                    std_output.push_str(&s);
+                }
+                ThreadMsg::Error(e) => {
+                    eprintln!("Thread Error: {}", e);
+                    stdout.write_all(std_output.as_bytes())?;
+                    std_output.clear();
+                    continue;
+                }
+                ThreadMsg::Data(record) => {
+                    data_thread_tx.send(DataMsg::Data(record)).unwrap();
+                }
+                ThreadMsg::Plot(fields) => {
+                    plot.push(&fields);
+                }
+                ThreadMsg::Message(message) => {
+                    data_thread_tx.send(DataMsg::Message(message)).unwrap();
+                }
+                ThreadMsg::Stats(stats) => {
+                    link_stats = Some(stats);
+                }
+                ThreadMsg::DataStats(stats) => {
+                    data_stats = stats;
+                }
+                ThreadMsg::Alarm(event) => {
+                    let msg = format!("Alarm: {} ({} = {})", event.rule, event.field, event.value);
+                    std_output.push_str(&format!("\x07\n{}\n", msg.black().on_red()));
+
+                    if let Some(action) = event.action {
+                        std_output.push_str(&format!("Alarm action: {}\n", action));
+                        serial_thread_tx.send(serial_msg(&format!("{}\n", action)))?;
+                    }
+                }
+                ThreadMsg::Done => {
+                    std_output.push_str("\nThread Done\n");
+                }
+                ThreadMsg::Started => {
+                    std_output.push_str("\nThread Started\n");
+                }
+                ThreadMsg::Exiting => {
+                    std_output.push_str("\nThread Exiting\n");
+                    stdout.write_all(std_output.as_bytes())?;
+                    std_output.clear();
+                    break;
+                }
+            }
+        }
+
+        // ———————————————————————————————————————— Input ——————————————————————————————————————————
+
+        // Read stdin raw - non-blocking
+        for key in read_raw_stdin_input(&mut std_input)? {
+            match key.code {
+                KeyCode::F(2) => {
+                    plot.toggle_visible();
+                    PLOT_VISIBLE.store(plot.is_visible(), Ordering::Relaxed);
+                }
+                KeyCode::F(3) => plot.toggle_pause(),
+                KeyCode::F(4) => plot.toggle_style(),
+                KeyCode::F(5) => {
+                    STATS_VISIBLE.fetch_xor(true, Ordering::Relaxed);
+                }
+                _ => continue,
+            }
+            // Redraw right away
+            last_panel_draw = Instant::now() - PANEL_REFRESH;
+        }
+
+        // Detect new line in input buffer
+        if std_input.ends_with('\n') {
+            std_output.push_str(&format!("\n{} {}", ">>:".green(), std_input.clone().blue())); // Print the input line
+
+            // Handled locally
+            if let Some(command) = std_input.strip_prefix(LOCAL_COMMAND_PREFIX) {
+                let command = command.trim_end();
+
+                // Profile macros are sent as if typed
+                if let Some(lines) = MACROS.get().unwrap().get(command) {
+                    for line in lines {
+                        std_output.push_str(&format!(
+                            "{} {}\n",
+                            ">>:".green(),
+                            line.clone().blue()
+                        ));
+                        serial_thread_tx.send(serial_msg(&format!("{}\n", line)))?;
+                    }
+                }
+                else {
+                    data_thread_tx.send(DataMsg::Command(command.to_string()))?;
+                }
+            }
+            // Sending to serial thread
+            else {
+                serial_thread_tx.send(serial_msg(&std_input))?;
+            }
+            std_input.clear();
+        }
+
+        // Write all
+        stdout.write_all(std_output.as_bytes())?;
+        std_output.clear();
+
+        // —————————————————————————————————————— Input Bar ————————————————————————————————————————
+
+        // Link statistics
+        let stats_msg = link_stats
+            .map(|s| format!("[{}] ", s).dark_grey().to_string())
+            .unwrap_or_default();
+
+        // Format status msg
+        let status_bar_msg = format_args!(
+            "{} {}{} {}",
+            port_name.clone().red(),
+            stats_msg,
+            ">>:".green(),
+            std_input.clone().blue()
+        )
+        .to_string();
+
+        print_input_bar(&status_bar_msg);
+
+        // ———————————————————————————————————————— Panel ——————————————————————————————————————————
+
+        if last_panel_draw.elapsed() >= PANEL_REFRESH {
+            let (cols, _rows) = terminal::size()?;
+            let mut panel = Vec::new();
+
+            if STATS_VISIBLE.load(Ordering::Relaxed) {
+                panel.extend(data_stats.render());
+            }
+            if plot.is_visible() {
+                panel.extend(plot.render(cols as usize));
+            }
+
+            set_panel_height(panel.len() as u16);
+            print_panel(&panel);
+            last_panel_draw = Instant::now();
+        }
+    }
+
+    set_panel_height(0);
+
+    // Let the data thread finish the queued records, so logs are complete
+    drop(data_thread_tx);
+    let _ = data_thread.join();
+    *CONSOLE_TX.lock().unwrap() = None;
+    for msg in main_thread_rx.try_iter() {
+        match msg {
+            ThreadMsg::Print(s) => stdout.write_all(s.as_bytes())?,
+            ThreadMsg::Error(e) => eprintln!("Thread Error: {}", e),
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                          Data Thread
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+/// Decoded payloads handled by the data thread
+#[derive(Debug)]
+pub enum DataMsg {
+    Data(Record),
+    Message(Message),
+    /// Local command for the data processors
+    Command(String),
+}
+
+fn spawn_data_thread(
+    main_thread_tx: mpsc::Sender<ThreadMsg>,
+    data_thread_rx: mpsc::Receiver<DataMsg>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut chain = MxsProcessorChain::new();
+        if let Err(e) = register_processors(&mut chain, &main_thread_tx) {
+            main_thread_tx
+                .send(ThreadMsg::Error(format!("Data processors: {:#}", e)))
+                .unwrap();
+        }
+
+        'data: loop {
+            let msg = match data_thread_rx.recv_timeout(DATA_POLL_INTERVAL) {
+                Ok(msg) => Some(msg),
+                Err(mpsc::RecvTimeoutError::Timeout) => None,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break 'data,
+            };
+
+            let mut errors = chain.poll();
+
+            match msg {
+                // ---- Records go through the processor chain
+                Some(DataMsg::Data(record)) => {
+                    errors.extend(chain.process(&mut Sample::new(record)));
+                }
+                Some(DataMsg::Message(message)) => {
+                    let msg = match message.process() {
+                        Ok(res) => ThreadMsg::Print(res),
+                        Err(e) => ThreadMsg::Error(format!("{}", e)),
+                    };
+                    main_thread_tx.send(msg).unwrap();
+                }
+                Some(DataMsg::Command(command)) => {
+                    let (handled, command_errors) = chain.command(&command);
+                    errors.extend(command_errors);
+
+                    if !handled {
+                        main_thread_tx
+                            .send(ThreadMsg::Error(format!(
+                                "Unknown command: {}{}",
+                                LOCAL_COMMAND_PREFIX, command
+                            )))
+                            .unwrap();
+                    }
+                }
+                None => (),
+            }
+
+            for e in errors {
+                main_thread_tx
+                    .send(ThreadMsg::Error(e.to_string()))
+                    .unwrap();
+            }
+        }
+    })
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                          Serial Thread
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[derive(Debug)]
+pub enum ThreadMsg {
+    Started,
+    Done,
+    Exiting,
+    Error(String),
+    Print(String),
+    Data(Record),
+    Message(Message),
+    Stats(MxsLinkStats),
+    DataStats(DataStats),
+    Alarm(AlarmEvent),
+    /// Numeric fields of a record, for the plot
+    Plot(Vec<(String, f64)>),
+}
+
+/// Output requests handled by the serial thread
+#[derive(Debug)]
+pub enum SerialMsg {
+    /// Raw text written as is
+    Write(String),
+    /// Reliable MXS command
+    Command(String),
+}
+
+fn spawn_serial_thread(
+    serial_port: impl LinkPort,
+    port_name: String,
+    main_thread_tx: mpsc::Sender<ThreadMsg>,
+    local_thread_rx: mpsc::Receiver<SerialMsg>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        main_thread_tx.send(ThreadMsg::Started).unwrap();
+
+        // Everything read and written goes through the capture
+        let capture = match open_capture(&port_name) {
+            Ok(capture) => capture,
+            Err(e) => {
+                main_thread_tx
+                    .send(ThreadMsg::Error(format!("Capture disabled: {:#}", e)))
+                    .unwrap();
+                None
+            }
+        };
+        if let Some((_, path)) = &capture {
+            main_thread_tx
+                .send(ThreadMsg::Print(format!("Capturing to {}\n", path.display())))
+                .unwrap();
+        }
+        let mut serial_port = CaptureTap::new(serial_port, capture.map(|(capture, _)| capture));
+
+        let host_config = *LINK_CONFIG.get().unwrap();
+        let mut link_config = host_config;
+        // Set when the device speaks an unsupported protocol version
+        let mut raw_output = false;
+
+        let registry = PACKET_TYPES.get().unwrap();
+        let schema = DATA_SCHEMA.get().unwrap();
+        let mut reassembler = MxsReassembler::new(REASSEMBLY_TIMEOUT);
+        let mut stats = MxsLinkStats::new();
+
+        let reliable_config = *RELIABLE_CONFIG.get().unwrap();
+        let mut reliable = MxsReliableSender::new(link_config, reliable_config);
+
+        let mut decoder = MxsStreamDecoder::new(link_config);
+        decoder.set_types(registry.ids());
+        let mut raw_read = [0u8; READ_BUFFER_SIZE];
+
+        'serial_rw: loop {
+            // Serial Write
+            if let Ok(output_msg) = local_thread_rx.try_recv() {
+                let output = match output_msg {
+                    SerialMsg::Write(text) => text.into_bytes(),
+                    SerialMsg::Command(command) => {
+                        if let Err(e) = check_command(&command, &link_config, raw_output) {
+                            main_thread_tx.send(ThreadMsg::Error(e)).unwrap();
+                            continue 'serial_rw;
+                        }
+
+                        let (seq, packet) = reliable.send(command.as_bytes());
+                        main_thread_tx
+                            .send(ThreadMsg::Print(format!("Command #{} sent\n", seq)))
+                            .unwrap();
+                        packet
+                    }
+                };
+
+                if let Err(e) = serial_port.write_all(&output) {
+                    main_thread_tx
+                        .send(ThreadMsg::Error(format!("Serial write error: {:?}", e)))
+                        .unwrap();
+                    break 'serial_rw;
+                };
+            }
+
+            // Command Retransmission
+            for event in reliable.poll() {
+                if let Err(e) = handle_delivery_event(event, &mut serial_port, &main_thread_tx) {
+                    main_thread_tx
+                        .send(ThreadMsg::Error(format!("Serial write error: {:?}", e)))
+                        .unwrap();
+                    break 'serial_rw;
+                }
+            }
+
+            // Wake up in time for the next retransmission
+            let port_timeout = SERIAL_SETTINGS.get().unwrap().timeout;
+            let read_timeout = match reliable.next_deadline() {
+                Some(deadline) => deadline
+                    .saturating_duration_since(Instant::now())
+                    .clamp(Duration::from_millis(1), port_timeout),
+                None => port_timeout,
+            };
+            serial_port.port_mut().set_read_timeout(read_timeout);
+
+            // Serial Read
+            match serial_port.read(&mut raw_read) {
+                Ok(n) => {
+                    // Direct Mode
+                    if *DIRECT_MODE.get().unwrap() || raw_output {
+                        main_thread_tx
+                            .send(ThreadMsg::Print(format!(
+                                "{}",
+                                String::from_utf8_lossy(&raw_read[..n])
+                            )))
+                            .unwrap();
+
+                        // Raw output lasts until the device restarts with a supported version
+                        if raw_output {
+                            decoder.push(&raw_read[..n]);
+                            let handshake = decoder.events().find_map(|event| match event {
+                                MxsEvent::Packet(packet)
+                                    if packet.packet_type == MxsPacketType::Start =>
+                                {
+                                    let device = MxsDeviceInfo::from_payload(&packet.data).ok()?;
+                                    let config = host_config.negotiate(&device).ok()?;
+                                    Some((device.to_string(), config))
+                                }
+                                _ => None,
+                            });
+
+                            if let Some((device, config)) = handshake {
+                                raw_output = false;
+                                link_config = config;
+                                reliable.set_link_config(config);
+                                decoder.clear();
+                                decoder.set_config(config);
+
+                                main_thread_tx
+                                    .send(ThreadMsg::Print(format!("Device: {}\n", device)))
+                                    .unwrap();
+                            }
+                        }
+                        continue 'serial_rw;
+                    }
+
+                    // MXS Packet Filtering Mode
+                    decoder.push(&raw_read[..n]);
+                    let mut packets_received = false;
+
+                    for event in decoder.events() {
+                        let packet = match event {
+                            // Handle non-packet data
+                            MxsEvent::Text(text) => {
+                                main_thread_tx
+                                    .send(ThreadMsg::Print(format!(
+                                        "{}",
+                                        String::from_utf8_lossy(&text)
+                                    )))
+                                    .unwrap();
+                                continue;
+                            }
+
+                            // Report packets that failed the CRC check
+                            MxsEvent::Corrupted(packet) => {
+                                stats.record_corrupted();
+                                packets_received = true;
+                                main_thread_tx
+                                    .send(ThreadMsg::Error(format!(
+                                        "Corrupted {:?} packet ({} bytes): CRC {:#06x} != {:#06x}",
+                                        packet.packet_type,
+                                        packet.data.len(),
+                                        packet.received_crc,
+                                        packet.computed_crc
+                                    )))
+                                    .unwrap();
+                                continue;
+                            }
+
+                            MxsEvent::Packet(packet) => packet,
+                        };
+
+                        stats.record_packet(&packet);
+                        packets_received = true;
+
+                        // ---- Process Packets based on type
+                        match &packet.packet_type {
+                            // Fragment of a larger message
+                            MxsPacketType::Data if packet.fragment.is_some() => {
+                                let fragment = packet.fragment.as_ref().unwrap();
+
+                                if let Some(message) = reassembler.push(fragment, &packet.data) {
+                                    main_thread_tx
+                                        .send(ThreadMsg::Message(Message(message)))
+                                        .unwrap();
+                                }
+                            }
+                            // Sized Data
+                            MxsPacketType::Data => {
+                                let packet_data = packet.data.as_ref();
+
+                                match Record::decode(packet.record, packet_data, schema) {
+                                    Ok(record) => {
+                                        main_thread_tx.send(ThreadMsg::Data(record)).unwrap();
+                                    }
+                                    Err(e) => {
+                                        main_thread_tx
+                                            .send(ThreadMsg::Error(format!(
+                                                "Couldn't convert byte stream into data: {}",
+                                                e
+                                            )))
+                                            .unwrap();
+                                    }
+                                }
+                            }
+                            // Handshake
+                            MxsPacketType::Start => {
+                                let negotiated = MxsDeviceInfo::from_payload(&packet.data)
+                                    .and_then(|device| {
+                                        Ok((device, host_config.negotiate(&device)?))
+                                    });
+
+                                match negotiated {
+                                    Ok((device, config)) => {
+                                        raw_output = false;
+                                        link_config = config;
+                                        reliable.set_link_config(config);
+
+                                        main_thread_tx
+                                            .send(ThreadMsg::Print(format!("Device: {}\n", device)))
+                                            .unwrap();
+                                    }
+                                    Err(e @ MxsHandshakeError::UnsupportedVersion(_)) => {
+                                        raw_output = true;
+
+                                        main_thread_tx
+                                            .send(ThreadMsg::Error(format!(
+                                                "{}. Showing raw data",
+                                                e
+                                            )))
+                                            .unwrap();
+                                        break;
+                                    }
+                                    Err(e) => {
+                                        main_thread_tx
+                                            .send(ThreadMsg::Error(format!(
+                                                "Handshake failed: {}",
+                                                e
+                                            )))
+                                            .unwrap();
+                                    }
+                                }
+                            }
+                            // Reliable command answers
+                            MxsPacketType::Ack | MxsPacketType::Nak => {
+                                let Some(event) = reliable.handle_packet(&packet)
+                                else {
+                                    continue;
+                                };
+
+                                if let Err(e) =
+                                    handle_delivery_event(event, &mut serial_port, &main_thread_tx)
+                                {
+                                    main_thread_tx
+                                        .send(ThreadMsg::Error(format!(
+                                            "Serial write error: {:?}",
+                                            e
+                                        )))
+                                        .unwrap();
+                                    break 'serial_rw;
+                                }
+                            }
+                            // Application Packets
+                            MxsPacketType::Unknown(id) => {
+                                let msg = match registry.get(*id) {
+                                    Some(kind) => match (kind.decoder)(&packet.data) {
+                                        Ok(text) => {
+                                            ThreadMsg::Print(format!("{}: {}\n", kind.name, text))
+                                        }
+                                        Err(e) => ThreadMsg::Error(format!(
+                                            "Couldn't decode {} packet: {}",
+                                            kind.name, e
+                                        )),
+                                    },
+                                    None => ThreadMsg::Print(format!(
+                                        "Received: Unknown({}) {} bytes\n",
+                                        id,
+                                        packet.data.len()
+                                    )),
+                                };
+                                main_thread_tx.send(msg).unwrap();
+                            }
+                            // Unsized Msg Packets
+                            MxsPacketType::End => {
+                                main_thread_tx
+                                    .send(ThreadMsg::Print("Received: End\n".into()))
+                                    .unwrap();
+                            }
+
+                            // Other Notification Packets
+                            p => {
+                                main_thread_tx
+                                    .send(ThreadMsg::Print(format!("Received: {:?}\n", p)))
+                                    .unwrap();
+                            }
+                        } // ----
+                    }
+
+                    // ---- Apply the handshake outcome
+                    decoder.set_config(link_config);
+                    if raw_output {
+                        decoder.clear();
+                    }
+
+                    // ---- Update status bar counters
+                    if packets_received {
+                        main_thread_tx.send(ThreadMsg::Stats(stats)).unwrap();
+                    }
+                }
+
+                // Timeout > Ignore
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => (),
+
+                // End of a replayed capture > Return
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    main_thread_tx
+                        .send(ThreadMsg::Print(format!("\n{}\n", e)))
+                        .unwrap();
+                    break 'serial_rw;
+                }
+
+                // Error > Return
+                Err(ref e) => {
+                    main_thread_tx
+                        .send(ThreadMsg::Error(format!("Serial read error: {:?}", e)))
+                        .unwrap();
+                    break 'serial_rw;
+                }
+            };
+
+            // ---- Report messages that could not be reassembled
+            for message in reassembler.poll_incomplete() {
+                main_thread_tx
+                    .send(ThreadMsg::Error(format!(
+                        "Incomplete message {} ({:?}): {} fragments, {} bytes received",
+                        message.message_id, message.reason, message.fragments, message.bytes
+                    )))
+                    .unwrap();
+            }
+
+            // ---- Keep the capture flushed
+            serial_port.poll();
+            if let Some(e) = serial_port.take_error() {
+                main_thread_tx
+                    .send(ThreadMsg::Error(format!("Capture disabled: {}", e)))
+                    .unwrap();
+            }
+        }
+
+        // Done
+        main_thread_tx.send(ThreadMsg::Exiting).unwrap();
+    })
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                            Functions
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+/// Input line for the serial thread, a reliable command when it starts with `COMMAND_PREFIX`
+fn serial_msg(line: &str) -> SerialMsg {
+    match line.strip_prefix(COMMAND_PREFIX) {
+        Some(command) => SerialMsg::Command(command.trim_end().to_string()),
+        None => SerialMsg::Write(line.to_string()),
+    }
+}
+
+/// Whether a command can be sent over the negotiated link
+fn check_command(command: &str, link_config: &MxsConfig, raw_output: bool) -> Result<(), String> {
+    if raw_output {
+        return Err("Commands are disabled, the device protocol is not supported".into());
+    }
+    if !link_config.seq {
+        return Err("Device does not support reliable commands".into());
+    }
+    if command.len() > MAX_DATA_LEN && !link_config.ext_len {
+        return Err(format!(
+            "Command longer than {} bytes, the device does not support extended length",
+            MAX_DATA_LEN
+        ));
+    }
+    Ok(())
+}
+
+/// Report a reliable command outcome, writing retransmissions to the port
+fn handle_delivery_event(
+    event: MxsDeliveryEvent,
+    serial_port: &mut impl Write,
+    main_thread_tx: &mpsc::Sender<ThreadMsg>,
+) -> io::Result<()> {
+    match event {
+        MxsDeliveryEvent::Confirmed { seq, attempts } => {
+            let msg = format!("Command #{} confirmed ({} attempts)", seq, attempts);
+            main_thread_tx
+                .send(ThreadMsg::Print(format!("{}\n", msg.green())))
+                .unwrap();
+        }
+        MxsDeliveryEvent::Retransmit { seq, packet } => {
+            serial_port.write_all(&packet)?;
+        }
+        MxsDeliveryEvent::Failed { seq, attempts, reason } => {
+            main_thread_tx
+                .send(ThreadMsg::Error(format!(
+                    "Command #{} failed after {} attempts: {:?}",
+                    seq, attempts, reason
+                )))
+                .unwrap();
+        }
+    }
+
+    Ok(())
+}
+
+/// Start a capture file for the connection, when enabled
+fn open_capture(port_name: &str) -> AnyResult<Option<(CaptureWriter, PathBuf)>> {
+    let Some(path) = CAPTURE_PATH.get().unwrap()
+    else {
+        return Ok(None);
+    };
+
+    let framing = LINK_CONFIG.get().unwrap().framing;
+    let mode = match (*DIRECT_MODE.get().unwrap(), framing) {
+        (true, _) => CaptureMode::Direct,
+        (false, MxsFraming::Plain) => CaptureMode::Plain,
+        (false, MxsFraming::Stuffed) => CaptureMode::Stuffed,
+    };
+
+    let header = CaptureHeader {
+        port: port_name.to_string(),
+        baud: SERIAL_SETTINGS.get().unwrap().baud,
+        mode,
+        host_protocol: MXS_PROTOCOL_VERSION,
+        started: chrono::Utc::now(),
+    };
+
+    CaptureWriter::create(path, &header).map(Some)
+}
+
+fn find_port(port_name: &str, usb: &UsbMatch) -> AnyResult<String> {
+    loop {
+        let serial_port = serialport::available_ports().context("Failed to list ports")?;
+
+        if !usb.is_empty() {
+            let matching: Vec<_> = serial_port.into_iter().filter(|p| usb.matches(p)).collect();
+            if matching.len() > 1 {
+                let names: Vec<&str> = matching.iter().map(|p| p.port_name.as_str()).collect();
+                print!("\n{} ports match: {}", matching.len(), names.join(", "));
+            }
+            if let Some(value) = auto_select_port(matching) {
+                return Ok(value);
+            }
+        }
+        else if !port_name.is_empty() {
+            if serial_port.iter().any(|p| p.port_name == port_name) {
+                return Ok(port_name.to_string());
+            }
+        }
+        // No port specified
+        else {
+            // Auto find the port with the longest name or largest number
+            if let Some(value) = auto_select_port(serial_port) {
+                return Ok(value);
+            }
+        }
+
+        print!(".");
+        io::stdout().flush()?;
+        sleep(Duration::from_secs(1));
+    }
+}
+
+/// Find the port with the longest name or largest number
+fn auto_select_port(serial_port: Vec<serialport::SerialPortInfo>) -> Option<String> {
+    if serial_port.is_empty() {
+        return None;
+    }
+
+    let mut sorted_ports = serial_port.clone();
+    sorted_ports.sort_by_key(|k| k.port_name.len());
+
+    let name_len = sorted_ports[0].port_name.len();
+
+    if let Some(port) = serial_port
+        .iter()
+        .filter(|f| f.port_name.len() == name_len)
+        .max_by_key(|p| generate_key_from_suffix(&p.port_name))
+    {
+        return Some(port.port_name.clone());
+    }
+
+    None
+}
+
+fn generate_key_from_suffix(name: &str) -> u16 {
+    if name.is_empty() {
+        return 0;
+    };
+
+    let mut key = 0_u16;
+
+    if name.ends_with(|pat: char| pat.is_numeric()) {
+        name.chars()
+            .rev()
+            .take_while(|c| c.is_numeric())
+            .enumerate()
+            .for_each(|f| {
+                let i = f.0;
+                let n = f.1.to_digit(10).unwrap() as u16;
+                key += if i == 0 { n } else { i as u16 * 10 * n };
+            });
+        return key;
+    }
+    else {
+        return 0;
+    }
+}
+
+fn connect_to_port(port_name: &str) -> AnyResult<PortType> {
+    let settings = SERIAL_SETTINGS.get().unwrap();
+    println!("Connecting to port: {} ({})", port_name.to_owned().red(), settings);
+    io::stdout().flush()?;
+
+    const ATTEMPTS: u8 = 5;
+
+    for attempt in 0..=ATTEMPTS {
+        match settings.builder(port_name).open_native() {
+            Ok(mut port) => {
+                if let Some(rts) = settings.rts {
+                    port.write_request_to_send(rts)
+                        .context("Couldn't set RTS")?;
+                }
+                return Ok(port);
+            }
+            Err(e) if attempt == ATTEMPTS => {
+                return Err(e).context("Failed after 5 attempts");
+            }
+            Err(e) => {
+                println!("Port Error: {}", e.to_string().red());
+            }
+        }
+        sleep(Duration::from_millis(500));
+    }
+    unreachable!()
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Tests
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_lines() {
+        let cases = [
+            ("hello\n", None),
+            (" !stop\n", None),
+            ("!stop\n", Some("stop")),
+            ("!led on \r\n", Some("led on")),
+        ];
+
+        for (line, expected) in cases {
+            match serial_msg(line) {
+                SerialMsg::Command(command) => {
+                    assert_eq!(Some(command.as_str()), expected, "{}", line)
+                }
+                SerialMsg::Write(text) => {
+                    assert_eq!(expected, None, "{}", line);
+                    assert_eq!(text, line);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn commands_need_a_reliable_link() {
+        let reliable = MxsConfig::for_version(MXS_PROTOCOL_VERSION, MxsFraming::Plain);
+        let short = MxsConfig {
+            ext_len: false,
+            ..reliable
+        };
+        let long = "x".repeat(MAX_DATA_LEN + 1);
+
+        assert!(check_command("stop", &reliable, false).is_ok());
+        assert!(check_command(&long, &reliable, false).is_ok());
+        assert!(check_command(&long, &short, false).is_err());
+        assert!(check_command("stop", &MxsConfig::LEGACY, false).is_err());
+        assert!(check_command("stop", &reliable, true).is_err());
+    }
+}
@@ -0,0 +1,438 @@
+//! Threshold alarms on decoded record fields
+//!
+//! Rules are one line each:
+//!
+//! ```text
+//! field0 > 800                     - fires when the value crosses above 800
+//! field0 > 800 for 50ms            - the value must stay above 800 for 50 ms
+//! imu.gyro.2 changes sign          - fires on every sign change, zeros are skipped
+//! battery.charge <= 10 => !stop    - also sends `stop` as a reliable command
+//! ```
+//!
+//! Field names are the ones shown in the plot and statistics panels, rules on other fields are
+//! rejected. A rule fires once when its condition becomes true and re-arms when it turns false
+//! again.
+
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result as AnyResult, bail};
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Rules
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Equal,
+    NotEqual,
+}
+
+impl CompareOp {
+    fn matches(self, left: f64, right: f64) -> bool {
+        match self {
+            Self::Greater => left > right,
+            Self::GreaterEq => left >= right,
+            Self::Less => left < right,
+            Self::LessEq => left <= right,
+            Self::Equal => left == right,
+            Self::NotEqual => left != right,
+        }
+    }
+}
+
+impl FromStr for CompareOp {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let op = match s {
+            ">" => Self::Greater,
+            ">=" => Self::GreaterEq,
+            "<" => Self::Less,
+            "<=" => Self::LessEq,
+            "==" => Self::Equal,
+            "!=" => Self::NotEqual,
+            _ => bail!("Unknown operator '{}', expected >, >=, <, <=, == or !=", s),
+        };
+        Ok(op)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlarmCondition {
+    /// Comparison that must hold for `hold` before firing
+    Compare {
+        op:    CompareOp,
+        value: f64,
+        hold:  Duration,
+    },
+    SignChange,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlarmRule {
+    /// Condition as written by the user
+    pub text:      String,
+    pub field:     String,
+    pub condition: AlarmCondition,
+    /// Input line sent to the device when the rule fires
+    pub action:    Option<String>,
+}
+
+impl AlarmRule {
+    /// Read a rules file, one rule per line. Empty lines and `#` comments are skipped.
+    pub fn load(path: impl AsRef<Path>) -> AnyResult<Vec<Self>> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read alarm file {}", path.display()))?;
+
+        text.lines()
+            .enumerate()
+            .map(|(i, line)| (i, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(i, line)| {
+                line.parse()
+                    .with_context(|| format!("{}:{}", path.display(), i + 1))
+            })
+            .collect()
+    }
+}
+
+impl FromStr for AlarmRule {
+    type Err = anyhow::Error;
+
+    /// `<field> <op> <value> [for <duration>] [=> <action>]` or `<field> changes sign [=> ..]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (rule, action) = match s.split_once("=>") {
+            Some((rule, action)) if !action.trim().is_empty() => {
+                (rule, Some(action.trim().to_string()))
+            }
+            Some(_) => bail!("Alarm '{}' has an empty action", s),
+            None => (s, None),
+        };
+
+        let words: Vec<&str> = rule.split_whitespace().collect();
+
+        let condition = match words.as_slice() {
+            [_, "changes", "sign"] => AlarmCondition::SignChange,
+            [_, op, value, rest @ ..] => {
+                let op: CompareOp = op.parse()?;
+                let value: f64 = value
+                    .parse()
+                    .with_context(|| format!("Invalid value '{}' in alarm '{}'", value, s))?;
+
+                let hold = match rest {
+                    [] => Duration::ZERO,
+                    ["for", duration] => parse_duration(duration)
+                        .with_context(|| format!("Invalid duration in alarm '{}'", s))?,
+                    _ => bail!("Invalid alarm '{}', expected e.g. 'field0 > 800 for 50ms'", s),
+                };
+
+                AlarmCondition::Compare { op, value, hold }
+            }
+            _ => bail!("Invalid alarm '{}', expected e.g. 'field0 > 800 for 50ms'", s),
+        };
+
+        Ok(Self {
+            text: rule.trim().to_string(),
+            field: words[0].to_string(),
+            condition,
+            action,
+        })
+    }
+}
+
+/// `ms`, `s` or `min` suffixed duration
+pub fn parse_duration(s: &str) -> AnyResult<Duration> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: u64 = value.parse().with_context(|| format!("'{}'", s))?;
+
+    let duration = match unit {
+        "ms" => Duration::from_millis(value),
+        "s" => Duration::from_secs(value),
+        "min" => match value.checked_mul(60) {
+            Some(secs) => Duration::from_secs(secs),
+            None => bail!("'{}' is too long", s),
+        },
+        _ => bail!("'{}', expected e.g. 50ms or 2s", s),
+    };
+    Ok(duration)
+}
+
+/// Fail on rules whose field no record has, a typo would never fire
+pub fn check_alarm_fields(rules: &[AlarmRule], fields: &[String]) -> AnyResult<()> {
+    for rule in rules {
+        if !fields.contains(&rule.field) {
+            bail!(
+                "Alarm '{}' is on unknown field '{}', the fields are: {}",
+                rule.text,
+                rule.field,
+                fields.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                             Alarms
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+/// A rule that fired
+#[derive(Debug, Clone)]
+pub struct AlarmEvent {
+    pub rule:   String,
+    pub field:  String,
+    pub value:  f64,
+    pub action: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct RuleState {
+    /// Time the condition became true
+    since:     Option<Instant>,
+    fired:     bool,
+    last_sign: Option<bool>,
+}
+
+pub struct Alarms {
+    rules: Vec<(AlarmRule, RuleState)>,
+}
+
+impl Alarms {
+    pub fn new(rules: Vec<AlarmRule>) -> Self {
+        Self {
+            rules: rules
+                .into_iter()
+                .map(|rule| (rule, RuleState::default()))
+                .collect(),
+        }
+    }
+
+    /// Evaluate the rules against the fields of one record received at `now`
+    pub fn check(&mut self, fields: &[(String, f64)], now: Instant) -> Vec<AlarmEvent> {
+        let mut events = Vec::new();
+
+        for (rule, state) in &mut self.rules {
+            let Some(&(_, value)) = fields.iter().find(|(name, _)| *name == rule.field)
+            else {
+                continue;
+            };
+
+            let fire = match rule.condition {
+                AlarmCondition::Compare { op, value: limit, hold } => {
+                    if op.matches(value, limit) {
+                        let since = *state.since.get_or_insert(now);
+                        let fire = !state.fired && now - since >= hold;
+                        state.fired |= fire;
+                        fire
+                    }
+                    else {
+                        state.since = None;
+                        state.fired = false;
+                        false
+                    }
+                }
+                AlarmCondition::SignChange => {
+                    if value == 0.0 || value.is_nan() {
+                        false
+                    }
+                    else {
+                        let positive = value > 0.0;
+                        let changed = state.last_sign.is_some_and(|last| last != positive);
+                        state.last_sign = Some(positive);
+                        changed
+                    }
+                }
+            };
+
+            if fire {
+                events.push(AlarmEvent {
+                    rule: rule.text.clone(),
+                    field: rule.field.clone(),
+                    value,
+                    action: rule.action.clone(),
+                });
+            }
+        }
+
+        events
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Tests
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::data::Record;
+    use crate::app::schema::DataSchema;
+
+    fn rule(text: &str) -> AlarmRule {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_durations() {
+        let cases = [
+            ("50ms", Some(Duration::from_millis(50))),
+            ("2s", Some(Duration::from_secs(2))),
+            ("3min", Some(Duration::from_secs(180))),
+            ("0ms", Some(Duration::ZERO)),
+            ("18446744073709551615s", Some(Duration::from_secs(u64::MAX))),
+            ("307445734561825860min", Some(Duration::from_secs(307445734561825860 * 60))),
+            // Overflow of the minutes
+            ("307445734561825861min", None),
+            ("18446744073709551616ms", None),
+            ("2", None),
+            ("2h", None),
+            ("ms", None),
+            ("-1s", None),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(parse_duration(text).ok(), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn rule_fields_must_exist() {
+        let fields = Record::numeric_field_names(&DataSchema::default());
+        let rules = |text: &str| vec![rule(text)];
+
+        assert!(check_alarm_fields(&rules("field0 > 800"), &fields).is_ok());
+        assert!(check_alarm_fields(&rules("imu.gyro.2 changes sign"), &fields).is_ok());
+        assert!(check_alarm_fields(&rules("battery.charge <= 10 => !stop"), &fields).is_ok());
+
+        let error = check_alarm_fields(&rules("feild0 > 800"), &fields).unwrap_err();
+        assert!(error.to_string().contains("unknown field 'feild0'"));
+        assert!(check_alarm_fields(&rules("imu.gyro.3 > 0"), &fields).is_err());
+        assert!(check_alarm_fields(&rules("imu.gyro > 0"), &fields).is_err());
+    }
+
+    #[test]
+    fn parse_rules() {
+        let compare = |op, value, hold| AlarmCondition::Compare { op, value, hold };
+        let cases = [
+            (
+                "field0 > 800",
+                Some(("field0", compare(CompareOp::Greater, 800.0, Duration::ZERO), None)),
+            ),
+            (
+                "field1 <= -2.5 for 50ms",
+                Some(("field1", compare(CompareOp::LessEq, -2.5, Duration::from_millis(50)), None)),
+            ),
+            (
+                "battery.charge != 0 => ping ",
+                Some((
+                    "battery.charge",
+                    compare(CompareOp::NotEqual, 0.0, Duration::ZERO),
+                    Some("ping"),
+                )),
+            ),
+            (
+                "imu.gyro.2 changes sign => !stop",
+                Some(("imu.gyro.2", AlarmCondition::SignChange, Some("!stop"))),
+            ),
+            ("field0 > 800 =>", None),
+            ("field0 > 800 =>  ", None),
+            ("field0 > 800 for", None),
+            ("field0 > 800 for 50", None),
+            ("field0 > 800 during 50ms", None),
+            ("field0 >> 800", None),
+            ("field0 > high", None),
+            ("field0 changes", None),
+            ("field0", None),
+            ("", None),
+        ];
+
+        for (text, expected) in cases {
+            let parsed = text.parse::<AlarmRule>().ok();
+            let parsed = parsed
+                .as_ref()
+                .map(|r| (r.field.as_str(), r.condition.clone(), r.action.as_deref()));
+            assert_eq!(parsed, expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn alarm_transitions() {
+        let start = Instant::now();
+
+        // Time in ms, value and whether the rule fires
+        type Samples = &'static [(u64, f64, bool)];
+
+        let cases: [(&str, Samples); 4] = [
+            // Fires once per crossing, re-armed when the condition turns false
+            ("x > 800", &[
+                (0, 100.0, false),
+                (1, 900.0, true),
+                (2, 950.0, false),
+                (3, 800.0, false),
+                (4, 801.0, true),
+            ]),
+            // Must hold for the whole duration, a dip restarts it
+            ("x > 800 for 50ms", &[
+                (0, 900.0, false),
+                (49, 900.0, false),
+                (50, 900.0, true),
+                (100, 900.0, false),
+                (110, 0.0, false),
+                (120, 900.0, false),
+                (169, 900.0, false),
+                (170, 900.0, true),
+            ]),
+            // Zeros and NaN neither fire nor reset the last sign
+            ("x changes sign", &[
+                (0, 1.0, false),
+                (1, 2.0, false),
+                (2, 0.0, false),
+                (3, f64::NAN, false),
+                (4, -1.0, true),
+                (5, 0.0, false),
+                (6, -3.0, false),
+                (7, 4.0, true),
+            ]),
+            // NaN is unequal to everything
+            ("x != 0", &[(0, 0.0, false), (1, f64::NAN, true), (2, 1.0, false)]),
+        ];
+
+        for (text, samples) in cases {
+            let mut alarms = Alarms::new(vec![rule(text)]);
+            for &(ms, value, fires) in samples {
+                let now = start + Duration::from_millis(ms);
+                let events = alarms.check(&[("x".to_string(), value)], now);
+                assert_eq!(events.len(), fires as usize, "{}: {} at {}ms", text, value, ms);
+            }
+        }
+    }
+
+    #[test]
+    fn alarm_events() {
+        let mut alarms = Alarms::new(vec![rule("x > 1 => !stop"), rule("y < 0"), rule("z > 0")]);
+        let now = Instant::now();
+
+        let fields = [("x".to_string(), 2.0), ("y".to_string(), -1.0)];
+        let events = alarms.check(&fields, now);
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            (events[0].rule.as_str(), events[0].field.as_str(), events[0].value),
+            ("x > 1", "x", 2.0)
+        );
+        assert_eq!(events[0].action.as_deref(), Some("!stop"));
+        assert_eq!((events[1].rule.as_str(), events[1].action.as_deref()), ("y < 0", None));
+
+        // Missing fields leave the rule as it was
+        assert!(alarms.check(&[], now).is_empty());
+        assert!(alarms.check(&fields, now).is_empty());
+    }
+}
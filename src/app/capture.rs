@@ -0,0 +1,554 @@
+//! Session capture
+//!
+//! Records the raw serial traffic of a connection, before any MXS filtering, so field issues can
+//! be replayed and inspected later.
+//!
+//! File layout, little-endian:
+//!
+//! ```text
+//! Header  "MXSC" [VERSION u8][MODE u8][HOST PROTOCOL u8][BAUD u32][START µs i64][PORT LEN u16][PORT]
+//! Chunk   [DIRECTION u8][DELTA µs varint][LEN varint][DATA]
+//! ```
+//!
+//! Chunk times are deltas from the previous chunk, the first one from the header start time.
+//! Varints are LEB128: 7 bits per byte, low bits first, high bit set on all but the last byte.
+//!
+//! The header keeps the host side of the link. The device version and features are negotiated
+//! from its Start packet, which is part of the captured traffic, so a replay negotiates them again.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result as AnyResult, bail};
+use chrono::{DateTime, Utc};
+
+use crate::app::sink::{SessionName, create_session_file};
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                             Format
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+pub const CAPTURE_MAGIC: [u8; 4] = *b"MXSC";
+pub const CAPTURE_VERSION: u8 = 1;
+
+/// Time between flushes of the capture file
+const CAPTURE_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaptureDirection {
+    /// Read from the device
+    Rx = 0,
+    /// Written by the host
+    Tx = 1,
+}
+
+impl TryFrom<u8> for CaptureDirection {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Rx),
+            1 => Ok(Self::Tx),
+            _ => bail!("Invalid chunk direction {}", value),
+        }
+    }
+}
+
+/// How the host was reading the link
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaptureMode {
+    Direct  = 0,
+    Plain   = 1,
+    Stuffed = 2,
+}
+
+impl TryFrom<u8> for CaptureMode {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Direct),
+            1 => Ok(Self::Plain),
+            2 => Ok(Self::Stuffed),
+            _ => bail!("Invalid capture mode {}", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureHeader {
+    pub port:          String,
+    pub baud:          u32,
+    pub mode:          CaptureMode,
+    /// MXS protocol version the host offered, not the one negotiated with the device
+    pub host_protocol: u8,
+    pub started:       DateTime<Utc>,
+}
+
+impl CaptureHeader {
+    fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        let port = self.port.as_bytes();
+        let port_len = u16::try_from(port.len()).unwrap_or(u16::MAX);
+
+        out.write_all(&CAPTURE_MAGIC)?;
+        out.write_all(&[CAPTURE_VERSION, self.mode as u8, self.host_protocol])?;
+        out.write_all(&self.baud.to_le_bytes())?;
+        out.write_all(&self.started.timestamp_micros().to_le_bytes())?;
+        out.write_all(&port_len.to_le_bytes())?;
+        out.write_all(&port[..port_len as usize])
+    }
+
+    fn read_from(input: &mut impl Read) -> AnyResult<Self> {
+        let mut fixed = [0u8; 19];
+        input
+            .read_exact(&mut fixed)
+            .context("Header is truncated")?;
+
+        if fixed[..4] != CAPTURE_MAGIC {
+            bail!("Not a capture file");
+        }
+        if fixed[4] != CAPTURE_VERSION {
+            bail!("Unsupported capture version {}", fixed[4]);
+        }
+
+        let mode = CaptureMode::try_from(fixed[5])?;
+        let host_protocol = fixed[6];
+        let baud = u32::from_le_bytes(fixed[7..11].try_into().unwrap());
+        let started = i64::from_le_bytes(fixed[11..19].try_into().unwrap());
+
+        let mut port_len = [0u8; 2];
+        input
+            .read_exact(&mut port_len)
+            .context("Header is truncated")?;
+        let mut port = vec![0u8; u16::from_le_bytes(port_len) as usize];
+        input.read_exact(&mut port).context("Header is truncated")?;
+
+        Ok(Self {
+            port: String::from_utf8_lossy(&port).into_owned(),
+            baud,
+            mode,
+            host_protocol,
+            started: DateTime::from_timestamp_micros(started).unwrap_or_default(),
+        })
+    }
+}
+
+impl fmt::Display for CaptureHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at {} baud, {:?} mode, host MXS v{}, started {}",
+            self.port,
+            self.baud,
+            self.mode,
+            self.host_protocol,
+            self.started.format("%Y-%m-%d %H:%M:%S UTC")
+        )
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// `None` at the end of the input, before the first byte
+fn read_varint(input: &mut impl Read) -> AnyResult<Option<u64>> {
+    let mut value = 0u64;
+    let mut byte = [0u8; 1];
+
+    for shift in (0..64).step_by(7) {
+        if input.read(&mut byte)? == 0 {
+            if shift == 0 {
+                return Ok(None);
+            }
+            bail!("Capture is truncated");
+        }
+
+        value |= ((byte[0] & 0x7F) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+    bail!("Invalid varint in capture")
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                             Writer
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+pub struct CaptureWriter<W: Write = BufWriter<File>> {
+    writer:     W,
+    started:    Instant,
+    /// Time of the last chunk, in µs since `started`
+    last_time:  u64,
+    last_flush: Instant,
+    chunk:      Vec<u8>,
+}
+
+impl CaptureWriter {
+    /// Create `<stem>-<session start>.<ext>` next to `path`, so reconnects don't overwrite it
+    ///
+    /// File names are picked like the ones of the data log, see `create_session_file`.
+    pub fn create(path: &Path, header: &CaptureHeader) -> AnyResult<(Self, PathBuf)> {
+        let mut session = SessionName::new(header.started);
+        let (file, path) =
+            create_session_file(path, &mut session, "").context("Couldn't create capture file")?;
+
+        Ok((Self::new(BufWriter::new(file), header)?, path))
+    }
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// Write the header, chunk times count from now
+    pub fn new(mut writer: W, header: &CaptureHeader) -> io::Result<Self> {
+        header.write_to(&mut writer)?;
+
+        Ok(Self {
+            writer,
+            started: Instant::now(),
+            last_time: 0,
+            last_flush: Instant::now(),
+            chunk: Vec::new(),
+        })
+    }
+
+    pub fn write(&mut self, direction: CaptureDirection, data: &[u8]) -> io::Result<()> {
+        self.write_at(direction, data, self.started.elapsed())
+    }
+
+    /// Write a chunk that happened `time` after the start of the capture
+    pub fn write_at(
+        &mut self,
+        direction: CaptureDirection,
+        data: &[u8],
+        time: Duration,
+    ) -> io::Result<()> {
+        let time = time.as_micros() as u64;
+
+        self.chunk.clear();
+        self.chunk.push(direction as u8);
+        write_varint(&mut self.chunk, time.saturating_sub(self.last_time));
+        write_varint(&mut self.chunk, data.len() as u64);
+        self.writer.write_all(&self.chunk)?;
+        self.writer.write_all(data)?;
+        self.last_time = self.last_time.max(time);
+
+        self.flush_if_due()
+    }
+
+    /// Flush when `CAPTURE_FLUSH_INTERVAL` has passed since the last flush
+    pub fn flush_if_due(&mut self) -> io::Result<()> {
+        if self.last_flush.elapsed() >= CAPTURE_FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = Instant::now();
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> Drop for CaptureWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                             Reader
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureChunk {
+    pub direction: CaptureDirection,
+    /// Time since the start of the capture
+    pub time:      Duration,
+    pub data:      Vec<u8>,
+}
+
+pub struct CaptureReader<R: Read = BufReader<File>> {
+    reader: R,
+    header: CaptureHeader,
+    /// Time of the last chunk, in µs since the start
+    time:   u64,
+}
+
+impl CaptureReader {
+    pub fn open(path: &Path) -> AnyResult<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Couldn't open capture {}", path.display()))?;
+
+        Self::new(BufReader::new(file))
+            .with_context(|| format!("Invalid capture {}", path.display()))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Read the header, chunks follow with `next_chunk`
+    pub fn new(mut reader: R) -> AnyResult<Self> {
+        let header = CaptureHeader::read_from(&mut reader)?;
+        Ok(Self { reader, header, time: 0 })
+    }
+
+    pub fn header(&self) -> &CaptureHeader {
+        &self.header
+    }
+
+    /// Next chunk, `None` at the end of the file
+    pub fn next_chunk(&mut self) -> AnyResult<Option<CaptureChunk>> {
+        let mut direction = [0u8; 1];
+        if self.reader.read(&mut direction)? == 0 {
+            return Ok(None);
+        }
+        let direction = CaptureDirection::try_from(direction[0])?;
+
+        let (Some(delta), Some(len)) =
+            (read_varint(&mut self.reader)?, read_varint(&mut self.reader)?)
+        else {
+            bail!("Capture is truncated");
+        };
+
+        // The length isn't trusted for the allocation, a corrupt file could claim any size
+        let mut data = Vec::new();
+        (&mut self.reader).take(len).read_to_end(&mut data)?;
+        if (data.len() as u64) < len {
+            bail!("Capture is truncated");
+        }
+        self.time = self.time.saturating_add(delta);
+
+        Ok(Some(CaptureChunk {
+            direction,
+            time: Duration::from_micros(self.time),
+            data,
+        }))
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Tap
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+/// Port wrapper that captures everything read and written through it
+///
+/// A failing capture is dropped without affecting the port, the error is kept for `take_error`.
+pub struct CaptureTap<P> {
+    inner:   P,
+    capture: Option<CaptureWriter>,
+    error:   Option<io::Error>,
+}
+
+impl<P> CaptureTap<P> {
+    pub fn new(inner: P, capture: Option<CaptureWriter>) -> Self {
+        Self {
+            inner,
+            capture,
+            error: None,
+        }
+    }
+
+    /// The wrapped port, for settings that bypass the capture
+    pub fn port_mut(&mut self) -> &mut P {
+        &mut self.inner
+    }
+
+    /// Capture failure since the last call
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    /// Flush the capture while the port is idle
+    pub fn poll(&mut self) {
+        if let Some(Err(e)) = self.capture.as_mut().map(|c| c.flush_if_due()) {
+            self.fail(e);
+        }
+    }
+
+    fn record(&mut self, direction: CaptureDirection, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        if let Some(Err(e)) = self.capture.as_mut().map(|c| c.write(direction, data)) {
+            self.fail(e);
+        }
+    }
+
+    fn fail(&mut self, error: io::Error) {
+        self.error = Some(error);
+        self.capture = None;
+    }
+}
+
+impl<P: Read> Read for CaptureTap<P> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.record(CaptureDirection::Rx, &buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<P: Write> Write for CaptureTap<P> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.record(CaptureDirection::Tx, &buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Tests
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn header() -> CaptureHeader {
+        CaptureHeader {
+            port:          "/dev/ttyACM0".into(),
+            baud:          115_200,
+            mode:          CaptureMode::Stuffed,
+            host_protocol: 1,
+            started:       DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn varints() {
+        let cases = [
+            (0, vec![0x00]),
+            (0x7F, vec![0x7F]),
+            (0x80, vec![0x80, 0x01]),
+            (300, vec![0xAC, 0x02]),
+            (0x3FFF, vec![0xFF, 0x7F]),
+            (0x4000, vec![0x80, 0x80, 0x01]),
+            (u64::MAX, vec![0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]),
+        ];
+
+        for (value, bytes) in cases {
+            let mut out = Vec::new();
+            write_varint(&mut out, value);
+            assert_eq!(out, bytes, "{}", value);
+            assert_eq!(read_varint(&mut &out[..]).unwrap(), Some(value), "{}", value);
+        }
+
+        assert_eq!(read_varint(&mut &[][..]).unwrap(), None);
+        assert!(read_varint(&mut &[0x80][..]).is_err());
+        assert!(read_varint(&mut &[0xFF; 10][..]).is_err());
+    }
+
+    #[test]
+    fn header_bytes() {
+        let mut out = Vec::new();
+        CaptureWriter::new(&mut out, &header()).unwrap();
+
+        let mut expected = b"MXSC\x01\x02\x01".to_vec();
+        expected.extend(115_200u32.to_le_bytes());
+        expected.extend(1_700_000_000_000_000i64.to_le_bytes());
+        expected.extend(12u16.to_le_bytes());
+        expected.extend(b"/dev/ttyACM0");
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn chunk_bytes() {
+        let mut out = Vec::new();
+        let mut writer = CaptureWriter::new(&mut out, &header()).unwrap();
+        let header_len = 4 + 3 + 4 + 8 + 2 + 12;
+
+        let large = vec![0xA5; 300];
+        writer
+            .write_at(CaptureDirection::Tx, b"start", Duration::from_micros(100))
+            .unwrap();
+        writer
+            .write_at(CaptureDirection::Rx, &large, Duration::from_micros(400))
+            .unwrap();
+        writer
+            .write_at(CaptureDirection::Rx, b"\x00", Duration::from_micros(400))
+            .unwrap();
+        drop(writer);
+
+        // Times are deltas from the previous chunk
+        let mut expected = b"\x01\x64\x05start".to_vec();
+        expected.extend([0x00, 0xAC, 0x02, 0xAC, 0x02]);
+        expected.extend(&large);
+        expected.extend([0x00, 0x00, 0x01, 0x00]);
+        assert_eq!(out[header_len..], expected);
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let large = vec![0xA5; 300];
+        let chunks = [
+            (CaptureDirection::Tx, &b"start"[..], 100),
+            (CaptureDirection::Rx, &large, 400),
+            (CaptureDirection::Rx, b"", 5_000_000),
+        ];
+
+        let mut out = Vec::new();
+        let mut writer = CaptureWriter::new(&mut out, &header()).unwrap();
+        for (direction, data, time) in chunks {
+            writer
+                .write_at(direction, data, Duration::from_micros(time))
+                .unwrap();
+        }
+        drop(writer);
+
+        let mut reader = CaptureReader::new(Cursor::new(out)).unwrap();
+        assert_eq!(*reader.header(), header());
+        for (direction, data, time) in chunks {
+            let chunk = reader.next_chunk().unwrap().unwrap();
+            assert_eq!(chunk.direction, direction);
+            assert_eq!(chunk.data, data);
+            assert_eq!(chunk.time, Duration::from_micros(time));
+        }
+        assert_eq!(reader.next_chunk().unwrap(), None);
+    }
+
+    #[test]
+    fn invalid_captures() {
+        let mut valid = Vec::new();
+        let mut writer = CaptureWriter::new(&mut valid, &header()).unwrap();
+        writer
+            .write_at(CaptureDirection::Rx, b"data", Duration::ZERO)
+            .unwrap();
+        drop(writer);
+        let header_len = valid.len() - 7;
+
+        let with = |pos: usize, byte: u8| {
+            let mut bytes = valid.clone();
+            bytes[pos] = byte;
+            bytes
+        };
+        let next_chunk = |bytes: Vec<u8>| CaptureReader::new(Cursor::new(bytes))?.next_chunk();
+
+        let cases = [
+            ("magic", with(0, b'X')),
+            ("version", with(4, CAPTURE_VERSION + 1)),
+            ("mode", with(5, 3)),
+            ("header length", valid[..header_len - 1].to_vec()),
+            ("direction", with(header_len, 2)),
+            ("chunk length", with(header_len + 2, 5)),
+            ("chunk data", valid[..valid.len() - 1].to_vec()),
+        ];
+
+        for (case, bytes) in cases {
+            assert!(next_chunk(bytes).is_err(), "{}", case);
+        }
+        assert!(next_chunk(valid).unwrap().is_some());
+    }
+}
@@ -0,0 +1,668 @@
+//! Command line
+//!
+//! ```text
+//! mxs [port] [@profile] [options]
+//! mxs vid=<hex> pid=<hex> [sn=<serial>] [@profile] [options]
+//! mxs replay <file> [speed=<factor>] [options]
+//! mxs export <file> [out=<file>] [dissector=<file>]
+//! ```
+//!
+//! Options are bare words like `direct` or `key=value` pairs, in any order. Unknown, repeated and
+//! inapplicable options are errors rather than being taken for the port name. A profile, see
+//! `config`, adds its options to the ones the command line doesn't give.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Context, Result as AnyResult, bail};
+use serialport::{DataBits, FlowControl, Parity, SerialPortBuilder, StopBits};
+
+use crate::app::alarm::{AlarmRule, parse_duration};
+use crate::app::config::Config;
+use crate::app::replay::ReplaySpeed;
+use crate::app::sink::SinkRotation;
+use crate::app::usb::{USB_OPTIONS, UsbMatch};
+use crate::mxs_reliable::MxsReliableConfig;
+
+/// Selects a profile, e.g. `@imu-board`
+const PROFILE_PREFIX: char = '@';
+
+/// Framing flags, only one can be given
+const MODE_FLAGS: [&str; 3] = ["plain", "stuffed", "direct"];
+
+/// Options of the serial port
+const SERIAL_OPTIONS: [&str; 8] = [
+    "baud", "databits", "parity", "stopbits", "flow", "timeout", "dtr", "rts",
+];
+
+/// Retransmission options of the reliable `!` commands
+const COMMAND_OPTIONS: [&str; 2] = ["acktimeout", "retries"];
+
+/// Options of the decoding and data processing, shared by connections and replays
+const PIPELINE_OPTIONS: [&str; 7] = [
+    "schema", "log", "rotate", "alarm", "alarms", "forward", "capture",
+];
+
+/// Options that can be given more than once
+const REPEATABLE_OPTIONS: [&str; 2] = ["alarm", "alarms"];
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                         Serial Settings
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SerialSettings {
+    pub baud:         u32,
+    pub data_bits:    DataBits,
+    pub parity:       Parity,
+    pub stop_bits:    StopBits,
+    pub flow_control: FlowControl,
+    /// Longest a read blocks
+    pub timeout:      Duration,
+    /// DTR level when opening the port
+    pub dtr:          bool,
+    /// RTS level after opening the port, left to the driver when `None`
+    pub rts:          Option<bool>,
+}
+
+impl Default for SerialSettings {
+    fn default() -> Self {
+        Self {
+            baud:         115_200,
+            data_bits:    DataBits::Eight,
+            parity:       Parity::None,
+            stop_bits:    StopBits::One,
+            flow_control: FlowControl::None,
+            timeout:      Duration::from_millis(500),
+            dtr:          true,
+            rts:          None,
+        }
+    }
+}
+
+impl SerialSettings {
+    pub fn builder(&self, port_name: &str) -> SerialPortBuilder {
+        serialport::new(port_name, self.baud)
+            .data_bits(self.data_bits)
+            .parity(self.parity)
+            .stop_bits(self.stop_bits)
+            .flow_control(self.flow_control)
+            .timeout(self.timeout)
+            .dtr_on_open(self.dtr)
+    }
+
+    /// Apply one `SERIAL_OPTIONS` entry
+    fn set(&mut self, key: &str, value: &str) -> AnyResult<()> {
+        match key {
+            "baud" => {
+                self.baud = match value.parse() {
+                    Ok(baud) if baud > 0 => baud,
+                    _ => bail!("Invalid baud rate '{}'", value),
+                };
+            }
+            "databits" => {
+                self.data_bits = value
+                    .parse::<u8>()
+                    .ok()
+                    .and_then(|bits| DataBits::try_from(bits).ok())
+                    .with_context(|| format!("Invalid data bits '{}', expected 5 to 8", value))?;
+            }
+            "parity" => {
+                self.parity = match value {
+                    "none" => Parity::None,
+                    "odd" => Parity::Odd,
+                    "even" => Parity::Even,
+                    _ => bail!("Invalid parity '{}', expected none, odd or even", value),
+                };
+            }
+            "stopbits" => {
+                self.stop_bits = match value {
+                    "1" => StopBits::One,
+                    "2" => StopBits::Two,
+                    _ => bail!("Invalid stop bits '{}', expected 1 or 2", value),
+                };
+            }
+            "flow" => {
+                self.flow_control = match value {
+                    "none" => FlowControl::None,
+                    "rts-cts" => FlowControl::Hardware,
+                    "xon-xoff" => FlowControl::Software,
+                    _ => bail!(
+                        "Invalid flow control '{}', expected none, rts-cts or xon-xoff",
+                        value
+                    ),
+                };
+            }
+            "timeout" => {
+                self.timeout = parse_duration(value).context("Invalid timeout")?;
+                if self.timeout.is_zero() {
+                    bail!("Invalid timeout '{}', must be above zero", value);
+                }
+            }
+            "dtr" => self.dtr = parse_level(key, value)?,
+            "rts" => self.rts = Some(parse_level(key, value)?),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+/// e.g. `115200 baud 8N1, RTS/CTS flow control`
+impl fmt::Display for SerialSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parity = match self.parity {
+            Parity::None => 'N',
+            Parity::Odd => 'O',
+            Parity::Even => 'E',
+        };
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        };
+        write!(f, "{} baud {}{}{}", self.baud, u8::from(self.data_bits), parity, stop_bits)?;
+
+        match self.flow_control {
+            FlowControl::None => Ok(()),
+            FlowControl::Hardware => write!(f, ", RTS/CTS flow control"),
+            FlowControl::Software => write!(f, ", XON/XOFF flow control"),
+        }
+    }
+}
+
+fn parse_level(key: &str, value: &str) -> AnyResult<bool> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => bail!("Invalid {} level '{}', expected on or off", key, value),
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                            Arguments
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CliCommand {
+    Help,
+    /// Connect to a serial port
+    Connect,
+    /// Feed a capture through the decoder
+    Replay {
+        file:  PathBuf,
+        speed: ReplaySpeed,
+    },
+    /// Convert a capture to pcapng
+    Export {
+        file:      PathBuf,
+        out:       Option<PathBuf>,
+        dissector: Option<PathBuf>,
+    },
+}
+
+impl CliCommand {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Help => "help",
+            Self::Connect => "a connection",
+            Self::Replay { .. } => "replay",
+            Self::Export { .. } => "export",
+        }
+    }
+
+    /// Whether `option` has a meaning for this command
+    fn accepts(&self, option: &str) -> bool {
+        match self {
+            Self::Help => true,
+            Self::Connect => {
+                MODE_FLAGS.contains(&option)
+                    || SERIAL_OPTIONS.contains(&option)
+                    || USB_OPTIONS.contains(&option)
+                    || COMMAND_OPTIONS.contains(&option)
+                    || PIPELINE_OPTIONS.contains(&option)
+            }
+            Self::Replay { .. } => option == "speed" || PIPELINE_OPTIONS.contains(&option),
+            Self::Export { .. } => matches!(option, "out" | "dissector"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CliArgs {
+    pub command:     CliCommand,
+    /// Largest port number when `None` and `usb` is empty
+    pub port:        Option<String>,
+    /// Selects the port by USB metadata
+    pub usb:         UsbMatch,
+    pub serial:      SerialSettings,
+    /// Ack timeout and retries of the `!` commands
+    pub reliable:    MxsReliableConfig,
+    pub direct:      bool,
+    pub stuffed:     bool,
+    pub schema:      Option<PathBuf>,
+    pub log:         Option<PathBuf>,
+    pub rotate:      Option<SinkRotation>,
+    pub alarms:      Vec<AlarmRule>,
+    pub alarm_files: Vec<PathBuf>,
+    /// Resolved when starting, so a DNS failure is reported with the address
+    pub forward:     Option<String>,
+    pub capture:     Option<PathBuf>,
+    /// Selected profile name
+    pub profile:     Option<String>,
+    /// Input lines sent by `:<name>`, from the profile
+    pub macros:      BTreeMap<String, Vec<String>>,
+    /// Options given, in order
+    options:         Vec<String>,
+}
+
+impl CliArgs {
+    /// Parse the arguments following the program name
+    pub fn parse(args: impl IntoIterator<Item = String>) -> AnyResult<Self> {
+        Self::parse_with(args, Config::load)
+    }
+
+    /// `parse` with the config read by `load_config` when a profile is selected
+    fn parse_with(
+        args: impl IntoIterator<Item = String>,
+        load_config: impl FnOnce() -> AnyResult<Config>,
+    ) -> AnyResult<Self> {
+        let mut args: Vec<String> = args.into_iter().collect();
+
+        let mut cli = Self {
+            command:     CliCommand::Connect,
+            port:        None,
+            usb:         UsbMatch::default(),
+            serial:      SerialSettings::default(),
+            reliable:    MxsReliableConfig::default(),
+            direct:      false,
+            stuffed:     false,
+            schema:      None,
+            log:         None,
+            rotate:      None,
+            alarms:      Vec::new(),
+            alarm_files: Vec::new(),
+            forward:     None,
+            capture:     None,
+            profile:     None,
+            macros:      BTreeMap::new(),
+            options:     Vec::new(),
+        };
+
+        if args.iter().any(|a| a == "help") {
+            cli.command = CliCommand::Help;
+            return Ok(cli);
+        }
+
+        // ---- Profile
+        let mut profile_args = Vec::new();
+        if let Some(i) = args.iter().position(|a| a.starts_with(PROFILE_PREFIX)) {
+            let name = args.remove(i)[PROFILE_PREFIX.len_utf8()..].to_string();
+            if args.iter().any(|a| a.starts_with(PROFILE_PREFIX)) {
+                bail!("Only one profile can be selected");
+            }
+
+            let config = load_config()?;
+            let profile = config.profile(&name)?;
+            profile_args = profile.args();
+            cli.macros = profile.macros();
+            cli.profile = Some(name);
+        }
+
+        let mut args = args.into_iter().peekable();
+
+        // ---- Subcommand and its file
+        let subcommand = args.next_if(|a| a == "replay" || a == "export");
+        if let Some(subcommand) = subcommand {
+            let Some(file) = args.next_if(|a| !a.contains('='))
+            else {
+                bail!("Missing capture file, usage: mxs {} <file> [options]", subcommand);
+            };
+            let file = PathBuf::from(file);
+
+            cli.command = match subcommand.as_str() {
+                "replay" => CliCommand::Replay {
+                    file,
+                    speed: ReplaySpeed::default(),
+                },
+                _ => CliCommand::Export {
+                    file,
+                    out: None,
+                    dissector: None,
+                },
+            };
+        }
+
+        // ---- Options
+        let args: Vec<String> = args.collect();
+        for arg in cli.profile_args(profile_args, &args) {
+            cli.apply(arg).with_context(|| {
+                format!("Profile @{}", cli.profile.as_deref().unwrap_or_default())
+            })?;
+        }
+        for arg in args {
+            cli.apply(arg)?;
+        }
+
+        cli.validate()?;
+        Ok(cli)
+    }
+
+    /// Profile options the command line `args` don't override, and that apply to the command
+    fn profile_args(&self, profile_args: Vec<String>, args: &[String]) -> Vec<String> {
+        let given: Vec<&str> = args.iter().map(|a| option_key(a)).collect();
+
+        profile_args
+            .into_iter()
+            .filter(|a| {
+                let key = option_key(a);
+                let accepted = match key {
+                    "port" => self.command == CliCommand::Connect,
+                    "mode" => self.command.accepts(MODE_FLAGS[0]),
+                    key => self.command.accepts(key),
+                };
+                accepted && !given.contains(&key)
+            })
+            .collect()
+    }
+
+    /// Add one argument
+    fn apply(&mut self, arg: String) -> AnyResult<()> {
+        let (key, value) = match arg.split_once('=') {
+            Some((key, value)) => (key, Some(value)),
+            None => (arg.as_str(), None),
+        };
+
+        let is_flag = MODE_FLAGS.contains(&key);
+        let is_option = ["speed", "out", "dissector"].contains(&key)
+            || SERIAL_OPTIONS.contains(&key)
+            || USB_OPTIONS.contains(&key)
+            || COMMAND_OPTIONS.contains(&key)
+            || PIPELINE_OPTIONS.contains(&key);
+
+        // The port is the only bare argument
+        if value.is_none() && !is_flag {
+            if self.command != CliCommand::Connect || self.port.is_some() {
+                bail!("Unexpected argument '{}'", arg);
+            }
+            self.port = Some(arg);
+            return Ok(());
+        }
+
+        match value {
+            _ if !is_flag && !is_option => bail!("Unknown option '{}'", arg),
+            Some(_) if is_flag => bail!("'{}' doesn't take a value", key),
+            Some("") => bail!("Missing value for {}=", key),
+            _ => (),
+        }
+        if !self.command.accepts(key) {
+            bail!("'{}' doesn't apply to {}", arg, self.command.name());
+        }
+        if self.has(key) && !REPEATABLE_OPTIONS.contains(&key) {
+            bail!("'{}' given more than once", key);
+        }
+        self.options.push(key.to_string());
+
+        self.set(key, value.unwrap_or_default())
+    }
+
+    /// Whether the option was given, on the command line or by the profile
+    pub fn has(&self, option: &str) -> bool {
+        self.options.iter().any(|o| o == option)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> AnyResult<()> {
+        match key {
+            "plain" => (),
+            "direct" => self.direct = true,
+            "stuffed" => self.stuffed = true,
+            "schema" => self.schema = Some(value.into()),
+            "log" => self.log = Some(value.into()),
+            "rotate" => self.rotate = Some(value.parse()?),
+            "alarm" => self.alarms.push(value.parse()?),
+            "alarms" => self.alarm_files.push(value.into()),
+            "forward" => self.forward = Some(value.into()),
+            "capture" => self.capture = Some(value.into()),
+            "acktimeout" => {
+                self.reliable.timeout = parse_duration(value).context("Invalid ack timeout")?;
+                if self.reliable.timeout.is_zero() {
+                    bail!("Invalid ack timeout '{}', must be above zero", value);
+                }
+            }
+            "retries" => {
+                self.reliable.retries = value
+                    .parse()
+                    .with_context(|| format!("Invalid retries '{}', expected 0 to 255", value))?;
+            }
+            key if USB_OPTIONS.contains(&key) => self.usb.set(key, value)?,
+            "speed" => {
+                if let CliCommand::Replay { speed, .. } = &mut self.command {
+                    *speed = value.parse()?;
+                }
+            }
+            "out" | "dissector" => {
+                if let CliCommand::Export { out, dissector, .. } = &mut self.command {
+                    let target = if key == "out" { out } else { dissector };
+                    *target = Some(value.into());
+                }
+            }
+            _ => self.serial.set(key, value)?,
+        }
+        Ok(())
+    }
+
+    /// Reject combinations that can't work
+    fn validate(&self) -> AnyResult<()> {
+        let modes: Vec<&str> = MODE_FLAGS.into_iter().filter(|m| self.has(m)).collect();
+        if modes.len() > 1 {
+            bail!("'{}' can't be combined, pick one framing mode", modes.join("' and '"));
+        }
+        if self.port.is_some() && !self.usb.is_empty() {
+            bail!("A port name can't be combined with USB matching ({})", self.usb);
+        }
+        if self.has("rotate") && !self.has("log") {
+            bail!("'rotate' needs a log file, e.g. log=data.csv");
+        }
+
+        let serial = &self.serial;
+        if serial.flow_control == FlowControl::Hardware && serial.rts.is_some() {
+            bail!("'rts' can't be set with flow=rts-cts, the flow control drives RTS");
+        }
+
+        // MXS packets are binary, these only work with text
+        if serial.flow_control == FlowControl::Software && !self.direct {
+            bail!(
+                "flow=xon-xoff would drop the 0x11 and 0x13 bytes of MXS packets, use direct mode"
+            );
+        }
+        if serial.data_bits != DataBits::Eight && !self.direct {
+            bail!(
+                "databits={} can't carry MXS packets, use direct mode",
+                u8::from(serial.data_bits)
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Option name of an argument, `mode` for the framing flags and `port` for the port name and the
+/// USB match, so either one on the command line replaces the profile port selection
+fn option_key(arg: &str) -> &str {
+    match arg.split_once('=') {
+        Some((key, _)) if USB_OPTIONS.contains(&key) => "port",
+        Some((key, _)) => key,
+        None if MODE_FLAGS.contains(&arg) => "mode",
+        None => "port",
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Tests
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> AnyResult<CliArgs> {
+        CliArgs::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn valid_args() {
+        let cases: &[&[&str]] = &[
+            &[],
+            &["/dev/ttyACM0", "baud=921600", "stuffed"],
+            &["vid=0483", "pid=5740", "plain"],
+            &["log=data.csv", "rotate=15min"],
+            &["flow=rts-cts", "dtr=on"],
+            &["flow=xon-xoff", "direct"],
+            &["databits=7", "parity=even", "direct"],
+            &[
+                "alarm=field0 > 800",
+                "alarm=field1 < 2",
+                "alarms=a.txt",
+                "alarms=b.txt",
+            ],
+            &[
+                "replay",
+                "session.mxsc",
+                "speed=max",
+                "log=data.csv",
+                "schema=imu.toml",
+            ],
+            &[
+                "export",
+                "session.mxsc",
+                "out=session.pcapng",
+                "dissector=mxs.lua",
+            ],
+        ];
+
+        for args in cases {
+            assert!(parse(args).is_ok(), "{:?}: {:#}", args, parse(args).unwrap_err());
+        }
+    }
+
+    #[test]
+    fn invalid_args() {
+        let cases: &[(&[&str], &str)] = &[
+            (&["plain", "stuffed"], "pick one framing mode"),
+            (&["stuffed", "direct"], "pick one framing mode"),
+            (&["/dev/ttyACM0", "vid=0483"], "can't be combined with USB matching"),
+            (&["rotate=15min"], "'rotate' needs a log file"),
+            (&["flow=rts-cts", "rts=on"], "'rts' can't be set with flow=rts-cts"),
+            (&["flow=xon-xoff"], "use direct mode"),
+            (&["flow=xon-xoff", "stuffed"], "use direct mode"),
+            (&["databits=7"], "databits=7 can't carry MXS packets"),
+            (&["baud=9600", "baud=115200"], "'baud' given more than once"),
+            (&["direct", "direct"], "'direct' given more than once"),
+            (&["/dev/ttyACM0", "/dev/ttyACM1"], "Unexpected argument"),
+            (&["speed=2"], "doesn't apply to a connection"),
+            (&["replay", "session.mxsc", "baud=9600"], "doesn't apply to replay"),
+            (&["replay", "session.mxsc", "direct"], "doesn't apply to replay"),
+            (&["replay", "session.mxsc", "vid=0483"], "doesn't apply to replay"),
+            (&["replay", "session.mxsc", "/dev/ttyACM0"], "Unexpected argument"),
+            (&["export", "session.mxsc", "log=data.csv"], "doesn't apply to export"),
+            (&["export", "session.mxsc", "speed=2"], "doesn't apply to export"),
+            (&["replay"], "Missing capture file"),
+            (&["baudrate=9600"], "Unknown option"),
+            (&["direct=on"], "doesn't take a value"),
+            (&["log="], "Missing value"),
+            (&["@a", "@b"], "Only one profile"),
+        ];
+
+        for (args, error) in cases {
+            let message = format!("{:#}", parse(args).unwrap_err());
+            assert!(message.contains(error), "{:?}: {}", args, message);
+        }
+    }
+
+    #[test]
+    fn parsed_values() {
+        let cli = parse(&["vid=0483", "baud=921600", "stuffed", "retries=5"]).unwrap();
+        assert_eq!(cli.command, CliCommand::Connect);
+        assert_eq!(cli.port, None);
+        assert!(!cli.usb.is_empty());
+        assert_eq!(cli.serial.baud, 921_600);
+        assert!(cli.stuffed && !cli.direct);
+        assert_eq!(cli.reliable.retries, 5);
+
+        let cli = parse(&["replay", "session.mxsc", "speed=0.5"]).unwrap();
+        assert_eq!(cli.command, CliCommand::Replay {
+            file:  "session.mxsc".into(),
+            speed: ReplaySpeed::Scaled(0.5),
+        });
+
+        assert_eq!(parse(&["/dev/ttyACM0", "help"]).unwrap().command, CliCommand::Help);
+    }
+
+    const CONFIG: &str = r#"
+        [profiles.imu]
+        port     = "/dev/ttyACM0"
+        baud     = 921600
+        flow     = "rts-cts"
+        mode     = "stuffed"
+        log      = "imu.csv"
+        alarm    = ["field0 > 800"]
+
+        [profiles.imu.macros]
+        status = "!status"
+    "#;
+
+    fn parse_profile(args: &[&str]) -> AnyResult<CliArgs> {
+        CliArgs::parse_with(args.iter().map(|a| a.to_string()), || {
+            let mut config = Config::default();
+            config.add("mxs.toml".into(), CONFIG)?;
+            Ok(config)
+        })
+    }
+
+    #[test]
+    fn profile_options() {
+        let cli = parse_profile(&["@imu"]).unwrap();
+        assert_eq!(cli.profile.as_deref(), Some("imu"));
+        assert_eq!(cli.port.as_deref(), Some("/dev/ttyACM0"));
+        assert_eq!(cli.serial.baud, 921_600);
+        assert_eq!(cli.serial.flow_control, FlowControl::Hardware);
+        assert!(cli.stuffed);
+        assert_eq!(cli.log, Some("imu.csv".into()));
+        assert_eq!(cli.alarms.len(), 1);
+        assert_eq!(cli.macros["status"], ["!status"]);
+
+        // The command line overrides the profile, without 'given more than once' errors
+        let cli = parse_profile(&["@imu", "baud=9600", "log=other.csv"]).unwrap();
+        assert_eq!(cli.serial.baud, 9600);
+        assert_eq!(cli.log, Some("other.csv".into()));
+        assert_eq!(cli.serial.flow_control, FlowControl::Hardware);
+
+        // A port name or USB match replaces the profile port
+        let cli = parse_profile(&["@imu", "/dev/ttyUSB0"]).unwrap();
+        assert_eq!(cli.port.as_deref(), Some("/dev/ttyUSB0"));
+        let cli = parse_profile(&["@imu", "vid=0483"]).unwrap();
+        assert_eq!(cli.port, None);
+        assert!(!cli.usb.is_empty());
+
+        // Any framing flag replaces the profile mode
+        let cli = parse_profile(&["@imu", "direct"]).unwrap();
+        assert!(cli.direct && !cli.stuffed);
+
+        // Repeatable options are replaced too
+        let cli = parse_profile(&["@imu", "alarm=field1 < 2", "alarm=field2 < 2"]).unwrap();
+        assert_eq!(cli.alarms.len(), 2);
+
+        // Options that don't apply to the command are left out
+        let cli = parse_profile(&["replay", "session.mxsc", "@imu"]).unwrap();
+        assert_eq!(cli.port, None);
+        assert!(!cli.stuffed && cli.log.is_some());
+        assert_eq!(cli.serial.baud, SerialSettings::default().baud);
+
+        // Validation sees the merged options
+        let error = parse_profile(&["@imu", "stuffed", "flow=xon-xoff"]).unwrap_err();
+        assert!(format!("{:#}", error).contains("use direct mode"));
+
+        let error = parse_profile(&["@board"]).unwrap_err();
+        assert!(error.to_string().starts_with("Unknown profile @board"));
+    }
+}
@@ -0,0 +1,342 @@
+//! Configuration file
+//!
+//! Named device profiles, selected with `mxs @<name>`. Profiles are read from the user config
+//! (`$XDG_CONFIG_HOME/mxs/config.toml`, `~/.config/mxs/config.toml` or `%APPDATA%\mxs\config.toml`)
+//! and from `mxs.toml` in the working directory or its closest parent. A project profile replaces
+//! a user profile with the same name.
+//!
+//! ```toml
+//! [profiles.imu-board]
+//! port     = "/dev/ttyACM0"
+//! baud     = 921600
+//! flow     = "rts-cts"
+//! mode     = "stuffed"            # plain (default), stuffed or direct
+//! schema   = "schemas/imu.toml"   # schema and alarm files are relative to this file
+//! alarms   = ["imu-alarms.txt"]
+//! log      = "imu.csv"            # outputs are relative to the working directory
+//! rotate   = "15min"
+//! retries  = 5                    # command retransmissions, see `acktimeout` for the wait
+//!
+//! [profiles.imu-board.macros]
+//! calibrate = ["!calibrate", "!save"]   # typed as :calibrate
+//! status    = "!status"
+//!
+//! [profiles.probe]
+//! vid      = "0483"               # USB match instead of a port name, see `usb`
+//! product  = "*STLink*"
+//! ```
+//!
+//! Profile keys take the command line option values, see `mxs help`. Options given on the command
+//! line replace the profile ones.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result as AnyResult, bail};
+use serde::Deserialize;
+
+/// Project config file, searched from the working directory up
+pub const PROJECT_CONFIG: &str = "mxs.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileMode {
+    Plain,
+    Stuffed,
+    Direct,
+}
+
+/// Macro lines, one or several
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+enum MacroDef {
+    Line(String),
+    Lines(Vec<String>),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub port:         Option<String>,
+    pub vid:          Option<String>,
+    pub pid:          Option<String>,
+    pub sn:           Option<String>,
+    pub manufacturer: Option<String>,
+    pub product:      Option<String>,
+    pub baud:         Option<u32>,
+    pub databits:     Option<u8>,
+    pub parity:       Option<String>,
+    pub stopbits:     Option<u8>,
+    pub flow:         Option<String>,
+    pub timeout:      Option<String>,
+    pub dtr:          Option<bool>,
+    pub rts:          Option<bool>,
+    pub acktimeout:   Option<String>,
+    pub retries:      Option<u8>,
+    pub mode:         Option<ProfileMode>,
+    pub schema:       Option<PathBuf>,
+    pub log:          Option<PathBuf>,
+    pub rotate:       Option<String>,
+    #[serde(default)]
+    pub alarm:        Vec<String>,
+    #[serde(default)]
+    pub alarms:       Vec<PathBuf>,
+    pub forward:      Option<String>,
+    pub capture:      Option<PathBuf>,
+    #[serde(default)]
+    macros:           BTreeMap<String, MacroDef>,
+    /// Directory of the config file
+    #[serde(skip)]
+    dir:              PathBuf,
+}
+
+impl Profile {
+    /// Options in command line form
+    pub fn args(&self) -> Vec<String> {
+        let mut args: Vec<String> = self.port.iter().cloned().collect();
+
+        match self.mode {
+            Some(ProfileMode::Plain) => args.push("plain".into()),
+            Some(ProfileMode::Stuffed) => args.push("stuffed".into()),
+            Some(ProfileMode::Direct) => args.push("direct".into()),
+            None => (),
+        }
+
+        let level = |on: bool| if on { "on" } else { "off" }.to_string();
+        let options = [
+            ("vid", self.vid.clone()),
+            ("pid", self.pid.clone()),
+            ("sn", self.sn.clone()),
+            ("manufacturer", self.manufacturer.clone()),
+            ("product", self.product.clone()),
+            ("baud", self.baud.map(|v| v.to_string())),
+            ("databits", self.databits.map(|v| v.to_string())),
+            ("parity", self.parity.clone()),
+            ("stopbits", self.stopbits.map(|v| v.to_string())),
+            ("flow", self.flow.clone()),
+            ("timeout", self.timeout.clone()),
+            ("dtr", self.dtr.map(level)),
+            ("rts", self.rts.map(level)),
+            ("acktimeout", self.acktimeout.clone()),
+            ("retries", self.retries.map(|v| v.to_string())),
+            ("schema", self.schema.as_ref().map(|p| self.resolve(p))),
+            ("log", self.log.as_ref().map(|p| p.display().to_string())),
+            ("rotate", self.rotate.clone()),
+            ("forward", self.forward.clone()),
+            ("capture", self.capture.as_ref().map(|p| p.display().to_string())),
+        ];
+        args.extend(
+            options
+                .into_iter()
+                .filter_map(|(key, value)| Some(format!("{}={}", key, value?))),
+        );
+
+        args.extend(self.alarm.iter().map(|rule| format!("alarm={}", rule)));
+        args.extend(
+            self.alarms
+                .iter()
+                .map(|path| format!("alarms={}", self.resolve(path))),
+        );
+
+        args
+    }
+
+    /// Input lines of each macro
+    pub fn macros(&self) -> BTreeMap<String, Vec<String>> {
+        self.macros
+            .iter()
+            .map(|(name, lines)| {
+                let lines = match lines {
+                    MacroDef::Line(line) => vec![line.clone()],
+                    MacroDef::Lines(lines) => lines.clone(),
+                };
+                (name.clone(), lines)
+            })
+            .collect()
+    }
+
+    /// Input file path, relative to the config file
+    fn resolve(&self, path: &Path) -> String {
+        self.dir.join(path).display().to_string()
+    }
+}
+
+/// Config file contents
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    profiles: BTreeMap<String, Profile>,
+    /// Files read, user config first
+    sources:  Vec<PathBuf>,
+}
+
+impl Config {
+    /// Read the user and project config files that exist
+    pub fn load() -> AnyResult<Self> {
+        let mut config = Self::default();
+
+        for path in [user_config_path(), project_config_path()]
+            .into_iter()
+            .flatten()
+        {
+            if !path.is_file() {
+                continue;
+            }
+
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("Couldn't read config {}", path.display()))?;
+            config.add(path, &text)?;
+        }
+
+        Ok(config)
+    }
+
+    /// Add the profiles of the config file at `path`, replacing the ones with the same name
+    pub fn add(&mut self, path: PathBuf, text: &str) -> AnyResult<()> {
+        let file: ConfigFile =
+            toml::from_str(text).with_context(|| format!("Invalid config {}", path.display()))?;
+
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        for (name, mut profile) in file.profiles {
+            profile.dir = dir.clone();
+            self.profiles.insert(name, profile);
+        }
+        self.sources.push(path);
+        Ok(())
+    }
+
+    pub fn profile(&self, name: &str) -> AnyResult<&Profile> {
+        if let Some(profile) = self.profiles.get(name) {
+            return Ok(profile);
+        }
+
+        if self.sources.is_empty() {
+            let user = user_config_path()
+                .map(|p| format!("{} or ", p.display()))
+                .unwrap_or_default();
+            bail!("Unknown profile @{}, no config file found in {}{}", name, user, PROJECT_CONFIG);
+        }
+
+        let names: Vec<String> = self.profiles.keys().map(|n| format!("@{}", n)).collect();
+        bail!(
+            "Unknown profile @{}, available: {}",
+            name,
+            if names.is_empty() { "none".into() } else { names.join(", ") }
+        )
+    }
+}
+
+/// `$XDG_CONFIG_HOME/mxs/config.toml`, `~/.config/mxs/config.toml` or `%APPDATA%\mxs\config.toml`
+fn user_config_path() -> Option<PathBuf> {
+    #[cfg(windows)]
+    let dir = env::var_os("APPDATA").map(PathBuf::from);
+
+    #[cfg(not(windows))]
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+
+    dir.map(|dir| dir.join("mxs").join("config.toml"))
+}
+
+/// `PROJECT_CONFIG` in the working directory or the closest parent
+fn project_config_path() -> Option<PathBuf> {
+    let cwd = env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG))
+        .find(|path| path.is_file())
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Tests
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER: &str = r#"
+        [profiles.imu]
+        port   = "/dev/ttyACM0"
+        baud   = 921600
+        schema = "imu.toml"
+
+        [profiles.probe]
+        vid     = "0483"
+        product = "*STLink*"
+    "#;
+
+    const PROJECT: &str = r#"
+        [profiles.imu]
+        baud   = 115200
+        mode   = "direct"
+        alarms = ["alarms.txt"]
+
+        [profiles.imu.macros]
+        calibrate = ["!calibrate", "!save"]
+        status    = "!status"
+    "#;
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.add("home/mxs/config.toml".into(), USER).unwrap();
+        config.add("project/mxs.toml".into(), PROJECT).unwrap();
+        config
+    }
+
+    #[test]
+    fn project_replaces_user_profiles() {
+        let config = config();
+
+        let imu = config.profile("imu").unwrap();
+        assert_eq!(imu.port, None);
+        assert_eq!(imu.schema, None);
+        assert_eq!(imu.args(), ["direct", "baud=115200", "alarms=project/alarms.txt"]);
+
+        let probe = config.profile("probe").unwrap();
+        assert_eq!(probe.args(), ["vid=0483", "product=*STLink*"]);
+    }
+
+    #[test]
+    fn paths_and_macros() {
+        let mut config = Config::default();
+        config.add("home/mxs/config.toml".into(), USER).unwrap();
+        assert_eq!(config.profile("imu").unwrap().args(), [
+            "/dev/ttyACM0",
+            "baud=921600",
+            "schema=home/mxs/imu.toml"
+        ]);
+
+        let macros = self::config().profile("imu").unwrap().macros();
+        assert_eq!(macros["calibrate"], ["!calibrate", "!save"]);
+        assert_eq!(macros["status"], ["!status"]);
+    }
+
+    #[test]
+    fn invalid_configs() {
+        let cases = [
+            "[profiles.imu]\nbaudrate = 9600",
+            "[profiles.imu]\nmode = \"fast\"",
+            "[profile.imu]",
+            "[profiles.imu",
+        ];
+
+        for text in cases {
+            assert!(Config::default().add("mxs.toml".into(), text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn unknown_profile() {
+        let error = config().profile("board").unwrap_err().to_string();
+        assert_eq!(error, "Unknown profile @board, available: @imu, @probe");
+    }
+}
@@ -0,0 +1,465 @@
+// Data format and handling
+//
+// `Imu`, `Battery` and `Motor` are example layouts of tagged records, not the records of a real
+// device. Tagged records are decoded by compiled code, so a device with other records needs them
+// added here and the terminal rebuilt. Untagged payloads are described by the schema file instead.
+
+use std::fmt;
+
+use anyhow::Result as AnyResult;
+use serde::Serialize;
+use serde_json::{Map, Value as JsonValue};
+
+use crate::mxs_record::{MxsRecord, mxs_record_ids_unique};
+use crate::mxs_registry::{MxsRegistryError, MxsTypeRegistry};
+
+use crate::app::schema::{DataSchema, FieldType, SchemaError, Value};
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Data
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+/// Data packet decoded with the active schema
+#[derive(Debug, Clone)]
+pub struct Data {
+    schema:     &'static DataSchema,
+    pub values: Vec<Value>,
+}
+
+impl Data {
+    pub fn decode(schema: &'static DataSchema, buf: &[u8]) -> Result<Self, SchemaError> {
+        let values = schema.decode(buf)?;
+        Ok(Self { schema, values })
+    }
+
+    pub fn schema(&self) -> &'static DataSchema {
+        self.schema
+    }
+
+    /// Field names with their values
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.schema
+            .fields()
+            .iter()
+            .map(|f| f.name.as_str())
+            .zip(&self.values)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.fields().find(|(n, _)| *n == name).map(|(_, v)| v)
+    }
+
+    pub fn to_json(&self) -> Map<String, JsonValue> {
+        self.fields()
+            .map(|(name, value)| (name.to_string(), value.to_json()))
+            .collect()
+    }
+
+    /// One line summary, printed by the data processors
+    pub fn process(&self) -> AnyResult<String> {
+        let fields: Vec<String> = self
+            .schema
+            .fields()
+            .iter()
+            .zip(&self.values)
+            .map(|(field, value)| match &field.unit {
+                Some(unit) => format!("{}={} {}", field.name, value, unit),
+                None => format!("{}={}", field.name, value),
+            })
+            .collect();
+
+        Ok(format!("MXS Data: {}", fields.join(" ")))
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                            Records
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+/// Decoded Data packet
+#[derive(Debug, Clone)]
+pub enum Record {
+    /// Untagged payload, decoded with the schema
+    Data(Data),
+    Imu(Imu),
+    Battery(Battery),
+    Motor(Motor),
+}
+
+impl Record {
+    /// Pick the decoder from the record id of a tagged payload
+    pub fn decode(
+        record_id: Option<u8>,
+        buf: &[u8],
+        schema: &'static DataSchema,
+    ) -> Result<Self, DataError> {
+        let record = match record_id {
+            None => Self::Data(Data::decode(schema, buf)?),
+            Some(Imu::RECORD_ID) => Self::Imu(decode_record(buf)?),
+            Some(Battery::RECORD_ID) => Self::Battery(decode_record(buf)?),
+            Some(Motor::RECORD_ID) => Self::Motor(decode_record(buf)?),
+            Some(id) => return Err(DataError::UnknownRecord(id)),
+        };
+
+        Ok(record)
+    }
+
+    /// Record kind, used to label logged records
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Data(_) => "data",
+            Self::Imu(_) => "imu",
+            Self::Battery(_) => "battery",
+            Self::Motor(_) => "motor",
+        }
+    }
+
+    /// Field names and values, in layout order
+    pub fn to_json(&self) -> Map<String, JsonValue> {
+        let value = match self {
+            Self::Data(data) => return data.to_json(),
+            Self::Imu(imu) => serde_json::to_value(imu),
+            Self::Battery(battery) => serde_json::to_value(battery),
+            Self::Motor(motor) => serde_json::to_value(motor),
+        };
+
+        match value {
+            Ok(JsonValue::Object(map)) => map,
+            _ => Map::new(),
+        }
+    }
+
+    /// Unit label of a field, from the schema
+    pub fn unit(&self, field: &str) -> Option<&'static str> {
+        match self {
+            Self::Data(data) => data.schema().unit(field),
+            _ => None,
+        }
+    }
+
+    /// Numeric fields as `f64`, arrays split into `name.0`, `name.1`, ..
+    ///
+    /// Fields of tagged records are prefixed with the record name, e.g. `imu.accel.0`.
+    pub fn numeric_fields(&self) -> Vec<(String, f64)> {
+        fn flatten(out: &mut Vec<(String, f64)>, name: String, value: &JsonValue) {
+            match value {
+                JsonValue::Array(items) => {
+                    for (i, item) in items.iter().enumerate() {
+                        flatten(out, format!("{}.{}", name, i), item);
+                    }
+                }
+                JsonValue::Bool(b) => out.push((name, *b as u8 as f64)),
+                value => {
+                    if let Some(v) = value.as_f64() {
+                        out.push((name, v));
+                    }
+                }
+            }
+        }
+
+        let mut fields = Vec::new();
+        for (name, value) in self.to_json() {
+            let name = match self {
+                Self::Data(_) => name,
+                _ => format!("{}.{}", self.name(), name),
+            };
+            flatten(&mut fields, name, &value);
+        }
+        fields
+    }
+
+    /// Names `numeric_fields` gives for the records of every kind
+    pub fn numeric_field_names(schema: &DataSchema) -> Vec<String> {
+        let data = schema
+            .fields()
+            .iter()
+            .filter(|field| !matches!(field.ty, FieldType::Str(_)))
+            .map(|field| field.name.clone());
+        let tagged = [
+            Self::Imu(Imu::default()),
+            Self::Battery(Battery::default()),
+            Self::Motor(Motor::default()),
+        ];
+
+        data.chain(
+            tagged
+                .iter()
+                .flat_map(|record| record.numeric_fields())
+                .map(|(name, _)| name),
+        )
+        .collect()
+    }
+
+    pub fn process(&self) -> AnyResult<String> {
+        match self {
+            Self::Data(data) => data.process(),
+            Self::Imu(imu) => imu.process(),
+            Self::Battery(battery) => battery.process(),
+            Self::Motor(motor) => motor.process(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DataError {
+    /// Record id without a decoder
+    UnknownRecord(u8),
+    /// Payload size does not match the layout
+    Size { expected: usize, received: usize },
+}
+
+impl fmt::Display for DataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownRecord(id) => write!(f, "Unknown record id {}", id),
+            Self::Size { expected, received } => {
+                write!(f, "Payload is {} bytes, expected {}", received, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DataError {}
+
+impl From<SchemaError> for DataError {
+    fn from(e: SchemaError) -> Self {
+        Self::Size {
+            expected: e.expected,
+            received: e.received,
+        }
+    }
+}
+
+// Record ids sent on the same link
+const _: () = assert!(
+    mxs_record_ids_unique(&[Imu::RECORD_ID, Battery::RECORD_ID, Motor::RECORD_ID]),
+    "Duplicate record id"
+);
+
+fn decode_record<R: MxsRecord>(buf: &[u8]) -> Result<R, DataError> {
+    R::decode(buf).ok_or(DataError::Size {
+        expected: R::SIZE,
+        received: buf.len(),
+    })
+}
+
+// —————————————————————————————————————————————— IMU ——————————————————————————————————————————————
+
+#[derive(Debug, Default, Clone, Copy, Serialize, MxsRecord)]
+#[mxs(id = 1, size = 12)]
+pub struct Imu {
+    pub accel: [i16; 3],
+    pub gyro:  [i16; 3],
+}
+
+impl Imu {
+    pub fn process(&self) -> AnyResult<String> {
+        Ok(format!("IMU: accel {:?} gyro {:?}", self.accel, self.gyro))
+    }
+}
+
+// ———————————————————————————————————————————— Battery ————————————————————————————————————————————
+
+#[derive(Debug, Default, Clone, Copy, Serialize, MxsRecord)]
+#[mxs(id = 2, size = 5)]
+pub struct Battery {
+    pub millivolts: u16,
+    pub milliamps:  i16,
+    pub charge:     u8,
+}
+
+impl Battery {
+    pub fn process(&self) -> AnyResult<String> {
+        Ok(format!(
+            "Battery: {} mV {} mA {}%",
+            self.millivolts, self.milliamps, self.charge
+        ))
+    }
+}
+
+// ————————————————————————————————————————————— Motor —————————————————————————————————————————————
+
+#[derive(Debug, Default, Clone, Copy, Serialize, MxsRecord)]
+#[mxs(id = 3, size = 7)]
+pub struct Motor {
+    pub rpm:         i32,
+    pub milliamps:   i16,
+    pub temperature: i8,
+}
+
+impl Motor {
+    pub fn process(&self) -> AnyResult<String> {
+        Ok(format!("Motor: {} rpm {} mA {} °C", self.rpm, self.milliamps, self.temperature))
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                             Message
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+/// Message reassembled from fragmented Data packets
+#[derive(Debug, Default, Clone)]
+pub struct Message(pub Vec<u8>);
+
+impl Message {
+    /// Bytes shown of a binary message
+    const PREVIEW_LEN: usize = 32;
+
+    /// Text messages are printed whole, binary ones as a hex preview
+    pub fn process(&self) -> AnyResult<String> {
+        let bytes = &self.0;
+        let mut line = format!("MXS Message: {} bytes", bytes.len());
+
+        if let Ok(text) = std::str::from_utf8(bytes)
+            && !text.trim().is_empty()
+            && !text.chars().any(|c| c.is_control() && !c.is_whitespace())
+        {
+            return Ok(format!("{}\n{}\n", line, text.trim_end()));
+        }
+
+        for byte in bytes.iter().take(Self::PREVIEW_LEN) {
+            line.push_str(&format!(" {:02X}", byte));
+        }
+        if bytes.len() > Self::PREVIEW_LEN {
+            line.push_str(" ..");
+        }
+        line.push('\n');
+        Ok(line)
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                          Packet Types
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+/// Device log line sent as a packet instead of plain text
+pub const LOG_PACKET_ID: u8 = 0x10;
+
+/// Application specific packet types
+pub fn register_packet_types(registry: &mut MxsTypeRegistry) -> Result<(), MxsRegistryError> {
+    registry.register(LOG_PACKET_ID, "Log", |payload| {
+        Ok(String::from_utf8_lossy(payload).trim_end().to_string())
+    })?;
+
+    Ok(())
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Tests
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::LazyLock;
+
+    static SCHEMA: LazyLock<DataSchema> = LazyLock::new(DataSchema::default);
+
+    fn decode(record_id: Option<u8>, buf: &[u8]) -> Result<Record, DataError> {
+        Record::decode(record_id, buf, &SCHEMA)
+    }
+
+    #[test]
+    fn decode_routes_by_record_id() {
+        let imu = [1, 0, 2, 0, 3, 0, 0xFF, 0xFF, 0xFE, 0xFF, 0xFD, 0xFF];
+        let Ok(Record::Imu(imu)) = decode(Some(Imu::RECORD_ID), &imu)
+        else {
+            panic!("Not an IMU record");
+        };
+        assert_eq!((imu.accel, imu.gyro), ([1, 2, 3], [-1, -2, -3]));
+
+        let Ok(Record::Battery(battery)) =
+            decode(Some(Battery::RECORD_ID), &[0x10, 0x0E, 0x9C, 0xFF, 80])
+        else {
+            panic!("Not a battery record");
+        };
+        assert_eq!((battery.millivolts, battery.milliamps, battery.charge), (3600, -100, 80));
+
+        let motor = [0x60, 0xEA, 0x00, 0x00, 0xF4, 0x01, 0xF6];
+        let Ok(Record::Motor(motor)) = decode(Some(Motor::RECORD_ID), &motor)
+        else {
+            panic!("Not a motor record");
+        };
+        assert_eq!((motor.rpm, motor.milliamps, motor.temperature), (60000, 500, -10));
+
+        let Ok(Record::Data(data)) = decode(None, &[1, 0, 2, 0, 3, 0])
+        else {
+            panic!("Not a schema record");
+        };
+        assert_eq!(data.get("field2"), Some(&Value::Signed(3)));
+    }
+
+    #[test]
+    fn decode_errors() {
+        assert_eq!(decode(Some(9), &[]).unwrap_err(), DataError::UnknownRecord(9));
+
+        let cases = [
+            (Some(Imu::RECORD_ID), Imu::SIZE),
+            (Some(Battery::RECORD_ID), Battery::SIZE),
+            (Some(Motor::RECORD_ID), Motor::SIZE),
+            (None, SCHEMA.size()),
+        ];
+
+        for (record_id, size) in cases {
+            for received in [0, size - 1, size + 1] {
+                assert_eq!(
+                    decode(record_id, &vec![0; received]).unwrap_err(),
+                    DataError::Size { expected: size, received },
+                    "{:?}",
+                    record_id
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn numeric_fields_of_tagged_records_are_prefixed() {
+        let imu = Record::Imu(Imu {
+            accel: [1, 2, 3],
+            gyro:  [4, 5, -6],
+        });
+        assert_eq!(imu.numeric_fields(), [
+            ("imu.accel.0".to_string(), 1.0),
+            ("imu.accel.1".to_string(), 2.0),
+            ("imu.accel.2".to_string(), 3.0),
+            ("imu.gyro.0".to_string(), 4.0),
+            ("imu.gyro.1".to_string(), 5.0),
+            ("imu.gyro.2".to_string(), -6.0),
+        ]);
+
+        // Schema fields keep their names
+        let data = decode(None, &[1, 0, 2, 0, 0xFF, 0xFF]).unwrap();
+        assert_eq!(data.numeric_fields(), [
+            ("field0".to_string(), 1.0),
+            ("field1".to_string(), 2.0),
+            ("field2".to_string(), -1.0),
+        ]);
+    }
+
+    #[test]
+    fn numeric_field_names_skip_text() {
+        let schema = DataSchema::from_toml(
+            r#"
+            [[fields]]
+            name = "count"
+            type = "u8"
+
+            [[fields]]
+            name = "label"
+            type = "str"
+            len  = 4
+
+            [[fields]]
+            name = "ok"
+            type = "bool"
+            "#,
+        )
+        .unwrap();
+
+        let names = Record::numeric_field_names(&schema);
+        assert_eq!(names[..3], ["count", "ok", "imu.accel.0"]);
+        assert!(names.contains(&"motor.temperature".to_string()));
+        assert!(!names.iter().any(|name| name == "label"));
+    }
+}
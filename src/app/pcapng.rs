@@ -0,0 +1,251 @@
+//! pcapng export
+//!
+//! Converts a capture file to pcapng for Wireshark. Each capture chunk becomes one packet on a
+//! `LINKTYPE_USER0` interface, with its timestamp and direction kept. `MXS_DISSECTOR` decodes the
+//! MXS packets inside the chunks.
+//!
+//! Blocks written, little-endian:
+//!
+//! ```text
+//! Section Header        application name
+//! Interface Description port name, framing and baud rate, µs timestamps
+//! Enhanced Packet       one per chunk, inbound (device) or outbound (host) direction flag
+//! ```
+
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result as AnyResult};
+
+use crate::app::capture::{CaptureDirection, CaptureMode, CaptureReader};
+
+/// Wireshark Lua dissector for the exported files
+pub const MXS_DISSECTOR: &str = include_str!("../../wireshark/mxs.lua");
+
+/// First of the link types reserved for private use, DLT 147
+const LINKTYPE_USER0: u16 = 147;
+
+const BLOCK_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const BLOCK_INTERFACE: u32 = 0x0000_0001;
+const BLOCK_ENHANCED_PACKET: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+const OPT_END: u16 = 0;
+const OPT_SHB_USERAPPL: u16 = 4;
+const OPT_IF_NAME: u16 = 2;
+const OPT_IF_DESCRIPTION: u16 = 3;
+const OPT_IF_TSRESOL: u16 = 9;
+const OPT_EPB_FLAGS: u16 = 2;
+
+/// `epb_flags` direction bits
+const EPB_INBOUND: u32 = 0b01;
+const EPB_OUTBOUND: u32 = 0b10;
+
+/// Convert the capture file `capture` to the pcapng file `out`, returns the number of packets
+pub fn export_pcapng_file(capture: &Path, out: &Path) -> AnyResult<usize> {
+    let reader = CaptureReader::open(capture)?;
+    let file = File::create(out).with_context(|| format!("Couldn't create {}", out.display()))?;
+
+    export_pcapng(reader, BufWriter::new(file))
+        .with_context(|| format!("Couldn't write {}", out.display()))
+}
+
+/// Write the chunks of `reader` as pcapng to `writer`, returns the number of packets
+pub fn export_pcapng<R: Read, W: Write>(
+    mut reader: CaptureReader<R>,
+    mut writer: W,
+) -> AnyResult<usize> {
+    let header = reader.header().clone();
+
+    // ---- Section Header
+    let mut body = Vec::new();
+    body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+    body.extend_from_slice(&1u16.to_le_bytes());
+    body.extend_from_slice(&0u16.to_le_bytes());
+    // Section length not specified
+    body.extend_from_slice(&(-1i64).to_le_bytes());
+    let application = format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    push_option(&mut body, OPT_SHB_USERAPPL, application.as_bytes());
+    push_option(&mut body, OPT_END, &[]);
+    write_block(&mut writer, BLOCK_SECTION_HEADER, &body)?;
+
+    // ---- Interface Description
+    let description = match header.mode {
+        CaptureMode::Direct => format!("Direct mode, {} baud", header.baud),
+        mode => format!(
+            "MXS {} framing, host v{}, {} baud",
+            if mode == CaptureMode::Stuffed { "stuffed" } else { "plain" },
+            header.host_protocol,
+            header.baud
+        ),
+    };
+
+    let mut body = Vec::new();
+    body.extend_from_slice(&LINKTYPE_USER0.to_le_bytes());
+    body.extend_from_slice(&0u16.to_le_bytes());
+    // No snapshot length limit
+    body.extend_from_slice(&0u32.to_le_bytes());
+    push_option(&mut body, OPT_IF_NAME, header.port.as_bytes());
+    push_option(&mut body, OPT_IF_DESCRIPTION, description.as_bytes());
+    // Microseconds
+    push_option(&mut body, OPT_IF_TSRESOL, &[6]);
+    push_option(&mut body, OPT_END, &[]);
+    write_block(&mut writer, BLOCK_INTERFACE, &body)?;
+
+    // ---- Enhanced Packets
+    let started = header.started.timestamp_micros() as u64;
+    let mut packets = 0;
+
+    while let Some(chunk) = reader.next_chunk()? {
+        let timestamp = started + chunk.time.as_micros() as u64;
+        let flags = match chunk.direction {
+            CaptureDirection::Rx => EPB_INBOUND,
+            CaptureDirection::Tx => EPB_OUTBOUND,
+        };
+        let len = chunk.data.len() as u32;
+
+        let mut body = Vec::with_capacity(chunk.data.len() + 32);
+        // Interface id
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(timestamp as u32).to_le_bytes());
+        // Captured and original length
+        body.extend_from_slice(&len.to_le_bytes());
+        body.extend_from_slice(&len.to_le_bytes());
+        push_padded(&mut body, &chunk.data);
+        push_option(&mut body, OPT_EPB_FLAGS, &flags.to_le_bytes());
+        push_option(&mut body, OPT_END, &[]);
+        write_block(&mut writer, BLOCK_ENHANCED_PACKET, &body)?;
+
+        packets += 1;
+    }
+
+    writer.flush()?;
+    Ok(packets)
+}
+
+/// Block type and total length on both sides of the body
+fn write_block(out: &mut impl Write, block_type: u32, body: &[u8]) -> AnyResult<()> {
+    let total_len = (body.len() + 12) as u32;
+
+    out.write_all(&block_type.to_le_bytes())?;
+    out.write_all(&total_len.to_le_bytes())?;
+    out.write_all(body)?;
+    out.write_all(&total_len.to_le_bytes())?;
+    Ok(())
+}
+
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_le_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    push_padded(body, value);
+}
+
+/// Append `data` padded to 32 bits
+fn push_padded(body: &mut Vec<u8>, data: &[u8]) {
+    body.extend_from_slice(data);
+    body.resize(body.len().next_multiple_of(4), 0);
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Tests
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use chrono::DateTime;
+
+    use super::*;
+
+    use crate::app::capture::{CaptureHeader, CaptureWriter};
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn padding() {
+        for (len, padded) in [(0, 0), (1, 4), (3, 4), (4, 4), (5, 8)] {
+            let mut body = Vec::new();
+            push_padded(&mut body, &vec![0xFF; len]);
+            assert_eq!(body.len(), padded, "{}", len);
+            assert!(body[len..].iter().all(|&b| b == 0), "{}", len);
+        }
+
+        let mut body = Vec::new();
+        push_option(&mut body, OPT_IF_NAME, b"tty");
+        assert_eq!(body, [2, 0, 3, 0, b't', b't', b'y', 0]);
+    }
+
+    #[test]
+    fn block_lengths() {
+        let header = CaptureHeader {
+            port:          "/dev/ttyACM0".into(),
+            baud:          115_200,
+            mode:          CaptureMode::Plain,
+            host_protocol: 1,
+            started:       DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+        };
+        let chunks: [(CaptureDirection, &[u8]); 3] = [
+            (CaptureDirection::Tx, b"start"),
+            (CaptureDirection::Rx, b"data"),
+            (CaptureDirection::Rx, b"x"),
+        ];
+
+        let mut capture = Vec::new();
+        let mut writer = CaptureWriter::new(&mut capture, &header).unwrap();
+        for (direction, data) in chunks {
+            writer.write(direction, data).unwrap();
+        }
+        drop(writer);
+
+        let reader = CaptureReader::new(Cursor::new(capture)).unwrap();
+        let mut bytes = Vec::new();
+        assert_eq!(export_pcapng(reader, &mut bytes).unwrap(), chunks.len());
+
+        let mut blocks = Vec::new();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let block_type = u32_at(&bytes, offset);
+            let len = u32_at(&bytes, offset + 4) as usize;
+            assert_eq!(len % 4, 0, "block at {}", offset);
+            assert_eq!(u32_at(&bytes, offset + len - 4) as usize, len, "block at {}", offset);
+
+            blocks.push((block_type, &bytes[offset + 8..offset + len - 4]));
+            offset += len;
+        }
+        assert_eq!(offset, bytes.len());
+
+        let types: Vec<u32> = blocks.iter().map(|(block_type, _)| *block_type).collect();
+        assert_eq!(types, [
+            BLOCK_SECTION_HEADER,
+            BLOCK_INTERFACE,
+            BLOCK_ENHANCED_PACKET,
+            BLOCK_ENHANCED_PACKET,
+            BLOCK_ENHANCED_PACKET
+        ]);
+        assert_eq!(u32_at(blocks[0].1, 0), BYTE_ORDER_MAGIC);
+
+        for ((direction, data), (_, body)) in chunks.iter().zip(&blocks[2..]) {
+            let len = data.len();
+            let padded = len.next_multiple_of(4);
+            assert_eq!(u32_at(body, 12) as usize, len);
+            assert_eq!(u32_at(body, 16) as usize, len);
+            assert_eq!(&body[20..20 + len], *data);
+            assert!(body[20 + len..20 + padded].iter().all(|&b| b == 0));
+
+            // Flags option, then the end of options
+            let options = &body[20 + padded..];
+            let flags = match direction {
+                CaptureDirection::Rx => EPB_INBOUND,
+                CaptureDirection::Tx => EPB_OUTBOUND,
+            };
+            assert_eq!(options[..4], [OPT_EPB_FLAGS as u8, 0, 4, 0]);
+            assert_eq!(u32_at(options, 4), flags);
+            assert_eq!(options[8..], [0, 0, 0, 0]);
+        }
+    }
+}
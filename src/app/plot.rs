@@ -0,0 +1,353 @@
+//! Live plot of numeric record fields
+//!
+//! One rolling chart per field, drawn in the terminal panel above the input bar. Each chart is
+//! scaled to the samples currently on screen.
+
+use std::collections::VecDeque;
+
+use crossterm::style::Stylize;
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Plot
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+/// Samples kept per channel, enough for a wide terminal in braille mode
+const PLOT_HISTORY: usize = 1024;
+/// Channels beyond this are not plotted
+pub const MAX_PLOT_CHANNELS: usize = 8;
+
+const NAME_WIDTH: usize = 16;
+const VALUE_WIDTH: usize = 10;
+const MIN_CHART_WIDTH: usize = 8;
+
+const SPARK_LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+/// Terminal rows per braille chart, each row has 4 dot levels
+const BRAILLE_ROWS: usize = 2;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum PlotStyle {
+    #[default]
+    Sparkline,
+    Braille,
+}
+
+struct Channel {
+    name:    String,
+    samples: VecDeque<f64>,
+}
+
+#[derive(Default)]
+pub struct Plot {
+    channels: Vec<Channel>,
+    visible:  bool,
+    paused:   bool,
+    style:    PlotStyle,
+}
+
+impl Plot {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add one sample per field, new fields get a channel while there is room
+    pub fn push(&mut self, fields: &[(String, f64)]) {
+        if self.paused {
+            return;
+        }
+
+        for (name, value) in fields {
+            let pos = match self.channels.iter().position(|c| &c.name == name) {
+                Some(pos) => pos,
+                None if self.channels.len() < MAX_PLOT_CHANNELS => {
+                    self.channels.push(Channel {
+                        name:    name.clone(),
+                        samples: VecDeque::with_capacity(PLOT_HISTORY),
+                    });
+                    self.channels.len() - 1
+                }
+                None => continue,
+            };
+
+            let samples = &mut self.channels[pos].samples;
+            if samples.len() == PLOT_HISTORY {
+                samples.pop_front();
+            }
+            samples.push_back(*value);
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn toggle_visible(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn toggle_style(&mut self) {
+        self.style = match self.style {
+            PlotStyle::Sparkline => PlotStyle::Braille,
+            PlotStyle::Braille => PlotStyle::Sparkline,
+        };
+    }
+
+    /// Terminal lines taken by `render`, 0 when hidden
+    pub fn height(&self) -> u16 {
+        if !self.visible {
+            return 0;
+        }
+        (1 + self.channels.len().max(1) * self.rows_per_channel()) as u16
+    }
+
+    /// Title line followed by the charts
+    pub fn render(&self, width: usize) -> Vec<String> {
+        let mut lines = Vec::with_capacity(self.height() as usize);
+
+        let state = if self.paused {
+            " PAUSED ".black().on_yellow().to_string()
+        }
+        else {
+            String::new()
+        };
+        lines.push(format!(
+            "{} {}{}",
+            format!("Plot [{:?}]", self.style).bold(),
+            "F2 hide  F3 pause  F4 style ".dark_grey(),
+            state
+        ));
+
+        if self.channels.is_empty() {
+            lines.push("Waiting for data ...".dark_grey().to_string());
+            return lines;
+        }
+
+        // [name] [last value] [chart] [min..max]
+        let scale_width = 2 * VALUE_WIDTH + 3;
+        let chart_width = width
+            .saturating_sub(NAME_WIDTH + VALUE_WIDTH + scale_width + 2)
+            .max(MIN_CHART_WIDTH);
+
+        for channel in &self.channels {
+            let per_cell = if self.style == PlotStyle::Braille { 2 } else { 1 };
+            let count = channel.samples.len().min(chart_width * per_cell);
+            let visible: Vec<f64> = channel
+                .samples
+                .iter()
+                .skip(channel.samples.len() - count)
+                .copied()
+                .collect();
+
+            // ---- Autoscale
+            let min = visible.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = visible.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            let last = visible.last().copied().unwrap_or_default();
+
+            let chart = match self.style {
+                PlotStyle::Sparkline => vec![sparkline(&visible, min, max, chart_width)],
+                PlotStyle::Braille => braille(&visible, min, max, chart_width),
+            };
+
+            for (row, chart_row) in chart.into_iter().enumerate() {
+                let (name, value, scale) = if row == 0 {
+                    (
+                        truncate(&channel.name, NAME_WIDTH),
+                        format_value(last),
+                        format!("{}..{}", format_value(min), format_value(max)),
+                    )
+                }
+                else {
+                    Default::default()
+                };
+
+                lines.push(format!(
+                    "{:<nw$} {:>vw$} {} {}",
+                    name,
+                    value,
+                    chart_row.green(),
+                    scale.dark_grey(),
+                    nw = NAME_WIDTH,
+                    vw = VALUE_WIDTH
+                ));
+            }
+        }
+
+        lines
+    }
+
+    fn rows_per_channel(&self) -> usize {
+        match self.style {
+            PlotStyle::Sparkline => 1,
+            PlotStyle::Braille => BRAILLE_ROWS,
+        }
+    }
+}
+
+// ——————————————————————————————————————————— Drawing —————————————————————————————————————————————
+
+/// Position of `value` between `min` and `max`, as a level from 0 to `levels - 1`
+fn level(value: f64, min: f64, max: f64, levels: usize) -> usize {
+    let range = max - min;
+    if !range.is_finite() || range <= f64::EPSILON * max.abs().max(1.0) {
+        return levels / 2;
+    }
+    (((value - min) / range) * (levels - 1) as f64).round() as usize
+}
+
+/// One character per sample, right aligned
+fn sparkline(samples: &[f64], min: f64, max: f64, width: usize) -> String {
+    let mut line = " ".repeat(width - samples.len());
+    line.extend(
+        samples
+            .iter()
+            .map(|&v| SPARK_LEVELS[level(v, min, max, SPARK_LEVELS.len())]),
+    );
+    line
+}
+
+/// Two samples per character, `BRAILLE_ROWS` rows of 4 dots, right aligned
+fn braille(samples: &[f64], min: f64, max: f64, width: usize) -> Vec<String> {
+    // Dot bits of a braille cell, by column and dot row from the top
+    const DOTS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+    let levels = BRAILLE_ROWS * 4;
+
+    let mut cells = vec![[0u32; BRAILLE_ROWS]; width];
+    let offset = width * 2 - samples.len();
+
+    for (i, &value) in samples.iter().enumerate() {
+        let pos = offset + i;
+        let level = level(value, min, max, levels);
+
+        // Row 0 is the top one
+        let row = BRAILLE_ROWS - 1 - level / 4;
+        let dot = 3 - level % 4;
+        cells[pos / 2][row] |= DOTS[pos % 2][dot];
+    }
+
+    (0..BRAILLE_ROWS)
+        .map(|row| {
+            cells
+                .iter()
+                .map(|cell| char::from_u32(0x2800 + cell[row]).unwrap())
+                .collect()
+        })
+        .collect()
+}
+
+/// Short form of a value for the panels: integers as is, others with 2 decimals or an exponent
+pub fn format_value(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e9 {
+        format!("{}", value as i64)
+    }
+    else if value.abs() >= 1e5 || value.abs() < 1e-2 {
+        format!("{:.2e}", value)
+    }
+    else {
+        format!("{:.2}", value)
+    }
+}
+
+fn truncate(name: &str, width: usize) -> String {
+    match name.char_indices().nth(width) {
+        Some((end, _)) => name[..end].to_string(),
+        None => name.to_string(),
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Tests
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels() {
+        let cases = [
+            (0.0, 0.0, 10.0, 0),
+            (10.0, 0.0, 10.0, 7),
+            (5.0, 0.0, 10.0, 4),
+            (-3.0, -3.0, -1.0, 0),
+            (-1.0, -3.0, -1.0, 7),
+            // Flat or empty ranges draw in the middle
+            (5.0, 5.0, 5.0, 4),
+            (1e9, 1e9, 1e9 + 1e-9, 4),
+            (0.0, f64::INFINITY, f64::NEG_INFINITY, 4),
+        ];
+
+        for (value, min, max, expected) in cases {
+            assert_eq!(level(value, min, max, 8), expected, "{} in {}..{}", value, min, max);
+        }
+    }
+
+    #[test]
+    fn sparklines_are_right_aligned() {
+        assert_eq!(sparkline(&[1.0, 2.0, 3.0], 1.0, 3.0, 5), "  ▁▅█");
+        assert_eq!(sparkline(&[4.0], 4.0, 4.0, 3), "  ▅");
+        assert_eq!(sparkline(&[], 0.0, 0.0, 2), "  ");
+    }
+
+    #[test]
+    fn braille_dots() {
+        // Min in the left column of the bottom row, max in the right column of the top row
+        assert_eq!(braille(&[0.0, 1.0], 0.0, 1.0, 1), ["⠈", "⡀"]);
+
+        // One sample goes to the right column of the last cell
+        assert_eq!(braille(&[2.0], 2.0, 2.0, 2), ["⠀⢀", "⠀⠀"]);
+    }
+
+    #[test]
+    fn push_limits() {
+        let fields = |value: f64| -> Vec<(String, f64)> {
+            (0..MAX_PLOT_CHANNELS + 2)
+                .map(|i| (format!("field{}", i), value))
+                .collect()
+        };
+
+        let mut plot = Plot::new();
+        plot.toggle_pause();
+        plot.push(&fields(0.0));
+        assert!(plot.channels.is_empty());
+
+        plot.toggle_pause();
+        for i in 0..PLOT_HISTORY + 5 {
+            plot.push(&fields(i as f64));
+        }
+
+        assert_eq!(plot.channels.len(), MAX_PLOT_CHANNELS);
+        assert_eq!(plot.channels.last().unwrap().name, format!("field{}", MAX_PLOT_CHANNELS - 1));
+        for channel in &plot.channels {
+            assert_eq!(channel.samples.len(), PLOT_HISTORY);
+            assert_eq!(channel.samples.front(), Some(&5.0));
+            assert_eq!(channel.samples.back(), Some(&(PLOT_HISTORY as f64 + 4.0)));
+        }
+    }
+
+    #[test]
+    fn format_values() {
+        let cases = [
+            (0.0, "0"),
+            (-42.0, "-42"),
+            (123456789.0, "123456789"),
+            (1e9, "1.00e9"),
+            (1.234, "1.23"),
+            (-0.5, "-0.50"),
+            (12345.678, "12345.68"),
+            (123456.7, "1.23e5"),
+            (0.004, "4.00e-3"),
+            (f64::NAN, "NaN"),
+        ];
+
+        for (value, expected) in cases {
+            assert_eq!(format_value(value), expected, "{}", value);
+        }
+    }
+}
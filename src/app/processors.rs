@@ -0,0 +1,464 @@
+//! Data processors
+//!
+//! Every decoded record goes through this chain in the data thread. To run your own code on each
+//! record, implement `MxsDataProcessor<Sample>` and register it through `app::run_with`.
+
+use std::fmt;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::Ordering;
+use std::sync::{Mutex, mpsc};
+use std::time::Instant;
+
+use anyhow::{Context, Result as AnyResult, anyhow};
+use chrono::{SecondsFormat, Utc};
+use crossterm::style::Stylize;
+use serde_json::{Map, Value as JsonValue};
+
+use crate::app::alarm::{AlarmEvent, AlarmRule, Alarms};
+use crate::app::data::Record;
+use crate::app::sink::DataSink;
+use crate::app::stats::DataStats;
+use crate::app::{ALARM_RULES,
+                 CONSOLE_TX,
+                 DATA_SINK,
+                 FORWARD_ADDR,
+                 PLOT_VISIBLE,
+                 STATS_REFRESH,
+                 STATS_VISIBLE,
+                 ThreadMsg,
+                 USER_PROCESSORS};
+use crate::mxs_processor::{MxsDataProcessor, MxsFlow, MxsProcessorChain};
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                             Sample
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+/// Record passed along the processor chain
+#[derive(Debug)]
+pub struct Sample {
+    pub record: Record,
+    /// Numeric fields of the record, see `Record::numeric_fields`
+    pub fields: Vec<(String, f64)>,
+    /// Alarms fired by this record
+    pub alarms: Vec<AlarmEvent>,
+}
+
+impl Sample {
+    pub fn new(record: Record) -> Self {
+        Self {
+            fields: record.numeric_fields(),
+            record,
+            alarms: Vec::new(),
+        }
+    }
+}
+
+/// Adds processors of your own to the chain, see `app::run_with`
+pub type ProcessorRegistration =
+    Box<dyn FnOnce(&mut MxsProcessorChain<Sample>, &Console) -> AnyResult<()> + Send>;
+
+/// Processor added through `app::run_with`, kept across connections
+pub(crate) type UserProcessor = Mutex<Box<dyn MxsDataProcessor<Sample>>>;
+
+/// Prints to the terminal, above the input bar
+///
+/// Stays valid across reconnects. Lines printed while no port is open go straight to the terminal.
+#[derive(Debug, Clone)]
+pub struct Console {
+    _private: (),
+}
+
+impl Console {
+    /// Print a line
+    pub fn print(&self, line: impl fmt::Display) {
+        self.send(ThreadMsg::Print(format!("{}\n", line)));
+    }
+
+    /// Report an error, like the built-in processors do
+    pub fn error(&self, error: impl fmt::Display) {
+        self.send(ThreadMsg::Error(error.to_string()));
+    }
+
+    fn send(&self, msg: ThreadMsg) {
+        let msg = match &*CONSOLE_TX.lock().unwrap() {
+            Some(tx) => match tx.send(msg) {
+                Ok(()) => return,
+                Err(mpsc::SendError(msg)) => msg,
+            },
+            None => msg,
+        };
+
+        match msg {
+            ThreadMsg::Print(line) => print!("{}", line),
+            ThreadMsg::Error(error) => eprintln!("{}", error),
+            _ => (),
+        }
+    }
+}
+
+/// Run `register` once, its processors are shared by the chains of all connections
+pub(crate) fn register_user_processors(
+    register: ProcessorRegistration,
+) -> AnyResult<Vec<UserProcessor>> {
+    let mut chain = MxsProcessorChain::new();
+    register(&mut chain, &Console { _private: () }).context("Custom processors")?;
+
+    Ok(chain
+        .into_processors()
+        .into_iter()
+        .map(Mutex::new)
+        .collect())
+}
+
+/// Build the chain from the command line options, in processing order
+pub(crate) fn register_processors(
+    chain: &mut MxsProcessorChain<Sample>,
+    main_thread_tx: &mpsc::Sender<ThreadMsg>,
+) -> AnyResult<()> {
+    chain.register(StatsProcessor::new(main_thread_tx.clone()));
+
+    let rules = ALARM_RULES.get().unwrap();
+    if !rules.is_empty() {
+        chain.register(AlarmProcessor::new(rules.clone(), main_thread_tx.clone()));
+    }
+
+    for processor in USER_PROCESSORS.get().unwrap() {
+        chain.register(SharedProcessor::new(processor));
+    }
+
+    if let Some(config) = DATA_SINK.get().unwrap() {
+        chain.register(DataSink::new(config.clone()));
+    }
+
+    if let Some(addr) = FORWARD_ADDR.get().unwrap() {
+        chain.register(ForwardProcessor::new(*addr, main_thread_tx.clone())?);
+    }
+
+    chain.register(PlotProcessor(main_thread_tx.clone()));
+    chain.register(PrintProcessor(main_thread_tx.clone()));
+
+    Ok(())
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                           Processors
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+// ————————————————————————————————————————————— Stats —————————————————————————————————————————————
+
+/// Field statistics, sent to the panel while it is shown. `reset` restarts them.
+struct StatsProcessor {
+    stats:          DataStats,
+    main_thread_tx: mpsc::Sender<ThreadMsg>,
+    last_update:    Instant,
+}
+
+impl StatsProcessor {
+    fn new(main_thread_tx: mpsc::Sender<ThreadMsg>) -> Self {
+        Self {
+            stats: DataStats::new(),
+            main_thread_tx,
+            last_update: Instant::now(),
+        }
+    }
+
+    fn send(&mut self) -> AnyResult<()> {
+        self.main_thread_tx
+            .send(ThreadMsg::DataStats(self.stats.clone()))?;
+        self.last_update = Instant::now();
+        Ok(())
+    }
+}
+
+impl MxsDataProcessor<Sample> for StatsProcessor {
+    fn name(&self) -> &str {
+        "Statistics"
+    }
+
+    fn process(&mut self, sample: &mut Sample) -> AnyResult<MxsFlow> {
+        self.stats.push(&sample.fields);
+        Ok(MxsFlow::Continue)
+    }
+
+    fn poll(&mut self) -> AnyResult<()> {
+        if self.last_update.elapsed() >= STATS_REFRESH && STATS_VISIBLE.load(Ordering::Relaxed) {
+            self.send()?;
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, command: &str) -> AnyResult<bool> {
+        if command != "reset" {
+            return Ok(false);
+        }
+        self.stats.reset();
+        self.send()?;
+        Ok(true)
+    }
+}
+
+// ————————————————————————————————————————————— Alarms ————————————————————————————————————————————
+
+struct AlarmProcessor {
+    alarms:         Alarms,
+    main_thread_tx: mpsc::Sender<ThreadMsg>,
+}
+
+impl AlarmProcessor {
+    fn new(rules: Vec<AlarmRule>, main_thread_tx: mpsc::Sender<ThreadMsg>) -> Self {
+        Self {
+            alarms: Alarms::new(rules),
+            main_thread_tx,
+        }
+    }
+}
+
+impl MxsDataProcessor<Sample> for AlarmProcessor {
+    fn name(&self) -> &str {
+        "Alarms"
+    }
+
+    fn process(&mut self, sample: &mut Sample) -> AnyResult<MxsFlow> {
+        for event in self.alarms.check(&sample.fields, Instant::now()) {
+            self.main_thread_tx.send(ThreadMsg::Alarm(event.clone()))?;
+            sample.alarms.push(event);
+        }
+        Ok(MxsFlow::Continue)
+    }
+}
+
+// —————————————————————————————————————————————— Log ——————————————————————————————————————————————
+
+impl MxsDataProcessor<Sample> for DataSink {
+    fn name(&self) -> &str {
+        "Data log"
+    }
+
+    fn process(&mut self, sample: &mut Sample) -> AnyResult<MxsFlow> {
+        self.write(&sample.record)?;
+        for event in &sample.alarms {
+            self.write_event(event)?;
+        }
+        Ok(MxsFlow::Continue)
+    }
+
+    fn poll(&mut self) -> AnyResult<()> {
+        self.flush_if_due()
+    }
+}
+
+// ————————————————————————————————————————————— User ——————————————————————————————————————————————
+
+/// Runs a processor added through `app::run_with` in the chain of one connection
+///
+/// An error removes it from this chain only, the next connection runs it again.
+struct SharedProcessor {
+    name:      String,
+    processor: &'static UserProcessor,
+}
+
+impl SharedProcessor {
+    fn new(processor: &'static UserProcessor) -> Self {
+        let name = match processor.lock() {
+            Ok(processor) => processor.name().to_string(),
+            Err(poisoned) => poisoned.get_ref().name().to_string(),
+        };
+
+        Self { name, processor }
+    }
+
+    fn lock(&self) -> AnyResult<std::sync::MutexGuard<'_, Box<dyn MxsDataProcessor<Sample>>>> {
+        self.processor
+            .lock()
+            .map_err(|_| anyhow!("Panicked in an earlier connection"))
+    }
+}
+
+impl MxsDataProcessor<Sample> for SharedProcessor {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn process(&mut self, sample: &mut Sample) -> AnyResult<MxsFlow> {
+        self.lock()?.process(sample)
+    }
+
+    fn poll(&mut self) -> AnyResult<()> {
+        self.lock()?.poll()
+    }
+
+    fn command(&mut self, command: &str) -> AnyResult<bool> {
+        self.lock()?.command(command)
+    }
+}
+
+// ———————————————————————————————————————————— Forward ————————————————————————————————————————————
+
+/// Sends each record as a JSON datagram, `{"host_time": .., "record": "imu", ..fields}`
+///
+/// Send errors don't disable it, the datagrams may be refused while the listener is down. The
+/// first error is reported, the next one once a datagram went through again.
+struct ForwardProcessor {
+    socket:         UdpSocket,
+    addr:           SocketAddr,
+    main_thread_tx: mpsc::Sender<ThreadMsg>,
+    failing:        bool,
+}
+
+impl ForwardProcessor {
+    fn new(addr: SocketAddr, main_thread_tx: mpsc::Sender<ThreadMsg>) -> AnyResult<Self> {
+        let local: SocketAddr = if addr.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        }
+        else {
+            ([0; 16], 0).into()
+        };
+        let socket = UdpSocket::bind(local).context("Couldn't open the forward socket")?;
+
+        Ok(Self {
+            socket,
+            addr,
+            main_thread_tx,
+            failing: false,
+        })
+    }
+}
+
+impl MxsDataProcessor<Sample> for ForwardProcessor {
+    fn name(&self) -> &str {
+        "Forward"
+    }
+
+    fn process(&mut self, sample: &mut Sample) -> AnyResult<MxsFlow> {
+        let mut object = Map::new();
+        object.insert(
+            "host_time".into(),
+            Utc::now()
+                .to_rfc3339_opts(SecondsFormat::Micros, true)
+                .into(),
+        );
+        object.insert("record".into(), sample.record.name().into());
+        object.extend(sample.record.to_json());
+
+        let datagram = JsonValue::Object(object).to_string();
+        match self.socket.send_to(datagram.as_bytes(), self.addr) {
+            Ok(_) => self.failing = false,
+            Err(e) if !self.failing => {
+                self.failing = true;
+                self.main_thread_tx.send(ThreadMsg::Error(format!(
+                    "Forward: couldn't send to {}: {}",
+                    self.addr, e
+                )))?;
+            }
+            Err(_) => (),
+        }
+
+        Ok(MxsFlow::Continue)
+    }
+}
+
+// ————————————————————————————————————————————— Plot ——————————————————————————————————————————————
+
+struct PlotProcessor(mpsc::Sender<ThreadMsg>);
+
+impl MxsDataProcessor<Sample> for PlotProcessor {
+    fn name(&self) -> &str {
+        "Plot"
+    }
+
+    fn process(&mut self, sample: &mut Sample) -> AnyResult<MxsFlow> {
+        self.0.send(ThreadMsg::Plot(sample.fields.clone()))?;
+        Ok(MxsFlow::Continue)
+    }
+}
+
+// ————————————————————————————————————————————— Print —————————————————————————————————————————————
+
+/// Prints records to the console, highlighted when they fired an alarm. Quiet while plotting.
+struct PrintProcessor(mpsc::Sender<ThreadMsg>);
+
+impl MxsDataProcessor<Sample> for PrintProcessor {
+    fn name(&self) -> &str {
+        "Print"
+    }
+
+    fn process(&mut self, sample: &mut Sample) -> AnyResult<MxsFlow> {
+        if PLOT_VISIBLE.load(Ordering::Relaxed) {
+            return Ok(MxsFlow::Continue);
+        }
+
+        let msg = match sample.record.process() {
+            Ok(text) if !sample.alarms.is_empty() => {
+                ThreadMsg::Print(text.black().on_red().to_string())
+            }
+            Ok(text) => ThreadMsg::Print(text),
+            Err(e) => ThreadMsg::Error(format!("{}", e)),
+        };
+        self.0.send(msg)?;
+
+        Ok(MxsFlow::Continue)
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Tests
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::data::Battery;
+
+    fn errors(rx: &mpsc::Receiver<ThreadMsg>) -> usize {
+        rx.try_iter()
+            .filter(|msg| matches!(msg, ThreadMsg::Error(_)))
+            .count()
+    }
+
+    #[test]
+    fn forward_reports_send_errors_once() {
+        // Sending to port 0 always fails
+        let unreachable: SocketAddr = ([127, 0, 0, 1], 0).into();
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let mut forward = ForwardProcessor::new(unreachable, tx).unwrap();
+        let mut sample = Sample::new(Record::Battery(Battery::default()));
+
+        for _ in 0..3 {
+            assert_eq!(forward.process(&mut sample).unwrap(), MxsFlow::Continue);
+        }
+        assert_eq!(errors(&rx), 1);
+
+        // Recovers, and reports the next failure again
+        forward.addr = listener.local_addr().unwrap();
+        forward.process(&mut sample).unwrap();
+
+        let mut buf = [0u8; 512];
+        let len = listener.recv(&mut buf).unwrap();
+        let datagram: JsonValue = serde_json::from_slice(&buf[..len]).unwrap();
+        assert_eq!(datagram["record"], "battery");
+
+        forward.addr = unreachable;
+        forward.process(&mut sample).unwrap();
+        forward.process(&mut sample).unwrap();
+        assert_eq!(errors(&rx), 1);
+    }
+
+    #[test]
+    fn alarms_are_attached_and_reported() {
+        let rule = "battery.charge <= 10 => !stop".parse().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let mut chain = MxsProcessorChain::new();
+        chain.register(AlarmProcessor::new(vec![rule], tx));
+
+        let mut sample = Sample::new(Record::Battery(Battery::default()));
+        chain.process(&mut sample);
+
+        assert_eq!(sample.alarms.len(), 1);
+        assert_eq!(sample.alarms[0].action.as_deref(), Some("!stop"));
+        assert!(
+            matches!(rx.try_recv(), Ok(ThreadMsg::Alarm(event)) if event.field == "battery.charge")
+        );
+    }
+}
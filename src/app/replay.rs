@@ -0,0 +1,264 @@
+//! Capture replay
+//!
+//! Plays a capture file back as the serial port, so the decoder and the data processors can be
+//! debugged without the device attached. Device chunks are read back on their recorded timeline,
+//! scaled by the replay speed; everything written to the port is discarded.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result as AnyResult, bail};
+
+use crate::app::capture::{CaptureChunk, CaptureDirection, CaptureHeader, CaptureReader};
+
+/// Longest a read waits for the next chunk, like a port timeout
+const REPLAY_READ_TIMEOUT: Duration = Duration::from_millis(50);
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                             Speed
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+/// `1` is real time, `2` twice as fast, `max` as fast as possible
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    Scaled(f64),
+    Max,
+}
+
+impl Default for ReplaySpeed {
+    fn default() -> Self {
+        Self::Scaled(1.0)
+    }
+}
+
+impl FromStr for ReplaySpeed {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "max" {
+            return Ok(Self::Max);
+        }
+        match s.parse::<f64>() {
+            Ok(factor) if factor.is_finite() && factor > 0.0 => Ok(Self::Scaled(factor)),
+            _ => bail!("Invalid replay speed '{}', expected a factor like 2 or 0.5, or max", s),
+        }
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Port
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+pub struct ReplayPort<R: Read = BufReader<File>> {
+    reader:  CaptureReader<R>,
+    speed:   ReplaySpeed,
+    /// Set on the first read
+    started: Option<Instant>,
+    /// Capture time of the first device chunk, idle time before it is skipped
+    origin:  Option<Duration>,
+    chunk:   Option<CaptureChunk>,
+    /// Bytes of `chunk` already read
+    offset:  usize,
+}
+
+impl ReplayPort {
+    pub fn open(path: &Path, speed: ReplaySpeed) -> AnyResult<Self> {
+        Ok(Self::new(CaptureReader::open(path)?, speed))
+    }
+}
+
+impl<R: Read> ReplayPort<R> {
+    pub fn new(reader: CaptureReader<R>, speed: ReplaySpeed) -> Self {
+        Self {
+            reader,
+            speed,
+            started: None,
+            origin: None,
+            chunk: None,
+            offset: 0,
+        }
+    }
+
+    pub fn header(&self) -> &CaptureHeader {
+        self.reader.header()
+    }
+
+    /// Next chunk read from the device, host chunks are skipped
+    fn next_rx_chunk(&mut self) -> io::Result<Option<CaptureChunk>> {
+        loop {
+            let chunk = self
+                .reader
+                .next_chunk()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:#}", e)))?;
+
+            match chunk {
+                Some(chunk) if chunk.direction == CaptureDirection::Tx => continue,
+                chunk => return Ok(chunk),
+            }
+        }
+    }
+}
+
+/// Returns `TimedOut` while the next chunk is not due, `UnexpectedEof` at the end of the capture
+impl<R: Read> Read for ReplayPort<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.chunk.is_none() {
+            self.chunk = self.next_rx_chunk()?;
+            self.offset = 0;
+        }
+        let Some(chunk) = &self.chunk
+        else {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "End of capture"));
+        };
+
+        let origin = *self.origin.get_or_insert(chunk.time);
+        let started = *self.started.get_or_insert_with(Instant::now);
+
+        let due = match self.speed {
+            ReplaySpeed::Scaled(factor) => (chunk.time - origin).div_f64(factor),
+            ReplaySpeed::Max => Duration::ZERO,
+        };
+        let remaining = due.saturating_sub(started.elapsed());
+        if !remaining.is_zero() {
+            sleep(remaining.min(REPLAY_READ_TIMEOUT));
+            if remaining > REPLAY_READ_TIMEOUT {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+        }
+
+        let data = &chunk.data[self.offset..];
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+
+        self.offset += n;
+        if self.offset == chunk.data.len() {
+            self.chunk = None;
+        }
+        Ok(n)
+    }
+}
+
+/// Nothing is listening, writes are dropped
+impl<R: Read> Write for ReplayPort<R> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Tests
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::app::capture::{CaptureMode, CaptureWriter};
+
+    #[test]
+    fn parse_speed() {
+        let cases = [
+            ("1", Some(ReplaySpeed::Scaled(1.0))),
+            ("2", Some(ReplaySpeed::Scaled(2.0))),
+            ("0.5", Some(ReplaySpeed::Scaled(0.5))),
+            ("max", Some(ReplaySpeed::Max)),
+            ("0", None),
+            ("-1", None),
+            ("inf", None),
+            ("NaN", None),
+            ("Max", None),
+            ("", None),
+            ("2x", None),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(text.parse::<ReplaySpeed>().ok(), expected, "{}", text);
+        }
+    }
+
+    fn replay(
+        chunks: &[(CaptureDirection, &[u8], u64)],
+        speed: ReplaySpeed,
+    ) -> ReplayPort<Cursor<Vec<u8>>> {
+        let header = CaptureHeader {
+            port:          "/dev/ttyACM0".into(),
+            baud:          115_200,
+            mode:          CaptureMode::Plain,
+            host_protocol: 1,
+            started:       Default::default(),
+        };
+
+        let mut out = Vec::new();
+        let mut writer = CaptureWriter::new(&mut out, &header).unwrap();
+        for &(direction, data, ms) in chunks {
+            writer
+                .write_at(direction, data, Duration::from_millis(ms))
+                .unwrap();
+        }
+        drop(writer);
+
+        ReplayPort::new(CaptureReader::new(Cursor::new(out)).unwrap(), speed)
+    }
+
+    #[test]
+    fn reads_device_chunks() {
+        let large = [0xA5; 100];
+        let mut port = replay(
+            &[
+                (CaptureDirection::Tx, b"start", 0),
+                (CaptureDirection::Rx, &large, 1_000),
+                (CaptureDirection::Tx, b"ack", 2_000),
+                (CaptureDirection::Rx, b"end", 60_000),
+            ],
+            ReplaySpeed::Max,
+        );
+
+        // Host chunks are skipped, device chunks larger than the buffer span reads
+        let mut buf = [0u8; 64];
+        assert_eq!(port.read(&mut buf).unwrap(), 64);
+        assert_eq!(port.read(&mut buf).unwrap(), 36);
+        assert_eq!(buf[..36], large[64..]);
+        assert_eq!(port.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"end");
+
+        let error = port.read(&mut buf).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(port.write(b"ignored").unwrap(), 7);
+    }
+
+    #[test]
+    fn waits_for_scaled_chunk_times() {
+        // Idle time before the first device chunk is skipped
+        let mut port = replay(
+            &[
+                (CaptureDirection::Rx, b"a", 5_000),
+                (CaptureDirection::Rx, b"b", 5_040),
+                (CaptureDirection::Rx, b"c", 65_000),
+            ],
+            ReplaySpeed::Scaled(2.0),
+        );
+
+        let mut buf = [0u8; 8];
+        let start = Instant::now();
+        assert_eq!(port.read(&mut buf).unwrap(), 1);
+
+        // Due 20 ms later, within one read timeout
+        assert_eq!(port.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], b'b');
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        // Due in 30 s, reads time out until then
+        let error = port.read(&mut buf).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() >= Duration::from_millis(20) + REPLAY_READ_TIMEOUT);
+    }
+}
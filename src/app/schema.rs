@@ -0,0 +1,617 @@
+//! Data payload layouts
+//!
+//! A schema file describes the fields of an MXS Data payload, so the host can decode it without
+//! being recompiled for every firmware. Fields are packed in order, without padding.
+//!
+//! ```toml
+//! endian = "little"      # default byte order: "little" (default) or "big"
+//!
+//! [[fields]]
+//! name = "temperature"
+//! type = "f32"           # u8..u64, i8..i64, f32, f64, bool, str
+//!
+//! [[fields]]
+//! name   = "label"
+//! type   = "str"
+//! len    = 8             # fixed length, required for str
+//! endian = "big"         # per field override
+//!
+//! [[fields]]
+//! name   = "supply"
+//! type   = "i16"
+//! scale  = 0.125         # linear calibration: raw * scale + offset
+//! offset = 0.0
+//! unit   = "mV"          # shown next to the value
+//!
+//! [[fields]]
+//! name = "probe"
+//! type = "u16"
+//! poly = [-40.0, 0.01, 1e-6]   # polynomial calibration: c0 + c1 * raw + c2 * raw^2 ..
+//! unit = "degC"
+//! ```
+//!
+//! Calibrated fields are decoded as floats in engineering units, which is what the logs, plots and
+//! alarms see.
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Context, Result as AnyResult, bail};
+use serde::Deserialize;
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                             Schema
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldType {
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+    Bool,
+    /// Fixed length text, padded with zeros
+    Str(usize),
+}
+
+impl FieldType {
+    /// Bytes taken in the payload
+    pub const fn size(self) -> usize {
+        match self {
+            Self::U8 | Self::I8 | Self::Bool => 1,
+            Self::U16 | Self::I16 => 2,
+            Self::U32 | Self::I32 | Self::F32 => 4,
+            Self::U64 | Self::I64 | Self::F64 => 8,
+            Self::Str(len) => len,
+        }
+    }
+}
+
+impl FromStr for FieldType {
+    type Err = anyhow::Error;
+
+    /// Parse a scalar type name. Strings need a length and are built by the schema loader.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ty = match s {
+            "u8" => Self::U8,
+            "u16" => Self::U16,
+            "u32" => Self::U32,
+            "u64" => Self::U64,
+            "i8" => Self::I8,
+            "i16" => Self::I16,
+            "i32" => Self::I32,
+            "i64" => Self::I64,
+            "f32" => Self::F32,
+            "f64" => Self::F64,
+            "bool" => Self::Bool,
+            _ => bail!("Unknown field type '{}'", s),
+        };
+        Ok(ty)
+    }
+}
+
+/// Conversion from raw counts to engineering units
+#[derive(Debug, Clone, PartialEq)]
+pub enum Calibration {
+    Linear {
+        scale:  f64,
+        offset: f64,
+    },
+    /// Coefficients from the constant term up
+    Polynomial(Vec<f64>),
+}
+
+impl Calibration {
+    /// Full precision, `Value` rounds calibrated values only for display
+    pub fn apply(&self, raw: f64) -> f64 {
+        match self {
+            Self::Linear { scale, offset } => raw * scale + offset,
+            Self::Polynomial(coefficients) => {
+                coefficients.iter().rev().fold(0.0, |acc, c| acc * raw + c)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name:        String,
+    pub ty:          FieldType,
+    pub endian:      Endian,
+    pub calibration: Option<Calibration>,
+    pub unit:        Option<String>,
+}
+
+/// Layout of a Data payload
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataSchema {
+    fields: Vec<Field>,
+    size:   usize,
+}
+
+impl Default for DataSchema {
+    /// The original fixed layout: three little-endian i16
+    fn default() -> Self {
+        let fields = (0..3)
+            .map(|i| Field {
+                name:        format!("field{}", i),
+                ty:          FieldType::I16,
+                endian:      Endian::Little,
+                calibration: None,
+                unit:        None,
+            })
+            .collect();
+
+        Self::new(fields).unwrap()
+    }
+}
+
+impl DataSchema {
+    pub fn new(fields: Vec<Field>) -> AnyResult<Self> {
+        if fields.is_empty() {
+            bail!("Schema has no fields");
+        }
+
+        for (i, field) in fields.iter().enumerate() {
+            if field.name.is_empty() {
+                bail!("Field {} has no name", i);
+            }
+            if fields[..i].iter().any(|f| f.name == field.name) {
+                bail!("Field '{}' is declared twice", field.name);
+            }
+            if field.ty == FieldType::Str(0) {
+                bail!("Field '{}' has an empty string type", field.name);
+            }
+            if field.calibration.is_some()
+                && matches!(field.ty, FieldType::Str(_) | FieldType::Bool)
+            {
+                bail!("Field '{}': only numbers can be calibrated", field.name);
+            }
+            if let Some(Calibration::Polynomial(coefficients)) = &field.calibration
+                && coefficients.is_empty()
+            {
+                bail!("Field '{}' has an empty poly", field.name);
+            }
+        }
+
+        let size = fields.iter().map(|f| f.ty.size()).sum();
+
+        Ok(Self { fields, size })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> AnyResult<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read schema {}", path.display()))?;
+
+        Self::from_toml(&text).with_context(|| format!("Invalid schema {}", path.display()))
+    }
+
+    pub fn from_toml(text: &str) -> AnyResult<Self> {
+        let file: SchemaFile = toml::from_str(text)?;
+
+        let fields = file
+            .fields
+            .into_iter()
+            .map(|def| {
+                let ty = match (def.ty.as_str(), def.len) {
+                    ("str", Some(len)) => FieldType::Str(len),
+                    ("str", None) => bail!("Field '{}': str needs a len", def.name),
+                    (ty, None) => ty
+                        .parse()
+                        .with_context(|| format!("Field '{}'", def.name))?,
+                    (_, Some(_)) => bail!("Field '{}': len is only valid for str", def.name),
+                };
+
+                let calibration = match (def.scale, def.offset, def.poly) {
+                    (None, None, None) => None,
+                    (scale, offset, None) => Some(Calibration::Linear {
+                        scale:  scale.unwrap_or(1.0),
+                        offset: offset.unwrap_or(0.0),
+                    }),
+                    (None, None, Some(poly)) => Some(Calibration::Polynomial(poly)),
+                    _ => bail!("Field '{}': use either scale/offset or poly", def.name),
+                };
+
+                Ok(Field {
+                    name: def.name,
+                    ty,
+                    endian: def.endian.unwrap_or(file.endian),
+                    calibration,
+                    unit: def.unit,
+                })
+            })
+            .collect::<AnyResult<Vec<_>>>()?;
+
+        Self::new(fields)
+    }
+
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    /// Unit label of a field
+    pub fn unit(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|f| f.name == name)
+            .and_then(|f| f.unit.as_deref())
+    }
+
+    /// Payload size in bytes
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Decode a payload into one value per field
+    pub fn decode(&self, buf: &[u8]) -> Result<Vec<Value>, SchemaError> {
+        if buf.len() != self.size {
+            return Err(SchemaError {
+                expected: self.size,
+                received: buf.len(),
+            });
+        }
+
+        let mut pos = 0;
+        let values = self
+            .fields
+            .iter()
+            .map(|field| {
+                let bytes = &buf[pos..pos + field.ty.size()];
+                pos += bytes.len();
+                Value::decode(field, bytes)
+            })
+            .collect();
+
+        Ok(values)
+    }
+}
+
+/// Schema file contents, before validation
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SchemaFile {
+    #[serde(default)]
+    endian: Endian,
+    fields: Vec<FieldDef>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FieldDef {
+    name:   String,
+    #[serde(rename = "type")]
+    ty:     String,
+    len:    Option<usize>,
+    endian: Option<Endian>,
+    scale:  Option<f64>,
+    offset: Option<f64>,
+    poly:   Option<Vec<f64>>,
+    unit:   Option<String>,
+}
+
+/// Payload size does not match the schema
+#[derive(Debug, PartialEq, Eq)]
+pub struct SchemaError {
+    pub expected: usize,
+    pub received: usize,
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Payload is {} bytes, the schema expects {}", self.received, self.expected)
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                             Values
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+}
+
+impl Value {
+    /// Significant digits of displayed floats, hides the float noise of the calibration
+    const DISPLAY_DIGITS: usize = 9;
+
+    fn decode(field: &Field, bytes: &[u8]) -> Self {
+        let value = Self::decode_raw(field, bytes);
+
+        match (&field.calibration, value.as_f64()) {
+            (Some(calibration), Some(raw)) => Self::Float(calibration.apply(raw)),
+            _ => value,
+        }
+    }
+
+    fn decode_raw(field: &Field, bytes: &[u8]) -> Self {
+        if let FieldType::Str(_) = field.ty {
+            let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+            return Self::Str(String::from_utf8_lossy(&bytes[..end]).into_owned());
+        }
+
+        // ---- Scalars: assemble the raw bits, most significant byte first
+        let raw = match field.endian {
+            Endian::Little => bytes.iter().rev().fold(0u64, |acc, &b| acc << 8 | b as u64),
+            Endian::Big => bytes.iter().fold(0u64, |acc, &b| acc << 8 | b as u64),
+        };
+
+        // Sign extend from the field width
+        let shift = 64 - bytes.len() as u32 * 8;
+        let signed = ((raw << shift) as i64) >> shift;
+
+        match field.ty {
+            FieldType::U8 | FieldType::U16 | FieldType::U32 | FieldType::U64 => Self::Unsigned(raw),
+            FieldType::I8 | FieldType::I16 | FieldType::I32 | FieldType::I64 => {
+                Self::Signed(signed)
+            }
+            FieldType::F32 => Self::Float(f32::from_bits(raw as u32) as f64),
+            FieldType::F64 => Self::Float(f64::from_bits(raw)),
+            FieldType::Bool => Self::Bool(raw != 0),
+            FieldType::Str(_) => unreachable!(),
+        }
+    }
+
+    /// Non finite floats have no JSON representation and become null
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Self::Unsigned(v) => (*v).into(),
+            Self::Signed(v) => (*v).into(),
+            Self::Float(v) => (*v).into(),
+            Self::Bool(v) => (*v).into(),
+            Self::Str(v) => v.as_str().into(),
+        }
+    }
+
+    /// Numeric view of the value, strings have none
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Self::Unsigned(v) => Some(v as f64),
+            Self::Signed(v) => Some(v as f64),
+            Self::Float(v) => Some(v),
+            Self::Bool(v) => Some(v as u8 as f64),
+            Self::Str(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsigned(v) => write!(f, "{}", v),
+            Self::Signed(v) => write!(f, "{}", v),
+            Self::Float(v) if v.is_finite() => {
+                // Round through the exponent form, then print the shortest form of the result
+                let rounded = format!("{:.*e}", Self::DISPLAY_DIGITS - 1, v);
+                write!(f, "{}", rounded.parse::<f64>().unwrap_or(*v))
+            }
+            Self::Float(v) => write!(f, "{}", v),
+            Self::Bool(v) => write!(f, "{}", v),
+            Self::Str(v) => write!(f, "{:?}", v),
+        }
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Tests
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(ty: FieldType, endian: Endian) -> Field {
+        Field {
+            name: "x".into(),
+            ty,
+            endian,
+            calibration: None,
+            unit: None,
+        }
+    }
+
+    fn decode(ty: FieldType, endian: Endian, bytes: &[u8]) -> Value {
+        let schema = DataSchema::new(vec![field(ty, endian)]).unwrap();
+        schema.decode(bytes).unwrap().remove(0)
+    }
+
+    #[test]
+    fn signed_fields_sign_extend_from_their_width() {
+        use Endian::*;
+
+        assert_eq!(decode(FieldType::I8, Little, &[0xFF]), Value::Signed(-1));
+        assert_eq!(decode(FieldType::I8, Little, &[0x80]), Value::Signed(-128));
+        assert_eq!(decode(FieldType::I8, Little, &[0x7F]), Value::Signed(127));
+        assert_eq!(decode(FieldType::I16, Little, &[0x00, 0x80]), Value::Signed(-32768));
+        assert_eq!(decode(FieldType::I16, Big, &[0xFF, 0xFE]), Value::Signed(-2));
+        assert_eq!(decode(FieldType::I32, Little, &[0xFE, 0xFF, 0xFF, 0xFF]), Value::Signed(-2));
+        assert_eq!(
+            decode(FieldType::I32, Big, &[0x7F, 0xFF, 0xFF, 0xFF]),
+            Value::Signed(i32::MAX as i64)
+        );
+        assert_eq!(decode(FieldType::I64, Big, &i64::MIN.to_be_bytes()), Value::Signed(i64::MIN));
+    }
+
+    #[test]
+    fn unsigned_fields_keep_the_top_bit() {
+        use Endian::*;
+
+        assert_eq!(decode(FieldType::U8, Little, &[0xFF]), Value::Unsigned(255));
+        assert_eq!(decode(FieldType::U16, Little, &[0x00, 0x80]), Value::Unsigned(0x8000));
+        assert_eq!(
+            decode(FieldType::U32, Big, &[0xFF, 0xFF, 0xFF, 0xFE]),
+            Value::Unsigned(0xFFFF_FFFE)
+        );
+        assert_eq!(decode(FieldType::U64, Little, &[0xFF; 8]), Value::Unsigned(u64::MAX));
+    }
+
+    #[test]
+    fn byte_order() {
+        use Endian::*;
+
+        assert_eq!(decode(FieldType::U16, Little, &[0x34, 0x12]), Value::Unsigned(0x1234));
+        assert_eq!(decode(FieldType::U16, Big, &[0x12, 0x34]), Value::Unsigned(0x1234));
+        assert_eq!(decode(FieldType::U32, Little, &[1, 2, 3, 4]), Value::Unsigned(0x0403_0201));
+        assert_eq!(decode(FieldType::U32, Big, &[1, 2, 3, 4]), Value::Unsigned(0x0102_0304));
+        assert_eq!(decode(FieldType::F32, Little, &1.5f32.to_le_bytes()), Value::Float(1.5));
+        assert_eq!(decode(FieldType::F32, Big, &(-0.25f32).to_be_bytes()), Value::Float(-0.25));
+        assert_eq!(decode(FieldType::F64, Big, &1e300f64.to_be_bytes()), Value::Float(1e300));
+    }
+
+    #[test]
+    fn per_field_endian_override() {
+        let schema = DataSchema::from_toml(
+            r#"
+            endian = "big"
+
+            [[fields]]
+            name = "a"
+            type = "u16"
+
+            [[fields]]
+            name   = "b"
+            type   = "u16"
+            endian = "little"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(schema.decode(&[0x12, 0x34, 0x12, 0x34]).unwrap(), [
+            Value::Unsigned(0x1234),
+            Value::Unsigned(0x3412)
+        ]);
+    }
+
+    #[test]
+    fn strings_and_bools() {
+        let schema = DataSchema::from_toml(
+            r#"
+            [[fields]]
+            name = "label"
+            type = "str"
+            len  = 4
+
+            [[fields]]
+            name = "flag"
+            type = "bool"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(schema.size(), 5);
+        assert_eq!(schema.decode(b"ab\0\0\x02").unwrap(), [
+            Value::Str("ab".into()),
+            Value::Bool(true)
+        ]);
+        assert_eq!(schema.decode(b"abcd\0").unwrap(), [
+            Value::Str("abcd".into()),
+            Value::Bool(false)
+        ]);
+    }
+
+    #[test]
+    fn payload_size_must_match() {
+        let schema = DataSchema::default();
+
+        assert_eq!(schema.size(), 6);
+        assert_eq!(schema.decode(&[0; 5]), Err(SchemaError { expected: 6, received: 5 }));
+        assert!(schema.decode(&[0; 7]).is_err());
+    }
+
+    #[test]
+    fn linear_calibration() {
+        let calibration = Calibration::Linear {
+            scale:  0.125,
+            offset: -10.0,
+        };
+
+        assert_eq!(calibration.apply(0.0), -10.0);
+        assert_eq!(calibration.apply(80.0), 0.0);
+        assert_eq!(calibration.apply(-8.0), -11.0);
+    }
+
+    #[test]
+    fn polynomial_calibration() {
+        // 2 - 3x + 0.5x^2
+        let calibration = Calibration::Polynomial(vec![2.0, -3.0, 0.5]);
+
+        assert_eq!(calibration.apply(0.0), 2.0);
+        assert_eq!(calibration.apply(2.0), -2.0);
+        assert_eq!(calibration.apply(-4.0), 22.0);
+
+        // A constant ignores the raw value
+        assert_eq!(Calibration::Polynomial(vec![7.0]).apply(123.0), 7.0);
+    }
+
+    #[test]
+    fn calibrated_fields_decode_to_floats() {
+        let schema = DataSchema::from_toml(
+            r#"
+            [[fields]]
+            name  = "supply"
+            type  = "i16"
+            scale = 0.5
+            unit  = "mV"
+
+            [[fields]]
+            name = "probe"
+            type = "u8"
+            poly = [-40.0, 0.0, 1.0]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(schema.decode(&[0xFC, 0xFF, 10]).unwrap(), [
+            Value::Float(-2.0),
+            Value::Float(60.0)
+        ]);
+        assert_eq!(schema.unit("supply"), Some("mV"));
+        assert_eq!(schema.unit("probe"), None);
+    }
+
+    #[test]
+    fn invalid_schemas() {
+        let field =
+            |def: &str| DataSchema::from_toml(&format!("[[fields]]\nname = \"x\"\n{}", def));
+
+        assert!(field("type = \"u24\"").is_err());
+        assert!(field("type = \"str\"").is_err());
+        assert!(field("type = \"str\"\nlen = 0").is_err());
+        assert!(field("type = \"u8\"\nlen = 2").is_err());
+        assert!(field("type = \"bool\"\nscale = 2.0").is_err());
+        assert!(field("type = \"u8\"\npoly = []").is_err());
+        assert!(field("type = \"u8\"\nscale = 2.0\npoly = [1.0]").is_err());
+        assert!(field("type = \"u8\"\nsize = 2").is_err());
+        assert!(DataSchema::from_toml("fields = []").is_err());
+    }
+
+    #[test]
+    fn display_rounds_calibration_noise() {
+        assert_eq!(Value::Float(0.1 + 0.2).to_string(), "0.3");
+        assert_eq!(Value::Float(-1234.5).to_string(), "-1234.5");
+        assert_eq!(Value::Float(f64::NAN).to_string(), "NaN");
+        assert_eq!(Value::Str("a".into()).to_string(), "\"a\"");
+    }
+}
@@ -0,0 +1,495 @@
+//! Data logging
+//!
+//! Writes every decoded record to disk with a host timestamp, for analysis after a test run.
+//!
+//! JSON Lines - one file, one object per record: `{"host_time": .., "record": "imu", ..fields}`
+//! CSV        - one file per record kind, with a header row. Array fields get one column per item.
+//!
+//! Alarm events are logged the same way, as `{"host_time": .., "event": "alarm", ..}` lines or in
+//! their own CSV file.
+//!
+//! Files are named `<stem>-<session start>[-<record>]-<index>.<ext>` and a new index is started
+//! when the rotation size or age is reached. Existing files are never overwritten, see
+//! `create_session_file`.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result as AnyResult, bail};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{Map, Value as JsonValue};
+
+use crate::app::alarm::AlarmEvent;
+use crate::app::data::Record;
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                             Config
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SinkFormat {
+    Csv,
+    JsonLines,
+}
+
+impl SinkFormat {
+    /// Pick the format from the file extension
+    pub fn from_path(path: &Path) -> AnyResult<Self> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("csv") => Ok(Self::Csv),
+            Some("jsonl" | "json") => Ok(Self::JsonLines),
+            _ => bail!("Log file {} must end in .csv or .jsonl", path.display()),
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::JsonLines => "jsonl",
+        }
+    }
+}
+
+/// When to start a new file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SinkRotation {
+    Size(u64),
+    Time(Duration),
+}
+
+impl std::str::FromStr for SinkRotation {
+    type Err = anyhow::Error;
+
+    /// Size with a `B`, `KB`, `MB` or `GB` suffix, or age with `s`, `min` or `h`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (value, unit) = s.split_at(split);
+
+        let value: u64 = value
+            .parse()
+            .with_context(|| format!("Invalid rotation '{}'", s))?;
+        if value == 0 {
+            bail!("Invalid rotation '{}'", s);
+        }
+
+        let seconds = |secs| Self::Time(Duration::from_secs(secs));
+        let (multiplier, rotation): (u64, fn(u64) -> Self) = match unit {
+            "B" => (1, Self::Size),
+            "KB" => (1 << 10, Self::Size),
+            "MB" => (1 << 20, Self::Size),
+            "GB" => (1 << 30, Self::Size),
+            "s" => (1, seconds),
+            "min" => (60, seconds),
+            "h" => (3600, seconds),
+            _ => bail!("Invalid rotation '{}', expected e.g. 10MB or 15min", s),
+        };
+
+        match value.checked_mul(multiplier) {
+            Some(value) => Ok(rotation(value)),
+            None => bail!("Invalid rotation '{}'", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SinkConfig {
+    pub path:     PathBuf,
+    pub format:   SinkFormat,
+    pub rotation: Option<SinkRotation>,
+}
+
+impl SinkConfig {
+    pub fn new(path: impl Into<PathBuf>, rotation: Option<SinkRotation>) -> AnyResult<Self> {
+        let path = path.into();
+        let format = SinkFormat::from_path(&path)?;
+
+        Ok(Self { path, format, rotation })
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                            Sessions
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+/// Name shared by the files of one session, the start time with a `-2`, `-3`.. suffix when files
+/// of an earlier session started in the same second exist
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionName {
+    started:   String,
+    /// Picked by the first file of the session
+    duplicate: Option<u32>,
+}
+
+impl SessionName {
+    pub fn new(started: DateTime<Utc>) -> Self {
+        Self {
+            started:   started.format("%Y%m%dT%H%M%SZ").to_string(),
+            duplicate: None,
+        }
+    }
+
+    fn with_duplicate(&self, duplicate: u32) -> String {
+        match duplicate {
+            1 => self.started.clone(),
+            n => format!("{}-{}", self.started, n),
+        }
+    }
+}
+
+impl fmt::Display for SessionName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.with_duplicate(self.duplicate.unwrap_or(1)))
+    }
+}
+
+/// Create `<stem>-<session><suffix>.<ext>` next to `path`, never overwriting a file
+///
+/// The first file of a session picks its suffix, skipping the session names that files next to
+/// `path` already use. Later files keep that suffix and fail if their name is taken, so the files
+/// of one session always share a name.
+pub fn create_session_file(
+    path: &Path,
+    session: &mut SessionName,
+    suffix: &str,
+) -> AnyResult<(File, PathBuf)> {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy())
+        .unwrap_or_default();
+    let first = session.duplicate.is_none();
+    let mut duplicate = session.duplicate.unwrap_or(1);
+
+    loop {
+        let prefix = format!("{}-{}", stem, session.with_duplicate(duplicate));
+        if first && session_taken(path, &prefix) {
+            duplicate += 1;
+            continue;
+        }
+
+        let mut name = format!("{}{}", prefix, suffix);
+        if let Some(ext) = path.extension() {
+            name.push('.');
+            name.push_str(&ext.to_string_lossy());
+        }
+        let file_path = path.with_file_name(name);
+
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&file_path)
+        {
+            Ok(file) => {
+                session.duplicate = Some(duplicate);
+                return Ok((file, file_path));
+            }
+            // Created since the check
+            Err(e) if first && e.kind() == io::ErrorKind::AlreadyExists => duplicate += 1,
+            Err(e) => {
+                return Err(e).with_context(|| format!("Couldn't create {}", file_path.display()));
+            }
+        }
+    }
+}
+
+/// Whether a file next to `path` is named `<prefix>` followed by a suffix or extension
+fn session_taken(path: &Path, prefix: &str) -> bool {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let Ok(entries) = fs::read_dir(dir)
+    else {
+        return false;
+    };
+
+    entries.flatten().any(|entry| {
+        let name = entry.file_name();
+        name.to_string_lossy()
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(['-', '.']))
+    })
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Sink
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+/// Time between flushes of the buffered files
+pub const SINK_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+struct SinkFile {
+    /// Record kind for CSV files, empty for JSON Lines
+    kind:    &'static str,
+    index:   u32,
+    writer:  BufWriter<File>,
+    bytes:   u64,
+    opened:  Instant,
+    columns: Vec<String>,
+}
+
+pub struct DataSink {
+    config:     SinkConfig,
+    session:    SessionName,
+    files:      Vec<SinkFile>,
+    last_flush: Instant,
+}
+
+impl DataSink {
+    pub fn new(config: SinkConfig) -> Self {
+        Self {
+            config,
+            session: SessionName::new(Utc::now()),
+            files: Vec::new(),
+            last_flush: Instant::now(),
+        }
+    }
+
+    pub fn write(&mut self, record: &Record) -> AnyResult<()> {
+        self.write_entry("record", record.name(), record.to_json(), |field| record.unit(field))
+    }
+
+    pub fn write_event(&mut self, event: &AlarmEvent) -> AnyResult<()> {
+        let mut fields = Map::new();
+        fields.insert("rule".into(), event.rule.clone().into());
+        fields.insert("field".into(), event.field.clone().into());
+        fields.insert("value".into(), event.value.into());
+        fields.insert("action".into(), event.action.clone().into());
+
+        self.write_entry("event", "alarm", fields, |_| None)
+    }
+
+    /// Write one line tagged with `key: name`, CSV files are split by `name`
+    ///
+    /// CSV headers show the field units as `name [unit]`.
+    fn write_entry(
+        &mut self,
+        key: &str,
+        name: &'static str,
+        fields: Map<String, JsonValue>,
+        units: impl Fn(&str) -> Option<&'static str>,
+    ) -> AnyResult<()> {
+        let host_time = Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true);
+
+        let kind = match self.config.format {
+            SinkFormat::Csv => name,
+            SinkFormat::JsonLines => "",
+        };
+
+        let pos = match self.files.iter().position(|f| f.kind == kind) {
+            Some(pos) => pos,
+            None => {
+                let file = self.open(kind, 0)?;
+                self.files.push(file);
+                self.files.len() - 1
+            }
+        };
+
+        // ---- Rotate
+        let rotate = match self.config.rotation {
+            Some(SinkRotation::Size(max)) => self.files[pos].bytes >= max,
+            Some(SinkRotation::Time(max)) => self.files[pos].opened.elapsed() >= max,
+            None => false,
+        };
+        if rotate {
+            self.files[pos].writer.flush()?;
+            let index = self.files[pos].index + 1;
+            self.files[pos] = self.open(kind, index)?;
+        }
+
+        // ---- Format
+        let file = &mut self.files[pos];
+        let mut line = String::new();
+
+        match self.config.format {
+            SinkFormat::JsonLines => {
+                let mut object = Map::new();
+                object.insert("host_time".into(), host_time.into());
+                object.insert(key.into(), name.into());
+                object.extend(fields);
+
+                line.push_str(&JsonValue::Object(object).to_string());
+            }
+            SinkFormat::Csv => {
+                let mut row = vec![("host_time".to_string(), host_time)];
+                for (name, value) in fields {
+                    let column = match units(&name) {
+                        Some(unit) => format!("{} [{}]", name, unit),
+                        None => name,
+                    };
+                    flatten_csv(&mut row, column, value);
+                }
+
+                // Header row at the start of each file
+                if file.columns.is_empty() {
+                    file.columns = row.iter().map(|(name, _)| name.clone()).collect();
+                    let header: Vec<_> = file.columns.iter().map(|c| csv_escape(c)).collect();
+                    line.push_str(&header.join(","));
+                    line.push('\n');
+                }
+
+                let values: Vec<_> = row.iter().map(|(_, v)| csv_escape(v)).collect();
+                line.push_str(&values.join(","));
+            }
+        }
+        line.push('\n');
+
+        file.writer.write_all(line.as_bytes())?;
+        file.bytes += line.len() as u64;
+
+        self.flush_if_due()
+    }
+
+    /// Flush when `SINK_FLUSH_INTERVAL` has passed since the last flush
+    pub fn flush_if_due(&mut self) -> AnyResult<()> {
+        if self.last_flush.elapsed() >= SINK_FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> AnyResult<()> {
+        for file in &mut self.files {
+            file.writer.flush()?;
+        }
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+
+    fn open(&mut self, kind: &'static str, index: u32) -> AnyResult<SinkFile> {
+        let suffix = match kind {
+            "" => format!("-{:03}", index),
+            kind => format!("-{}-{:03}", kind, index),
+        };
+        let path = self
+            .config
+            .path
+            .with_extension(self.config.format.extension());
+        let (file, _) = create_session_file(&path, &mut self.session, &suffix)
+            .context("Couldn't create log file")?;
+
+        Ok(SinkFile {
+            kind,
+            index,
+            writer: BufWriter::new(file),
+            bytes: 0,
+            opened: Instant::now(),
+            columns: Vec::new(),
+        })
+    }
+}
+
+impl Drop for DataSink {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// Split arrays into one column per item: `accel.0`, `accel.1`, ..
+fn flatten_csv(row: &mut Vec<(String, String)>, name: String, value: JsonValue) {
+    match value {
+        JsonValue::Array(items) => {
+            for (i, item) in items.into_iter().enumerate() {
+                flatten_csv(row, format!("{}.{}", name, i), item);
+            }
+        }
+        JsonValue::String(s) => row.push((name, s)),
+        JsonValue::Null => row.push((name, String::new())),
+        value => row.push((name, value.to_string())),
+    }
+}
+
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    }
+    else {
+        value.to_string()
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Tests
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rotation() {
+        let cases = [
+            ("512B", Some(SinkRotation::Size(512))),
+            ("10KB", Some(SinkRotation::Size(10 << 10))),
+            ("10MB", Some(SinkRotation::Size(10 << 20))),
+            ("2GB", Some(SinkRotation::Size(2 << 30))),
+            ("30s", Some(SinkRotation::Time(Duration::from_secs(30)))),
+            ("15min", Some(SinkRotation::Time(Duration::from_secs(900)))),
+            ("2h", Some(SinkRotation::Time(Duration::from_secs(7200)))),
+            ("0MB", None),
+            ("10", None),
+            ("10mb", None),
+            ("MB", None),
+            ("-1MB", None),
+            // Overflow of the unit multiplier
+            ("17179869184GB", None),
+            ("307445734561825861min", None),
+            ("18446744073709551615h", None),
+            ("18446744073709551616B", None),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(text.parse::<SinkRotation>().ok(), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn session_files_are_never_overwritten() {
+        /// Removed even when the test fails
+        struct TempDir(PathBuf);
+
+        impl Drop for TempDir {
+            fn drop(&mut self) {
+                let _ = fs::remove_dir_all(&self.0);
+            }
+        }
+
+        let dir = TempDir(std::env::temp_dir().join(format!("mxs-session-{}", std::process::id())));
+        fs::create_dir_all(&dir.0).unwrap();
+        let path = dir.0.join("log.csv");
+        let started = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+
+        let create = |session: &mut SessionName, suffix: &str| {
+            create_session_file(&path, session, suffix)
+                .map(|(_, path)| path.file_name().unwrap().to_string_lossy().into_owned())
+        };
+
+        let mut first = SessionName::new(started);
+        assert_eq!(create(&mut first, "-imu-000").unwrap(), "log-20231114T221320Z-imu-000.csv");
+        assert_eq!(first.to_string(), "20231114T221320Z");
+
+        // Sessions started in the same second, e.g. by reconnects, get the next free suffix even
+        // when their first file has another name
+        let mut second = SessionName::new(started);
+        assert_eq!(
+            create(&mut second, "-battery-000").unwrap(),
+            "log-20231114T221320Z-2-battery-000.csv"
+        );
+        let mut third = SessionName::new(started);
+        assert_eq!(create(&mut third, "-imu-000").unwrap(), "log-20231114T221320Z-3-imu-000.csv");
+
+        // Later files keep the suffix of the session
+        assert_eq!(create(&mut second, "-imu-000").unwrap(), "log-20231114T221320Z-2-imu-000.csv");
+        assert_eq!(second.to_string(), "20231114T221320Z-2");
+
+        // And fail rather than overwrite or move to another name
+        fs::write(dir.0.join("log-20231114T221320Z-2-motor-000.csv"), "kept").unwrap();
+        assert!(create(&mut second, "-motor-000").is_err());
+        assert_eq!(
+            fs::read_to_string(dir.0.join("log-20231114T221320Z-2-motor-000.csv")).unwrap(),
+            "kept"
+        );
+    }
+}
@@ -0,0 +1,224 @@
+//! Running statistics of numeric record fields
+//!
+//! Collected in the data thread and shown in the terminal panel, so sensor noise can be checked on
+//! the bench without exporting a log.
+
+use std::time::Instant;
+
+use crossterm::style::Stylize;
+
+use crate::app::plot::format_value;
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                           Field Stats
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+/// Fields beyond this are not tracked
+pub const MAX_STATS_FIELDS: usize = 16;
+
+const NAME_WIDTH: usize = 16;
+const COLUMN_WIDTH: usize = 11;
+
+/// Min, max, mean and variance (Welford) of one field since the last reset
+#[derive(Debug, Clone)]
+pub struct FieldStats {
+    pub name:  String,
+    pub count: u64,
+    pub min:   f64,
+    pub max:   f64,
+    mean:      f64,
+    m2:        f64,
+    first:     Instant,
+    last:      Instant,
+}
+
+impl FieldStats {
+    fn new(name: String, now: Instant) -> Self {
+        Self {
+            name,
+            count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            mean: 0.0,
+            m2: 0.0,
+            first: now,
+            last: now,
+        }
+    }
+
+    fn push(&mut self, value: f64, now: Instant) {
+        if self.count == 0 {
+            self.first = now;
+        }
+        self.count += 1;
+        self.last = now;
+
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Sample standard deviation, 0 until there are two samples
+    pub fn stddev(&self) -> f64 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        (self.m2 / (self.count - 1) as f64).sqrt()
+    }
+
+    /// Samples per second between the first and the last sample
+    pub fn rate(&self) -> f64 {
+        let elapsed = (self.last - self.first).as_secs_f64();
+        if self.count < 2 || elapsed <= 0.0 {
+            return 0.0;
+        }
+        (self.count - 1) as f64 / elapsed
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                           Data Stats
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[derive(Debug, Clone, Default)]
+pub struct DataStats {
+    fields: Vec<FieldStats>,
+}
+
+impl DataStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add one sample per field, new fields are tracked while there is room
+    pub fn push(&mut self, fields: &[(String, f64)]) {
+        let now = Instant::now();
+
+        for (name, value) in fields {
+            let pos = match self.fields.iter().position(|f| &f.name == name) {
+                Some(pos) => pos,
+                None if self.fields.len() < MAX_STATS_FIELDS => {
+                    self.fields.push(FieldStats::new(name.clone(), now));
+                    self.fields.len() - 1
+                }
+                None => continue,
+            };
+            self.fields[pos].push(*value, now);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.fields.clear();
+    }
+
+    /// Terminal lines taken by `render`
+    pub fn height(&self) -> u16 {
+        (1 + self.fields.len().max(1)) as u16
+    }
+
+    /// Title line followed by one line per field
+    pub fn render(&self) -> Vec<String> {
+        let mut lines = Vec::with_capacity(self.height() as usize);
+
+        let mut title = format!("{:<nw$}", "Stats", nw = NAME_WIDTH);
+        for column in ["count", "min", "max", "mean", "stddev", "rate Hz"] {
+            title.push_str(&format!(" {:>cw$}", column, cw = COLUMN_WIDTH));
+        }
+        lines.push(format!("{} {}", title.bold(), " F5 hide  :reset".dark_grey()));
+
+        if self.fields.is_empty() {
+            lines.push("Waiting for data ...".dark_grey().to_string());
+            return lines;
+        }
+
+        for field in &self.fields {
+            let mut line = format!("{:<nw$.nw$}", field.name, nw = NAME_WIDTH);
+            line.push_str(&format!(" {:>cw$}", field.count, cw = COLUMN_WIDTH));
+            for value in [
+                field.min,
+                field.max,
+                field.mean(),
+                field.stddev(),
+                field.rate(),
+            ] {
+                line.push_str(&format!(" {:>cw$}", format_value(value), cw = COLUMN_WIDTH));
+            }
+            lines.push(line);
+        }
+
+        lines
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Tests
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn field_stats() {
+        let start = Instant::now();
+        let mut stats = FieldStats::new("x".into(), start);
+        assert_eq!((stats.stddev(), stats.rate()), (0.0, 0.0));
+
+        stats.push(2.0, start + Duration::from_secs(1));
+        assert_eq!((stats.mean(), stats.stddev(), stats.rate()), (2.0, 0.0, 0.0));
+
+        // The rate counts from the first sample, not from creation
+        let samples = [4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        for (i, value) in samples.into_iter().enumerate() {
+            stats.push(value, start + Duration::from_millis(1250 + 250 * i as u64));
+        }
+
+        assert_eq!(stats.count, 8);
+        assert_eq!((stats.min, stats.max), (2.0, 9.0));
+        assert!((stats.mean() - 5.0).abs() < 1e-12);
+        assert!((stats.stddev() - (32.0f64 / 7.0).sqrt()).abs() < 1e-12);
+        assert!((stats.rate() - 4.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn rate_needs_elapsed_time() {
+        let now = Instant::now();
+        let mut stats = FieldStats::new("x".into(), now);
+        stats.push(1.0, now);
+        stats.push(2.0, now);
+        assert_eq!(stats.rate(), 0.0);
+    }
+
+    #[test]
+    fn data_stats_cap_and_reset() {
+        let fields: Vec<(String, f64)> = (0..MAX_STATS_FIELDS + 3)
+            .map(|i| (format!("field{}", i), i as f64))
+            .collect();
+
+        let mut stats = DataStats::new();
+        stats.push(&fields);
+        stats.push(&fields[MAX_STATS_FIELDS - 1..]);
+
+        assert_eq!(stats.fields.len(), MAX_STATS_FIELDS);
+        assert_eq!(stats.fields[0].count, 1);
+        assert_eq!(stats.fields[MAX_STATS_FIELDS - 1].count, 2);
+        assert_eq!(stats.height(), 1 + MAX_STATS_FIELDS as u16);
+
+        stats.reset();
+        assert!(stats.fields.is_empty());
+        assert_eq!(stats.height(), 2);
+
+        // Fields are tracked again after a reset
+        stats.push(&fields[MAX_STATS_FIELDS..]);
+        assert_eq!(stats.fields[0].name, format!("field{}", MAX_STATS_FIELDS));
+    }
+}
@@ -0,0 +1,298 @@
+//! USB port matching
+//!
+//! Selects the port by the USB metadata of the device instead of its name, which depends on the
+//! order the devices were plugged in. Patterns are case-insensitive, `*` matches any run of
+//! characters and `?` a single one.
+//!
+//! ```text
+//! mxs vid=0483 pid=5740
+//! mxs manufacturer=ftdi* sn=A50285BI
+//! mxs product="*Virtual COM*"
+//! ```
+//!
+//! VID and PID are 4 hex digits, with an optional `0x` prefix.
+
+use std::fmt;
+
+use anyhow::{Result as AnyResult, bail};
+use serialport::{SerialPortInfo, SerialPortType, UsbPortInfo};
+
+/// Options of the USB match
+pub const USB_OPTIONS: [&str; 5] = ["vid", "pid", "sn", "manufacturer", "product"];
+
+/// Patterns the USB metadata of a port has to match, unset ones match anything
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UsbMatch {
+    pub vid:           Option<String>,
+    pub pid:           Option<String>,
+    pub serial_number: Option<String>,
+    pub manufacturer:  Option<String>,
+    pub product:       Option<String>,
+}
+
+impl UsbMatch {
+    /// No pattern set, the port is selected by name
+    pub fn is_empty(&self) -> bool {
+        self.patterns().iter().all(|(_, pattern)| pattern.is_none())
+    }
+
+    /// Apply one `USB_OPTIONS` entry
+    pub fn set(&mut self, key: &str, value: &str) -> AnyResult<()> {
+        match key {
+            "vid" => self.vid = Some(parse_id(key, value)?),
+            "pid" => self.pid = Some(parse_id(key, value)?),
+            "sn" => self.serial_number = Some(value.into()),
+            "manufacturer" => self.manufacturer = Some(value.into()),
+            "product" => self.product = Some(value.into()),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// Whether `port` is a USB port with matching metadata
+    pub fn matches(&self, port: &SerialPortInfo) -> bool {
+        let SerialPortType::UsbPort(info) = &port.port_type
+        else {
+            return false;
+        };
+
+        let vid = format!("{:04x}", info.vid);
+        let pid = format!("{:04x}", info.pid);
+        let fields = [
+            (&self.vid, Some(vid.as_str())),
+            (&self.pid, Some(pid.as_str())),
+            (&self.serial_number, info.serial_number.as_deref()),
+            (&self.manufacturer, info.manufacturer.as_deref()),
+            (&self.product, info.product.as_deref()),
+        ];
+
+        fields.into_iter().all(|(pattern, value)| match pattern {
+            Some(pattern) => {
+                value.is_some_and(|v| glob_match(&pattern.to_lowercase(), &v.to_lowercase()))
+            }
+            None => true,
+        })
+    }
+
+    fn patterns(&self) -> [(&'static str, &Option<String>); 5] {
+        [
+            ("vid", &self.vid),
+            ("pid", &self.pid),
+            ("sn", &self.serial_number),
+            ("manufacturer", &self.manufacturer),
+            ("product", &self.product),
+        ]
+    }
+}
+
+/// Set patterns in option form, e.g. `vid=0483 pid=5740`
+impl fmt::Display for UsbMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let patterns: Vec<String> = self
+            .patterns()
+            .into_iter()
+            .filter_map(|(key, pattern)| Some(format!("{}={}", key, pattern.as_ref()?)))
+            .collect();
+        write!(f, "{}", patterns.join(" "))
+    }
+}
+
+/// Connection type and USB metadata of a port for the port list, e.g.
+/// `USB 0483:5740 STMicroelectronics STM32 Virtual ComPort sn 3A7F2B1C`
+pub fn describe_port(port: &SerialPortInfo) -> String {
+    match &port.port_type {
+        SerialPortType::UsbPort(info) => describe_usb(info),
+        SerialPortType::PciPort => "PCI".into(),
+        SerialPortType::BluetoothPort => "Bluetooth".into(),
+        SerialPortType::Unknown => String::new(),
+    }
+}
+
+fn describe_usb(info: &UsbPortInfo) -> String {
+    let mut text = format!("USB {:04x}:{:04x}", info.vid, info.pid);
+
+    // Some drivers repeat the manufacturer in the product string
+    let product = info.product.as_deref().filter(|p| {
+        info.manufacturer
+            .as_deref()
+            .is_none_or(|m| !p.starts_with(m))
+    });
+    for name in [info.manufacturer.as_deref(), product]
+        .into_iter()
+        .flatten()
+    {
+        text.push(' ');
+        text.push_str(name);
+    }
+    if let Some(serial_number) = &info.serial_number {
+        text.push_str(" sn ");
+        text.push_str(serial_number);
+    }
+
+    text
+}
+
+/// VID or PID pattern, lowercase without the `0x` prefix
+fn parse_id(key: &str, value: &str) -> AnyResult<String> {
+    let lower = value.to_lowercase();
+    let id = lower.strip_prefix("0x").unwrap_or(&lower);
+
+    let digits = id.chars().filter(|c| *c != '*').count();
+    let valid = id
+        .chars()
+        .all(|c| c.is_ascii_hexdigit() || c == '*' || c == '?');
+    if id.is_empty() || !valid || digits > 4 || (digits < 4 && !id.contains('*')) {
+        bail!(
+            "Invalid {} '{}', expected 4 hex digits like 0483, wildcards allowed",
+            key,
+            value
+        );
+    }
+
+    Ok(id.to_string())
+}
+
+/// `*` matches any run of characters, `?` a single one
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text position it was tried at
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => {
+                // Let the last `*` take one more character
+                let Some((star_p, star_t)) = star
+                else {
+                    return false;
+                };
+                star = Some((star_p, star_t + 1));
+                p = star_p + 1;
+                t = star_t + 1;
+            }
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Tests
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        let cases = [
+            ("stlink", "stlink", true),
+            ("stlink", "stlink-v3", false),
+            ("*", "", true),
+            ("*", "anything", true),
+            ("*link", "stlink", true),
+            ("*link", "stlink-v3", false),
+            ("st*", "stlink", true),
+            ("st*", "ftdi", false),
+            ("st*v3", "stlink-v3", true),
+            ("st*v3", "stlink-v2", false),
+            ("*virtual*com*", "stm32 virtual comport", true),
+            ("*a*b", "aab", true),
+            ("*a*b", "aba", false),
+            ("st?ink", "stlink", true),
+            ("st?ink", "stink", false),
+            ("????", "0483", true),
+            ("????", "048", false),
+            ("04?3", "0483", true),
+        ];
+
+        for (pattern, text, expected) in cases {
+            assert_eq!(glob_match(pattern, text), expected, "{} {}", pattern, text);
+        }
+    }
+
+    #[test]
+    fn parse_ids() {
+        let cases = [
+            ("0483", Some("0483")),
+            ("0x0483", Some("0483")),
+            ("0X0483", Some("0483")),
+            ("ABCD", Some("abcd")),
+            ("0xABcd", Some("abcd")),
+            ("04*", Some("04*")),
+            ("0x*", Some("*")),
+            ("04?3", Some("04?3")),
+            ("483", None),
+            ("04830", None),
+            ("0x", None),
+            ("", None),
+            ("04g3", None),
+            ("0x0x0483", None),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(parse_id("vid", text).ok().as_deref(), expected, "{}", text);
+        }
+    }
+
+    fn usb_port(vid: u16, pid: u16, product: Option<&str>) -> SerialPortInfo {
+        SerialPortInfo {
+            port_name: "/dev/ttyACM0".into(),
+            port_type: SerialPortType::UsbPort(UsbPortInfo {
+                vid,
+                pid,
+                serial_number: Some("3A7F2B1C".into()),
+                manufacturer: Some("STMicroelectronics".into()),
+                product: product.map(String::from),
+            }),
+        }
+    }
+
+    #[test]
+    fn matches() {
+        let stlink = usb_port(0x0483, 0x374b, Some("STM32 STLink"));
+        let ftdi = usb_port(0x0403, 0x6001, None);
+
+        // Options, then whether they match the ST-Link and the FTDI port
+        let cases = [
+            ("", true, true),
+            ("vid=0483", true, false),
+            ("vid=0x0483 pid=374B", true, false),
+            ("vid=04*", true, true),
+            ("vid=0* pid=6*", false, true),
+            ("pid=37?b", true, false),
+            ("vid=0483 pid=5740", false, false),
+            ("product=*stlink*", true, false),
+            ("product=*", true, false),
+            ("manufacturer=stmicro* sn=3a7f2b1c", true, true),
+        ];
+
+        for (options, stlink_matches, ftdi_matches) in cases {
+            let mut usb = UsbMatch::default();
+            for option in options.split_whitespace() {
+                let (key, value) = option.split_once('=').unwrap();
+                usb.set(key, value).unwrap();
+            }
+            assert_eq!(usb.matches(&stlink), stlink_matches, "{}", options);
+            assert_eq!(usb.matches(&ftdi), ftdi_matches, "{}", options);
+        }
+
+        let pci = SerialPortInfo {
+            port_name: "/dev/ttyS0".into(),
+            port_type: SerialPortType::PciPort,
+        };
+        assert!(!UsbMatch::default().matches(&pci));
+    }
+}
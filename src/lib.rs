@@ -1 +1,42 @@
+//! MXS Serial Link
+//!
+//! The MXS protocol codec, shared by the host terminal and device firmware.
+//!
+//! Features:
+//! - `alloc`  - packet decoder and stream decoder
+//! - `std`    - reassembler, reliable commands, the packet type registry and data processors
+//! - `derive` - `#[derive(MxsRecord)]` for record structs
+//! - `cli`    - terminal helpers and the host terminal, `app::run` (default)
+//!
+//! Firmware depends on the crate with `default-features = false`, which leaves the `no_std`
+//! protocol definitions, the record traits and the heapless encoder.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+// Lets `#[derive(MxsRecord)]` name this crate from the records defined in it
+#[cfg(feature = "derive")]
+extern crate self as mxs_serial_link;
+
+pub mod mxs_encoder;
+pub mod mxs_record;
+pub mod mxs_shared;
+
+#[cfg(feature = "alloc")]
+pub mod mxs_decoder;
+
+#[cfg(feature = "std")]
+pub mod mxs_processor;
+#[cfg(feature = "std")]
+pub mod mxs_reassembler;
+#[cfg(feature = "std")]
+pub mod mxs_registry;
+#[cfg(feature = "std")]
+pub mod mxs_reliable;
+
+#[cfg(feature = "cli")]
+pub mod app;
+#[cfg(feature = "cli")]
 pub mod stdio_helper;
@@ -1,14 +1,34 @@
 pub use crate::mxs_shared::*;
 
+use alloc::borrow::Cow;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::fmt;
+
 // —————————————————————————————————————————————————————————————————————————————————————————————————
 //                                            MXS Decoder
 // —————————————————————————————————————————————————————————————————————————————————————————————————
 
 /// Decoded Packet
+///
+/// The payload is borrowed from the input buffer, unless it had to be unstuffed.
 #[derive(Debug)]
 pub struct MxsPacket<'a> {
     pub packet_type: MxsPacketType,
-    pub data:        &'a [u8],
+    pub seq:         Option<u8>,
+    pub fragment:    Option<MxsFragment>,
+    /// Record id of a tagged Data payload
+    pub record:      Option<u8>,
+    pub data:        Cow<'a, [u8]>,
+}
+
+/// Packet that failed the CRC check
+#[derive(Debug)]
+pub struct MxsCorruptedPacket<'a> {
+    pub packet_type:  MxsPacketType,
+    pub data:         Cow<'a, [u8]>,
+    pub received_crc: u16,
+    pub computed_crc: u16,
 }
 
 #[derive(Debug)]
@@ -16,12 +36,58 @@ pub struct MxsFilterResult<'a> {
     pub skipped_data: &'a [u8],
     pub trim_index:   usize,
     pub packets:      Vec<MxsPacket<'a>>,
+    pub corrupted:    Vec<MxsCorruptedPacket<'a>>,
+}
+
+/// Single extraction step result
+enum MxsExtracted<'a> {
+    Packet(MxsPacket<'a>),
+    Corrupted(MxsCorruptedPacket<'a>),
+}
+
+impl MxsPacket<'_> {
+    /// Detach the packet from the input buffer
+    pub fn into_owned(self) -> MxsPacket<'static> {
+        MxsPacket {
+            packet_type: self.packet_type,
+            seq:         self.seq,
+            fragment:    self.fragment,
+            record:      self.record,
+            data:        Cow::Owned(self.data.into_owned()),
+        }
+    }
+}
+
+impl MxsCorruptedPacket<'_> {
+    /// Detach the packet from the input buffer
+    pub fn into_owned(self) -> MxsCorruptedPacket<'static> {
+        MxsCorruptedPacket {
+            packet_type:  self.packet_type,
+            data:         Cow::Owned(self.data.into_owned()),
+            received_crc: self.received_crc,
+            computed_crc: self.computed_crc,
+        }
+    }
+}
+
+/// Reasons a packet could not be read after a marker
+enum MxsReadError {
+    /// Packet not fully received yet
+    Incomplete,
+    /// False marker, unknown header, or packet interrupted by a new marker
+    Invalid,
 }
 
 pub struct MxsDecoder<'a> {
-    data:     &'a [u8],
-    cursor:   usize,
-    skip_pos: Option<usize>,
+    data:          &'a [u8],
+    framing:       MxsFraming,
+    types:         MxsTypeSet,
+    cursor:        usize,
+    skip_pos:      Option<usize>,
+    /// End of the last packet that failed the CRC check, as declared by its header
+    corrupted_end: Option<usize>,
+    /// Largest end the incomplete packet can have on the wire, once its length is known
+    wire_end:      Option<usize>,
 }
 
 impl<'a> MxsDecoder<'a> {
@@ -30,112 +96,1039 @@ impl<'a> MxsDecoder<'a> {
     /// Scans the input buffer, extracts all complete packets, and returns:
     /// - the bytes skipped before the first valid packet,
     /// - the index of the last valid processed byte (for buffer draining),
-    /// - a list of decoded packets (empty if none were found).
+    /// - a list of decoded packets (empty if none were found),
+    /// - a list of packets that failed the CRC check.
     ///
     /// The caller can trim the processed portion of the buffer and append new data
-    /// before calling this function again. No allocations occur beyond the packet list.
+    /// before calling this function again. No allocations occur beyond the packet list,
+    /// except for stuffed payloads that contain escaped bytes.
     #[inline]
-    pub fn filter_buffer(data: &'a [u8]) -> MxsFilterResult<'a> {
-        let mut decoder = Self {
-            data,
-            cursor: 0,
-            skip_pos: None,
-        };
+    pub fn filter_buffer(data: &'a [u8], config: &MxsConfig) -> MxsFilterResult<'a> {
+        Self::filter_buffer_with_types(data, config, MxsTypeSet::EMPTY)
+    }
+
+    /// Same as `filter_buffer`, also accepting the application packet types in `types`
+    #[inline]
+    pub fn filter_buffer_with_types(
+        data: &'a [u8],
+        config: &MxsConfig,
+        types: MxsTypeSet,
+    ) -> MxsFilterResult<'a> {
+        let mut decoder = Self::new(data, config.framing, types);
         let mut packets = Vec::new();
+        let mut corrupted = Vec::new();
 
-        while let Some(packet) = decoder.extract_packet() {
-            packets.push(packet);
+        while let Some(extracted) = decoder.extract_packet() {
+            match extracted {
+                MxsExtracted::Packet(packet) => packets.push(packet),
+                MxsExtracted::Corrupted(packet) => corrupted.push(packet),
+            }
         }
 
         let first_pos = decoder.skip_pos.unwrap_or(0);
         let skipped_data = &data[..first_pos];
-        let trim_index = { if packets.is_empty() { first_pos } else { decoder.cursor } };
+        let nothing_found = packets.is_empty() && corrupted.is_empty();
+        let trim_index = { if nothing_found { first_pos } else { decoder.cursor } };
 
         MxsFilterResult {
             skipped_data,
             trim_index,
             packets,
+            corrupted,
         }
     }
 
-    #[inline]
-    fn extract_packet(&mut self) -> Option<MxsPacket<'a>> {
-        // Buffer too short to be able to extract a marker
-        if self.cursor + MARKER_LEN > self.data.len() {
-            return None;
+    fn new(data: &'a [u8], framing: MxsFraming, types: MxsTypeSet) -> Self {
+        Self {
+            data,
+            framing,
+            types,
+            cursor: 0,
+            skip_pos: None,
+            corrupted_end: None,
+            wire_end: None,
         }
+    }
+
+    #[inline]
+    fn extract_packet(&mut self) -> Option<MxsExtracted<'a>> {
+        loop {
+            // Buffer too short to be able to extract a marker
+            if self.cursor + MARKER_LEN > self.data.len() {
+                return self.skip_to_end();
+            }
 
-        // ---- Find packet start
-        let found_rel = self.data[self.cursor..]
-            .windows(MARKER_LEN)
-            .position(|w| w == MARKER);
+            // ---- Find packet start
+            let found_rel = self.data[self.cursor..]
+                .windows(MARKER_LEN)
+                .position(|w| w == MARKER);
 
-        // We skip most of the data if no markers were found in the entire buffer
-        if found_rel.is_none() {
-            if self.skip_pos.is_none() {
-                self.skip_pos = Some(self.data.len() - (MARKER_LEN - 1));
+            // We skip the data if no markers were found in the entire buffer
+            if found_rel.is_none() {
+                return self.skip_to_end();
             }
-            return None;
-        }
 
-        let start_pos = found_rel.unwrap() + self.cursor;
+            let start_pos = found_rel.unwrap() + self.cursor;
+
+            // Check if we have enough data to extract a minimal packet
+            if start_pos + MIN_PACKET_SIZE > self.data.len() {
+                // skip the non matching data
+                if self.skip_pos.is_none() {
+                    self.skip_pos = Some(start_pos);
+                }
+                return None;
+            }
 
-        // Check if we have enough data to extract a minimal packet
-        if start_pos + MIN_PACKET_SIZE > self.data.len() {
-            // skip the non matching data
-            if self.skip_pos.is_none() {
-                self.skip_pos = Some(start_pos);
+            match self.read_packet(start_pos) {
+                Ok(extracted) => return Some(extracted),
+                Err(MxsReadError::Incomplete) => return self.wait_for_data(start_pos),
+                // Keep searching after a false marker
+                Err(MxsReadError::Invalid) => self.cursor = start_pos + MARKER_LEN,
             }
-            return None;
         }
+    }
+
+    /// Read the packet following the marker at `start_pos`
+    #[inline]
+    fn read_packet(&mut self, start_pos: usize) -> Result<MxsExtracted<'a>, MxsReadError> {
+        let mut reader = MxsBodyReader {
+            data:    self.data,
+            pos:     start_pos + MARKER_LEN,
+            framing: self.framing,
+        };
+        self.wire_end = None;
 
-        let type_pos = start_pos + MARKER_LEN;
+        // Header bytes are kept for the CRC
+        let mut header = [0u8; MAX_HEADER_LEN];
+        let mut header_len = 0;
 
         // ---- Extract Packet Type
-        let packet_type = match MxsPacketType::try_from(self.data[type_pos]) {
-            Ok(pt) => pt,
-            Err(_) => {
-                // Unknown Packet Type or false marker
-                // Skip the non matching data
-                let skip_pos = start_pos + MARKER_LEN;
-                if self.skip_pos.is_none() {
-                    self.skip_pos = Some(skip_pos);
-                }
-                self.cursor = skip_pos;
+        let type_byte = reader.read_byte()?;
+        header[header_len] = type_byte;
+        header_len += TYPE_LEN;
 
-                return None;
+        // Unknown Packet Type or false marker
+        let packet_type = MxsPacketType::try_from(type_byte & !TYPE_FLAGS_BIT)
+            .map_err(|_| MxsReadError::Invalid)?;
+
+        // ---- Extract Flags
+        let mut flags = 0;
+
+        if type_byte & TYPE_FLAGS_BIT != 0 {
+            flags = reader.read_byte()?;
+
+            // Unknown flags or false marker
+            if flags & !KNOWN_FLAGS != 0 {
+                return Err(MxsReadError::Invalid);
             }
+
+            header[header_len] = flags;
+            header_len += FLAGS_LEN;
+        }
+
+        // Unregistered application type without a CRC, most likely a false marker
+        if let MxsPacketType::Unknown(id) = packet_type
+            && !self.types.contains(id)
+            && flags & FLAG_CRC == 0
+        {
+            return Err(MxsReadError::Invalid);
+        }
+
+        // ---- Extract Sequence Number
+        let seq = if flags & FLAG_SEQ != 0 {
+            let seq = reader.read_byte()?;
+            header[header_len] = seq;
+            header_len += SEQ_LEN;
+            Some(seq)
+        }
+        else {
+            None
         };
 
         // ---- Extract Data Length
-        let size_pos = type_pos + TYPE_LEN;
-        let data_len = self.data[size_pos] as usize;
+        let size_len = if flags & FLAG_EXT_LEN != 0 { EXT_SIZE_LEN } else { SIZE_LEN };
+        let mut size_bytes = [0u8; EXT_SIZE_LEN];
+
+        for byte in &mut size_bytes[..size_len] {
+            *byte = reader.read_byte()?;
+        }
+
+        header[header_len..header_len + size_len].copy_from_slice(&size_bytes[..size_len]);
+        header_len += size_len;
+
+        let data_len = u16::from_le_bytes(size_bytes) as usize;
+
+        // Every byte after the marker may be stuffed
+        let crc_len = if flags & FLAG_CRC != 0 { CRC_LEN } else { 0 };
+        let body_len = header_len + data_len + crc_len;
+        let stuffing = if self.framing == MxsFraming::Stuffed { 2 } else { 1 };
+        self.wire_end = Some(start_pos + MARKER_LEN + body_len * stuffing);
 
         // ---- Extract Data
-        let data_start = size_pos + SIZE_LEN;
-        let data_end = data_start + data_len;
+        let payload = reader.read_slice(data_len)?;
 
-        // Ensure packet fits in buffer
-        if data_end > self.data.len() {
-            self.cursor = start_pos; // Buffer too short, exit
-            // skip the non matching data
-            if self.skip_pos.is_none() {
-                self.skip_pos = Some(start_pos);
-            }
-            return None;
+        // ---- Extract CRC
+        let crc = if flags & FLAG_CRC != 0 {
+            let crc_bytes = [reader.read_byte()?, reader.read_byte()?];
+            Some(u16::from_le_bytes(crc_bytes))
         }
+        else {
+            None
+        };
 
-        let payload = &self.data[data_start..data_end];
-        self.cursor = data_end;
+        // ---- Extract Fragment Header
+        let fragment = if flags & FLAG_FRAGMENT != 0 {
+            let Some(header_bytes) = payload.first_chunk::<FRAGMENT_HEADER_LEN>()
+            else {
+                return Err(MxsReadError::Invalid);
+            };
+            Some(MxsFragment::from_bytes(*header_bytes))
+        }
+        else {
+            None
+        };
 
-        // Track first packet position
+        // ---- Extract Record Id, it follows the fragment header
+        let record_pos = if fragment.is_some() { FRAGMENT_HEADER_LEN } else { 0 };
+        let record = if flags & FLAG_RECORD != 0 {
+            let Some(&record) = payload.get(record_pos)
+            else {
+                return Err(MxsReadError::Invalid);
+            };
+            Some(record)
+        }
+        else {
+            None
+        };
+
+        // Track first packet position, once the header is known to be valid
         if self.skip_pos.is_none() {
             self.skip_pos = Some(start_pos);
         }
 
-        Some(MxsPacket {
+        // ---- Verify CRC
+        if let Some(received_crc) = crc {
+            let computed_crc = crc16_ccitt_update(crc16_ccitt(&header[..header_len]), &payload);
+
+            if received_crc != computed_crc {
+                // The length byte may be the corrupted one, so resume the search right after
+                // the marker instead of trusting the packet end
+                self.cursor = start_pos + MARKER_LEN;
+                self.corrupted_end = Some(reader.pos);
+
+                return Ok(MxsExtracted::Corrupted(MxsCorruptedPacket {
+                    packet_type,
+                    data: payload,
+                    received_crc,
+                    computed_crc,
+                }));
+            }
+        }
+
+        // ---- Strip Fragment Header and Record Id
+        let prefix_len = record_pos + if record.is_some() { RECORD_ID_LEN } else { 0 };
+        let payload = strip_prefix(payload, prefix_len);
+
+        self.cursor = reader.pos;
+
+        Ok(MxsExtracted::Packet(MxsPacket {
             packet_type,
+            seq,
+            fragment,
+            record,
             data: payload,
-        })
+        }))
+    }
+
+    /// Skip the rest of the buffer, keeping a trailing byte that could be the start of a marker
+    #[inline]
+    fn skip_to_end(&mut self) -> Option<MxsExtracted<'a>> {
+        if self.skip_pos.is_none() {
+            let partial_marker = self.data.last() == Some(&MARKER[0]);
+            let keep = if partial_marker { MARKER_LEN - 1 } else { 0 };
+            self.skip_pos = Some(self.data.len() - keep);
+        }
+        None
+    }
+
+    /// Stop at a packet that is not fully received yet
+    #[inline]
+    fn wait_for_data(&mut self, start_pos: usize) -> Option<MxsExtracted<'a>> {
+        self.cursor = start_pos; // Buffer too short, exit
+        // skip the non matching data
+        if self.skip_pos.is_none() {
+            self.skip_pos = Some(start_pos);
+        }
+        None
+    }
+}
+
+/// Drop the first `len` payload bytes, without copying borrowed payloads
+fn strip_prefix(payload: Cow<'_, [u8]>, len: usize) -> Cow<'_, [u8]> {
+    match payload {
+        Cow::Borrowed(p) => Cow::Borrowed(&p[len..]),
+        Cow::Owned(mut p) => {
+            p.drain(..len);
+            Cow::Owned(p)
+        }
+    }
+}
+
+// —————————————————————————————————————————— Body Reader ——————————————————————————————————————————
+
+/// Reads the bytes following a marker, undoing the byte stuffing
+struct MxsBodyReader<'a> {
+    data:    &'a [u8],
+    pos:     usize,
+    framing: MxsFraming,
+}
+
+impl<'a> MxsBodyReader<'a> {
+    #[inline]
+    fn read_byte(&mut self) -> Result<u8, MxsReadError> {
+        let byte = *self.data.get(self.pos).ok_or(MxsReadError::Incomplete)?;
+        self.pos += 1;
+
+        if self.framing == MxsFraming::Stuffed && byte == STUFF_ESCAPE {
+            let fill = *self.data.get(self.pos).ok_or(MxsReadError::Incomplete)?;
+
+            // Anything but the fill byte means a new marker interrupted this packet
+            if fill != STUFF_FILL {
+                return Err(MxsReadError::Invalid);
+            }
+            self.pos += 1;
+        }
+
+        Ok(byte)
+    }
+
+    #[inline]
+    fn read_slice(&mut self, len: usize) -> Result<Cow<'a, [u8]>, MxsReadError> {
+        let raw = self.data.get(self.pos..self.pos + len);
+
+        // Borrow when no unstuffing is needed
+        if let Some(raw) = raw {
+            if self.framing == MxsFraming::Plain || !raw.contains(&STUFF_ESCAPE) {
+                self.pos += len;
+                return Ok(Cow::Borrowed(raw));
+            }
+        }
+        else if self.framing == MxsFraming::Plain {
+            return Err(MxsReadError::Incomplete);
+        }
+
+        let mut unstuffed = Vec::with_capacity(len);
+        for _ in 0..len {
+            unstuffed.push(self.read_byte()?);
+        }
+
+        Ok(Cow::Owned(unstuffed))
+    }
+}
+
+// ————————————————————————————————————————— Stream Decoder ————————————————————————————————————————
+
+/// Decoded stream content, in the order it was received
+#[derive(Debug)]
+pub enum MxsEvent {
+    /// Bytes outside of any packet, such as debug prints
+    Text(Vec<u8>),
+    Packet(MxsPacket<'static>),
+    Corrupted(MxsCorruptedPacket<'static>),
+}
+
+/// Stateful decoder that owns the receive buffer
+///
+/// Bytes are pushed as they are read, and decoded events are pulled through `events`. Text found
+/// between packets is reported in place instead of being dropped. The bytes of a packet that
+/// failed the CRC check are dropped with it.
+pub struct MxsStreamDecoder {
+    config:  MxsConfig,
+    types:   MxsTypeSet,
+    buffer:  VecDeque<u8>,
+    pending: Option<MxsEvent>,
+    /// Buffered bytes that belong to a corrupted packet. They are searched for packets again, in
+    /// case its length was the corrupted field, but never reported as text.
+    hidden:  usize,
+}
+
+impl MxsStreamDecoder {
+    pub fn new(config: MxsConfig) -> Self {
+        Self::with_capacity(config, MAX_WIRE_SIZE)
+    }
+
+    pub fn with_capacity(config: MxsConfig, capacity: usize) -> Self {
+        Self {
+            config,
+            types: MxsTypeSet::EMPTY,
+            buffer: VecDeque::with_capacity(capacity),
+            pending: None,
+            hidden: 0,
+        }
+    }
+
+    /// Change the link settings, for example after a handshake
+    pub fn set_config(&mut self, config: MxsConfig) {
+        self.config = config;
+    }
+
+    pub fn config(&self) -> &MxsConfig {
+        &self.config
+    }
+
+    /// Application packet types to accept without a CRC
+    pub fn set_types(&mut self, types: MxsTypeSet) {
+        self.types = types;
+    }
+
+    /// Append received bytes
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend(bytes);
+    }
+
+    /// Bytes received but not decoded yet
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Drop all buffered bytes
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        self.pending = None;
+        self.hidden = 0;
+    }
+
+    /// Decode the buffered bytes
+    ///
+    /// The iterator ends once the rest of the buffer needs more data to be decoded.
+    pub fn events(&mut self) -> MxsEvents<'_> {
+        MxsEvents { decoder: self }
+    }
+
+    fn next_event(&mut self) -> Option<MxsEvent> {
+        if let Some(event) = self.pending.take() {
+            return Some(event);
+        }
+
+        let data = self.buffer.make_contiguous();
+
+        let mut decoder = MxsDecoder::new(data, self.config.framing, self.types);
+
+        let extracted = decoder.extract_packet();
+        let mut text_end = decoder.skip_pos.unwrap_or(0);
+        let mut consumed = decoder.cursor.max(text_end);
+
+        // An incomplete packet can't grow past its declared size, so its marker was false
+        let wire_end = decoder.wire_end.unwrap_or(MAX_EXT_WIRE_SIZE);
+        if extracted.is_none() && text_end == 0 && data.len() >= wire_end {
+            text_end = MARKER_LEN;
+            consumed = MARKER_LEN;
+        }
+
+        let text_start = self.hidden.min(text_end);
+        let text =
+            (text_end > text_start).then(|| MxsEvent::Text(data[text_start..text_end].to_vec()));
+
+        self.hidden = match (&extracted, decoder.corrupted_end) {
+            (Some(MxsExtracted::Corrupted(_)), Some(end)) => end.max(self.hidden) - consumed,
+            _ => self.hidden.saturating_sub(consumed),
+        };
+
+        let event = match extracted {
+            Some(MxsExtracted::Packet(packet)) => Some(MxsEvent::Packet(packet.into_owned())),
+            Some(MxsExtracted::Corrupted(packet)) => Some(MxsEvent::Corrupted(packet.into_owned())),
+            None => None,
+        };
+
+        self.buffer.drain(..consumed);
+
+        match (text, event) {
+            (Some(text), event) => {
+                self.pending = event;
+                Some(text)
+            }
+            (None, event) => event,
+        }
+    }
+}
+
+/// Iterator over the events of a `MxsStreamDecoder`
+pub struct MxsEvents<'a> {
+    decoder: &'a mut MxsStreamDecoder,
+}
+
+impl Iterator for MxsEvents<'_> {
+    type Item = MxsEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.decoder.next_event()
+    }
+}
+
+// ——————————————————————————————————————————— Statistics ———————————————————————————————————————————
+
+/// Sequence numbers remembered for duplicate detection
+const SEQ_WINDOW: u8 = 64;
+
+/// Per-connection packet statistics
+///
+/// Gaps, duplicates and reordering are tracked from the packet sequence numbers. A packet that
+/// arrives late is first counted as lost, then moved to `reordered`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MxsLinkStats {
+    pub packets:    u64,
+    pub lost:       u64,
+    pub duplicates: u64,
+    pub reordered:  u64,
+    pub corrupted:  u64,
+
+    /// Highest sequence number received
+    last_seq: Option<u8>,
+    /// Bit N set if `last_seq - N` was received
+    seen:     u64,
+}
+
+impl MxsLinkStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a received packet
+    pub fn record_packet(&mut self, packet: &MxsPacket) {
+        self.packets += 1;
+
+        if let Some(seq) = packet.seq {
+            self.record_seq(seq);
+        }
+    }
+
+    /// Record a packet that failed the CRC check
+    pub fn record_corrupted(&mut self) {
+        self.corrupted += 1;
+    }
+
+    fn record_seq(&mut self, seq: u8) {
+        let Some(last_seq) = self.last_seq
+        else {
+            self.last_seq = Some(seq);
+            self.seen = 1;
+            return;
+        };
+
+        let ahead = seq.wrapping_sub(last_seq);
+
+        // ---- Newer packet
+        if ahead != 0 && ahead < 128 {
+            self.lost += ahead as u64 - 1;
+            self.seen = self.seen.checked_shl(ahead as u32).unwrap_or(0) | 1;
+            self.last_seq = Some(seq);
+            return;
+        }
+
+        // ---- Older packet
+        let behind = last_seq.wrapping_sub(seq);
+
+        if behind >= SEQ_WINDOW {
+            // Too old to tell, most likely the device restarted its counter
+            self.last_seq = Some(seq);
+            self.seen = 1;
+        }
+        else if self.seen & (1 << behind) != 0 {
+            self.duplicates += 1;
+        }
+        else {
+            self.seen |= 1 << behind;
+            self.lost = self.lost.saturating_sub(1);
+            self.reordered += 1;
+        }
+    }
+}
+
+impl fmt::Display for MxsLinkStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rx:{} lost:{} dup:{} ooo:{} crc:{}",
+            self.packets, self.lost, self.duplicates, self.reordered, self.corrupted
+        )
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Tests
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every feature combination, with both framings
+    fn configs() -> Vec<MxsConfig> {
+        let mut configs = Vec::new();
+        for framing in [MxsFraming::Plain, MxsFraming::Stuffed] {
+            for bits in 0..8 {
+                configs.push(MxsConfig {
+                    crc: bits & 1 != 0,
+                    seq: bits & 2 != 0,
+                    ext_len: bits & 4 != 0,
+                    framing,
+                });
+            }
+        }
+        configs
+    }
+
+    /// Packet built by hand from the format description
+    fn packet(config: &MxsConfig, p_type: MxsPacketType, data: &[u8]) -> Vec<u8> {
+        packet_with_flags(config, p_type, 0, 0, data)
+    }
+
+    fn fragment_packet(config: &MxsConfig, fragment: MxsFragment, chunk: &[u8]) -> Vec<u8> {
+        let data = [&fragment.to_bytes()[..], chunk].concat();
+        packet_with_flags(config, MxsPacketType::Data, FLAG_FRAGMENT, 0, &data)
+    }
+
+    fn packet_with_flags(
+        config: &MxsConfig,
+        p_type: MxsPacketType,
+        mut flags: u8,
+        seq: u8,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let ext_len = data.len() > MAX_DATA_LEN;
+        if config.crc {
+            flags |= FLAG_CRC;
+        }
+        if config.seq {
+            flags |= FLAG_SEQ;
+        }
+        if ext_len {
+            flags |= FLAG_EXT_LEN;
+        }
+
+        let mut body = match flags {
+            0 => vec![p_type.id()],
+            flags => vec![p_type.id() | TYPE_FLAGS_BIT, flags],
+        };
+        if config.seq {
+            body.push(seq);
+        }
+        let size_len = if ext_len { EXT_SIZE_LEN } else { SIZE_LEN };
+        body.extend_from_slice(&data.len().to_le_bytes()[..size_len]);
+        body.extend_from_slice(data);
+        if config.crc {
+            body.extend(crc16_ccitt(&body).to_le_bytes());
+        }
+
+        let mut wire = MARKER.to_vec();
+        for byte in body {
+            wire.push(byte);
+            if config.framing == MxsFraming::Stuffed && byte == STUFF_ESCAPE {
+                wire.push(STUFF_FILL);
+            }
+        }
+        wire
+    }
+
+    /// Payload with markers and escape bytes inside
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| match i % 5 {
+                0 => MARKER[0],
+                1 => MARKER[1],
+                _ => i as u8,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        for config in configs() {
+            let sizes = [0, 1, 255, 256, 3000];
+
+            let mut wire = b"boot ok\n".to_vec();
+            for (seq, size) in sizes.into_iter().enumerate() {
+                let data = payload(size);
+                wire.extend(packet_with_flags(&config, MxsPacketType::Data, 0, seq as u8, &data));
+            }
+
+            let result = MxsDecoder::filter_buffer(&wire, &config);
+            assert_eq!(result.skipped_data, b"boot ok\n", "{:?}", config);
+            assert_eq!(result.trim_index, wire.len(), "{:?}", config);
+            assert!(result.corrupted.is_empty(), "{:?}", config);
+            assert_eq!(result.packets.len(), sizes.len(), "{:?}", config);
+
+            for (i, (packet, size)) in result.packets.iter().zip(sizes).enumerate() {
+                assert_eq!(packet.packet_type, MxsPacketType::Data);
+                assert_eq!(*packet.data, payload(size), "{:?} size {}", config, size);
+                assert_eq!(packet.seq, config.seq.then_some(i as u8), "{:?}", config);
+            }
+        }
+    }
+
+    #[test]
+    fn split_input_waits_for_data() {
+        for config in configs() {
+            let wire = packet(&config, MxsPacketType::Data, &payload(40));
+
+            for split in 1..wire.len() {
+                let result = MxsDecoder::filter_buffer(&wire[..split], &config);
+                assert!(result.packets.is_empty(), "{:?} split {}", config, split);
+                assert!(result.trim_index == 0, "{:?} split {}", config, split);
+            }
+        }
+    }
+
+    #[test]
+    fn resync_after_crc_mismatch() {
+        for framing in [MxsFraming::Plain, MxsFraming::Stuffed] {
+            let config = MxsConfig {
+                crc: true,
+                seq: true,
+                ext_len: true,
+                framing,
+            };
+
+            let mut wire = packet(&config, MxsPacketType::Data, &[1, 2, 3, 4]);
+            let last = wire.len() - 1;
+            wire[last] ^= 0x01;
+            wire.extend(packet(&config, MxsPacketType::Data, &[5, 6, 7]));
+
+            let result = MxsDecoder::filter_buffer(&wire, &config);
+            assert_eq!(result.corrupted.len(), 1, "{:?}", framing);
+            assert_eq!(*result.corrupted[0].data, [1, 2, 3, 4]);
+            assert_eq!(result.packets.len(), 1, "{:?}", framing);
+            assert_eq!(*result.packets[0].data, [5, 6, 7]);
+            assert_eq!(result.trim_index, wire.len());
+        }
+    }
+
+    #[test]
+    fn resync_after_corrupted_length() {
+        let config = MxsConfig {
+            crc: true,
+            ..MxsConfig::LEGACY
+        };
+
+        // Length byte bumped, the packet swallows the start of the next one
+        let mut wire = packet(&config, MxsPacketType::Data, &[1, 2, 3, 4]);
+        wire[4] += 2;
+        wire.extend(packet(&config, MxsPacketType::Data, &[5, 6, 7]));
+
+        let result = MxsDecoder::filter_buffer(&wire, &config);
+        assert_eq!(result.corrupted.len(), 1);
+        assert_eq!(result.packets.len(), 1);
+        assert_eq!(*result.packets[0].data, [5, 6, 7]);
+    }
+
+    #[test]
+    fn stuffed_marker_interrupts_packet() {
+        let config = MxsConfig {
+            framing: MxsFraming::Stuffed,
+            ..MxsConfig::LEGACY
+        };
+
+        // A new marker inside a cut off packet starts the next packet
+        let mut wire = packet(&config, MxsPacketType::Data, &[1, 2, 3, 4]);
+        wire.truncate(5);
+        wire.extend(packet(&config, MxsPacketType::Data, &[5, 6, 7]));
+
+        let result = MxsDecoder::filter_buffer(&wire, &config);
+        assert_eq!(result.packets.len(), 1);
+        assert_eq!(*result.packets[0].data, [5, 6, 7]);
+        assert_eq!(result.trim_index, wire.len());
+    }
+
+    // ---- Application Types
+
+    #[test]
+    fn unregistered_type_is_false_marker() {
+        let config = MxsConfig::LEGACY;
+
+        // Marker bytes inside plain text, followed by a space that looks like type 0x20
+        let mut wire = b"temp \xAA\x55 ok\n".to_vec();
+        wire.extend(packet(&config, MxsPacketType::Data, b"next"));
+
+        let result = MxsDecoder::filter_buffer(&wire, &config);
+        assert_eq!(result.packets.len(), 1);
+        assert_eq!(*result.packets[0].data, *b"next");
+        assert_eq!(result.skipped_data, b"temp \xAA\x55 ok\n");
+        assert_eq!(result.trim_index, wire.len());
+    }
+
+    #[test]
+    fn registered_type_is_accepted() {
+        let config = MxsConfig::LEGACY;
+        let wire = packet(&config, MxsPacketType::Unknown(0x20), b"log");
+
+        let types = MxsTypeSet::EMPTY.with(0x20);
+        let result = MxsDecoder::filter_buffer_with_types(&wire, &config, types);
+        assert_eq!(result.packets.len(), 1);
+        assert_eq!(result.packets[0].packet_type, MxsPacketType::Unknown(0x20));
+
+        let result = MxsDecoder::filter_buffer(&wire, &config);
+        assert!(result.packets.is_empty());
+    }
+
+    #[test]
+    fn unregistered_type_with_crc_is_accepted() {
+        let config = MxsConfig {
+            crc: true,
+            ..MxsConfig::LEGACY
+        };
+        let wire = packet(&config, MxsPacketType::Unknown(0x20), b"log");
+
+        let result = MxsDecoder::filter_buffer(&wire, &config);
+        assert_eq!(result.packets.len(), 1);
+        assert_eq!(result.packets[0].packet_type, MxsPacketType::Unknown(0x20));
+        assert_eq!(*result.packets[0].data, *b"log");
+    }
+
+    // ---- Fragments
+
+    fn fragment(message_id: u8, index: u16, last: bool) -> MxsFragment {
+        MxsFragment { message_id, index, last }
+    }
+
+    #[test]
+    fn round_trip_fragments() {
+        for config in configs() {
+            let message = payload(600);
+            let chunks: Vec<&[u8]> = message.chunks(250).collect();
+
+            let mut wire = Vec::new();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let last = i == chunks.len() - 1;
+                wire.extend(fragment_packet(&config, fragment(3, i as u16, last), chunk));
+            }
+
+            let result = MxsDecoder::filter_buffer(&wire, &config);
+            assert_eq!(result.packets.len(), 3, "{:?}", config);
+
+            for (i, packet) in result.packets.iter().enumerate() {
+                let header = packet.fragment.unwrap();
+                assert_eq!(header, fragment(3, i as u16, i == 2), "{:?}", config);
+                assert_eq!(*packet.data, *chunks[i], "{:?}", config);
+            }
+        }
+    }
+
+    #[test]
+    fn short_fragment_header_is_false_marker() {
+        let config = MxsConfig::LEGACY;
+
+        // Fragment flag without room for the fragment header
+        let mut wire = vec![0xAA, 0x55, 0x84, FLAG_FRAGMENT, 0x00];
+        wire.extend(b"hello");
+
+        let result = MxsDecoder::filter_buffer(&wire, &config);
+        assert!(result.packets.is_empty());
+        assert_eq!(result.skipped_data, wire);
+        assert_eq!(result.trim_index, wire.len());
+    }
+
+    // ---- Records
+
+    #[test]
+    fn round_trip_records() {
+        for config in configs() {
+            let data = [9, 1, 2, MARKER[0]];
+            let wire = packet_with_flags(&config, MxsPacketType::Data, FLAG_RECORD, 0, &data);
+
+            let result = MxsDecoder::filter_buffer(&wire, &config);
+            assert_eq!(result.packets.len(), 1, "{:?}", config);
+            assert_eq!(result.packets[0].record, Some(9), "{:?}", config);
+            assert_eq!(*result.packets[0].data, [1, 2, MARKER[0]], "{:?}", config);
+        }
+    }
+
+    #[test]
+    fn record_id_follows_fragment_header() {
+        let config = MxsConfig::LEGACY;
+        let header = fragment(5, 0, true).to_bytes();
+        let data = [&header[..], &[9, 1, 2]].concat();
+        let wire =
+            packet_with_flags(&config, MxsPacketType::Data, FLAG_FRAGMENT | FLAG_RECORD, 0, &data);
+
+        let result = MxsDecoder::filter_buffer(&wire, &config);
+        assert_eq!(result.packets.len(), 1);
+        assert_eq!(result.packets[0].fragment, Some(fragment(5, 0, true)));
+        assert_eq!(result.packets[0].record, Some(9));
+        assert_eq!(*result.packets[0].data, [1, 2]);
+    }
+
+    // ---- Stream decoder
+
+    /// Events as `T`ext, `P`acket and `C`orrupted with their bytes
+    fn stream_events(config: MxsConfig, wire: &[u8], chunk_len: usize) -> Vec<(char, Vec<u8>)> {
+        let mut decoder = MxsStreamDecoder::new(config);
+        let mut events = Vec::new();
+
+        for chunk in wire.chunks(chunk_len) {
+            decoder.push(chunk);
+            events.extend(decoder.events().map(|event| match event {
+                MxsEvent::Text(text) => ('T', text),
+                MxsEvent::Packet(packet) => ('P', packet.data.to_vec()),
+                MxsEvent::Corrupted(packet) => ('C', packet.data.to_vec()),
+            }));
+        }
+
+        // Join text split over several reads
+        let mut joined: Vec<(char, Vec<u8>)> = Vec::new();
+        for (kind, bytes) in events {
+            match joined.last_mut() {
+                Some(('T', text)) if kind == 'T' => text.extend(bytes),
+                _ => joined.push((kind, bytes)),
+            }
+        }
+        joined
+    }
+
+    #[test]
+    fn stream_text_and_packets() {
+        for config in configs() {
+            let mut wire = b"head ".to_vec();
+            wire.extend(packet(&config, MxsPacketType::Data, &payload(20)));
+            wire.extend(b"mid");
+            wire.extend(packet(&config, MxsPacketType::Data, &payload(3)));
+            wire.extend(b"tail");
+
+            for chunk_len in [1, 7, wire.len()] {
+                let expected = [
+                    ('T', b"head ".to_vec()),
+                    ('P', payload(20)),
+                    ('T', b"mid".to_vec()),
+                    ('P', payload(3)),
+                ];
+                let events = stream_events(config, &wire, chunk_len);
+                assert_eq!(events[..4], expected, "{:?} chunks of {}", config, chunk_len);
+                // The trailing text is held back while it could be part of a marker
+                let tail = events.get(4).map(|e| e.1.clone()).unwrap_or_default();
+                assert!(b"tail".starts_with(&tail));
+            }
+        }
+    }
+
+    #[test]
+    fn stream_hides_corrupted_packet() {
+        for framing in [MxsFraming::Plain, MxsFraming::Stuffed] {
+            let config = MxsConfig {
+                crc: true,
+                seq: true,
+                ext_len: true,
+                framing,
+            };
+
+            let mut corrupted = packet(&config, MxsPacketType::Data, &[1, 2, 3, 4, 4, 6]);
+            let last = corrupted.len() - 1;
+            corrupted[last] ^= 0x01;
+
+            let mut wire = b"head".to_vec();
+            wire.extend(corrupted);
+            wire.extend(b"tail!");
+
+            for chunk_len in [1, 5, wire.len()] {
+                let events = stream_events(config, &wire, chunk_len);
+                assert_eq!(
+                    events,
+                    [
+                        ('T', b"head".to_vec()),
+                        ('C', vec![1, 2, 3, 4, 4, 6]),
+                        ('T', b"tail!".to_vec()),
+                    ],
+                    "{:?} chunks of {}",
+                    framing,
+                    chunk_len
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn stream_resync_after_corrupted_length() {
+        let config = MxsConfig {
+            crc: true,
+            ..MxsConfig::LEGACY
+        };
+
+        // Length byte bumped, the packet swallows the start of the next one
+        let mut wire = packet(&config, MxsPacketType::Data, &[1, 2, 3, 4]);
+        wire[4] += 2;
+        wire.extend(packet(&config, MxsPacketType::Data, &[5, 6, 7]));
+        wire.extend(b"tail!");
+
+        let events = stream_events(config, &wire, wire.len());
+        assert_eq!(events.len(), 3, "{:?}", events);
+        assert_eq!(events[0].0, 'C');
+        assert_eq!(events[1], ('P', vec![5, 6, 7]));
+        assert_eq!(events[2], ('T', b"tail!".to_vec()));
+    }
+
+    #[test]
+    fn stream_reports_false_marker_as_text() {
+        let config = MxsConfig::LEGACY;
+        let wire = b"temp \xAA\x55 ok\n";
+
+        for chunk_len in [1, 4, wire.len()] {
+            let events = stream_events(config, wire, chunk_len);
+            assert_eq!(events, [('T', wire.to_vec())], "chunks of {}", chunk_len);
+        }
+    }
+
+    // ---- Sequence statistics
+
+    fn seq_stats(seqs: impl IntoIterator<Item = u8>) -> MxsLinkStats {
+        let mut stats = MxsLinkStats::new();
+        for seq in seqs {
+            stats.record_seq(seq);
+        }
+        stats
+    }
+
+    #[test]
+    fn seq_in_order() {
+        let stats = seq_stats(0..=200);
+        assert_eq!((stats.lost, stats.duplicates, stats.reordered), (0, 0, 0));
+    }
+
+    #[test]
+    fn seq_gaps() {
+        let stats = seq_stats([0, 1, 4, 5, 9]);
+        assert_eq!((stats.lost, stats.duplicates, stats.reordered), (5, 0, 0));
+    }
+
+    #[test]
+    fn seq_duplicates() {
+        let stats = seq_stats([0, 1, 1, 2, 0, 2]);
+        assert_eq!((stats.lost, stats.duplicates, stats.reordered), (0, 3, 0));
+    }
+
+    #[test]
+    fn seq_reordered() {
+        let stats = seq_stats([0, 2, 1, 5, 3, 4]);
+        assert_eq!((stats.lost, stats.duplicates, stats.reordered), (0, 0, 3));
+
+        // A late packet received twice is counted once
+        let stats = seq_stats([0, 2, 1, 1]);
+        assert_eq!((stats.lost, stats.duplicates, stats.reordered), (0, 1, 1));
+    }
+
+    #[test]
+    fn seq_wrap_around() {
+        let stats = seq_stats([253, 254, 255, 0, 1]);
+        assert_eq!((stats.lost, stats.duplicates, stats.reordered), (0, 0, 0));
+
+        let stats = seq_stats([254, 1]);
+        assert_eq!((stats.lost, stats.duplicates, stats.reordered), (2, 0, 0));
+
+        let stats = seq_stats([254, 0, 255, 0]);
+        assert_eq!((stats.lost, stats.duplicates, stats.reordered), (0, 1, 1));
+    }
+
+    #[test]
+    fn seq_counter_restart() {
+        // Too far behind to be a late packet, the counter starts over
+        let stats = seq_stats([100, 101, 0, 1, 2]);
+        assert_eq!((stats.lost, stats.duplicates, stats.reordered), (0, 0, 0));
     }
 }
@@ -7,26 +7,337 @@ pub use crate::mxs_shared::*;
 // For Embedded
 use heapless::Vec as HVec;
 
-pub struct MxsEncoder;
+use crate::mxs_record::MxsRecord;
+
+#[derive(Debug, Default, Clone)]
+pub struct MxsEncoder {
+    config: MxsConfig,
+    seq:    u8,
+}
 
 impl MxsEncoder {
+    pub const fn new(config: MxsConfig) -> Self {
+        Self { config, seq: 0 }
+    }
+
+    #[inline]
+    pub const fn config(&self) -> MxsConfig {
+        self.config
+    }
+
+    /// Change the settings, e.g. after a handshake. The sequence counter is kept.
+    #[inline]
+    pub fn set_config(&mut self, config: MxsConfig) {
+        self.config = config;
+    }
+
+    /// Sequence number of the next packet
     #[inline]
-    pub fn create_data_package(p_type: MxsPacketType, data: &[u8]) -> HVec<u8, MAX_PACKET_SIZE> {
+    pub const fn next_seq(&self) -> u8 {
+        self.seq
+    }
+
+    /// Encode a standard size packet into a buffer
+    #[inline]
+    pub fn create_data_package(
+        &mut self,
+        p_type: MxsPacketType,
+        data: &[u8],
+    ) -> HVec<u8, MAX_WIRE_SIZE> {
         assert!(data.len() <= MAX_DATA_LEN, "Data larger than buffer");
 
         let mut packet = HVec::<u8, _>::new();
-        let data_len = data.len().to_le_bytes();
-
-        packet.extend_from_slice(MARKER).unwrap();
-        packet.push(p_type as u8).unwrap();
-        packet.extend_from_slice(&data_len[..SIZE_LEN]).unwrap();
-        packet.extend_from_slice(data).unwrap();
+        self.write_data_package(p_type, data, |bytes| packet.extend_from_slice(bytes).unwrap());
 
         packet
     }
 
     #[inline]
-    pub fn create_package(p_type: MxsPacketType) -> HVec<u8, MAX_PACKET_SIZE> {
-        Self::create_data_package(p_type, &[])
+    pub fn create_package(&mut self, p_type: MxsPacketType) -> HVec<u8, MAX_WIRE_SIZE> {
+        self.create_data_package(p_type, &[])
+    }
+
+    /// Stream a packet through `write` without buffering it
+    ///
+    /// Payloads larger than `MAX_DATA_LEN` are sent as extended length packets, which requires
+    /// `ext_len` in the config.
+    #[inline]
+    pub fn write_data_package(
+        &mut self,
+        p_type: MxsPacketType,
+        data: &[u8],
+        mut write: impl FnMut(&[u8]),
+    ) {
+        self.write_packet(p_type, None, None, data, &mut write);
+    }
+
+    /// Stream a record as a tagged Data packet
+    pub fn write_record<R: MxsRecord>(&mut self, record: &R, write: impl FnMut(&[u8])) {
+        const {
+            assert!(
+                R::SIZE + RECORD_ID_LEN <= MAX_DATA_LEN,
+                "Record and record id larger than MAX_DATA_LEN"
+            )
+        };
+
+        let mut buf = [0u8; MAX_DATA_LEN];
+        record.encode(&mut buf[..R::SIZE]);
+
+        self.write_record_bytes(R::RECORD_ID, &buf[..R::SIZE], write);
+    }
+
+    /// Stream a tagged Data packet, the record id tells the host how to decode `data`
+    #[inline]
+    pub fn write_record_bytes(&mut self, record_id: u8, data: &[u8], mut write: impl FnMut(&[u8])) {
+        self.write_packet(MxsPacketType::Data, None, Some(record_id), data, &mut write);
+    }
+
+    /// Announce the device: protocol version, the capabilities of this config and an identity
+    pub fn write_start(&mut self, identity: &str, mut write: impl FnMut(&[u8])) {
+        assert!(identity.len() <= MAX_IDENTITY_LEN, "Identity too long");
+
+        let mut payload = HVec::<u8, MAX_DATA_LEN>::new();
+        payload
+            .extend_from_slice(&[MXS_PROTOCOL_VERSION, self.config.capabilities()])
+            .unwrap();
+        payload.extend_from_slice(identity.as_bytes()).unwrap();
+
+        self.write_packet(MxsPacketType::Start, None, None, &payload, &mut write);
+    }
+
+    /// Stream a message of any size as a sequence of fragmented Data packets
+    ///
+    /// Each packet carries at most `fragment_len` bytes of the message.
+    pub fn write_message(
+        &mut self,
+        message_id: u8,
+        data: &[u8],
+        fragment_len: usize,
+        mut write: impl FnMut(&[u8]),
+    ) {
+        assert!(
+            fragment_len > 0 && fragment_len <= MAX_EXT_DATA_LEN - FRAGMENT_HEADER_LEN,
+            "Invalid fragment length"
+        );
+
+        let count = data.len().div_ceil(fragment_len).max(1);
+        assert!(count <= MAX_FRAGMENT_INDEX as usize + 1, "Message too large");
+
+        for index in 0..count {
+            let start = index * fragment_len;
+            let end = (start + fragment_len).min(data.len());
+
+            let fragment = MxsFragment {
+                message_id,
+                index: index as u16,
+                last: index == count - 1,
+            };
+
+            self.write_packet(
+                MxsPacketType::Data,
+                Some(fragment),
+                None,
+                &data[start..end],
+                &mut write,
+            );
+        }
+    }
+
+    fn write_packet(
+        &mut self,
+        p_type: MxsPacketType,
+        fragment: Option<MxsFragment>,
+        record: Option<u8>,
+        data: &[u8],
+        write: &mut impl FnMut(&[u8]),
+    ) {
+        // Payload prefix: fragment header, then record id
+        let mut prefix = HVec::<u8, { FRAGMENT_HEADER_LEN + RECORD_ID_LEN }>::new();
+        if let Some(fragment) = fragment {
+            prefix.extend_from_slice(&fragment.to_bytes()).unwrap();
+        }
+        if let Some(record) = record {
+            prefix.push(record).unwrap();
+        }
+        let prefix = prefix.as_slice();
+
+        let payload_len = prefix.len() + data.len();
+        assert!(payload_len <= MAX_EXT_DATA_LEN, "Data larger than extended packet");
+        assert!(
+            payload_len <= MAX_DATA_LEN || self.config.ext_len,
+            "Extended length not enabled"
+        );
+        assert!(
+            p_type.id() != 0 && p_type.id() <= MxsPacketType::MAX_ID,
+            "Invalid packet type id"
+        );
+
+        let mut flags = 0;
+        if self.config.crc {
+            flags |= FLAG_CRC;
+        }
+        if payload_len > MAX_DATA_LEN {
+            flags |= FLAG_EXT_LEN;
+        }
+        if fragment.is_some() {
+            flags |= FLAG_FRAGMENT;
+        }
+        if record.is_some() {
+            flags |= FLAG_RECORD;
+        }
+        if self.config.seq {
+            flags |= FLAG_SEQ;
+        }
+
+        let size_len = if flags & FLAG_EXT_LEN != 0 { EXT_SIZE_LEN } else { SIZE_LEN };
+        let data_len = payload_len.to_le_bytes();
+
+        let mut header = HVec::<u8, MAX_HEADER_LEN>::new();
+
+        if flags != 0 {
+            header.push(p_type.id() | TYPE_FLAGS_BIT).unwrap();
+            header.push(flags).unwrap();
+        }
+        else {
+            header.push(p_type.id()).unwrap();
+        }
+
+        if flags & FLAG_SEQ != 0 {
+            header.push(self.seq).unwrap();
+            self.seq = self.seq.wrapping_add(1);
+        }
+
+        header.extend_from_slice(&data_len[..size_len]).unwrap();
+
+        write(MARKER);
+        self.write_body(write, &header);
+        self.write_body(write, prefix);
+        self.write_body(write, data);
+
+        // CRC covers everything after the marker, before stuffing
+        if flags & FLAG_CRC != 0 {
+            let crc = crc16_ccitt_update(crc16_ccitt_update(crc16_ccitt(&header), prefix), data);
+            self.write_body(write, &crc.to_le_bytes());
+        }
+    }
+
+    /// Write bytes following the marker, stuffing them if required
+    #[inline]
+    fn write_body(&self, write: &mut impl FnMut(&[u8]), bytes: &[u8]) {
+        match self.config.framing {
+            MxsFraming::Plain => {
+                write(bytes);
+            }
+            MxsFraming::Stuffed => {
+                for chunk in bytes.split_inclusive(|&b| b == STUFF_ESCAPE) {
+                    write(chunk);
+                    if chunk.last() == Some(&STUFF_ESCAPE) {
+                        write(&[STUFF_FILL]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Tests
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_packet_bytes() {
+        let mut encoder = MxsEncoder::new(MxsConfig::LEGACY);
+
+        assert_eq!(encoder.create_package(MxsPacketType::Start), [0xAA, 0x55, 0x01, 0x00]);
+        assert_eq!(encoder.create_data_package(MxsPacketType::Data, b"hi"), [
+            0xAA, 0x55, 0x04, 0x02, b'h', b'i'
+        ]);
+        // Legacy packets carry no sequence number
+        assert_eq!(encoder.next_seq(), 0);
+    }
+
+    #[test]
+    fn flagged_packet_bytes() {
+        let config = MxsConfig {
+            crc:     true,
+            seq:     true,
+            ext_len: true,
+            framing: MxsFraming::Plain,
+        };
+        let mut encoder = MxsEncoder::new(config);
+
+        let packet = encoder.create_data_package(MxsPacketType::Command, b"go");
+        let crc = crc16_ccitt(&[0x86, FLAG_CRC | FLAG_SEQ, 0x00, 0x02, b'g', b'o']);
+
+        let mut expected = vec![
+            0xAA,
+            0x55,
+            0x86,
+            FLAG_CRC | FLAG_SEQ,
+            0x00,
+            0x02,
+            b'g',
+            b'o',
+        ];
+        expected.extend_from_slice(&crc.to_le_bytes());
+        assert_eq!(packet, expected[..]);
+        assert_eq!(encoder.next_seq(), 1);
+    }
+
+    #[test]
+    fn stuffed_packet_bytes() {
+        let config = MxsConfig {
+            framing: MxsFraming::Stuffed,
+            ..MxsConfig::LEGACY
+        };
+        let mut encoder = MxsEncoder::new(config);
+
+        // Every 0xAA after the marker is followed by a fill byte
+        assert_eq!(encoder.create_data_package(MxsPacketType::Data, &[0xAA, 0x55]), [
+            0xAA, 0x55, 0x04, 0x02, 0xAA, STUFF_FILL, 0x55
+        ]);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn round_trip_through_decoder() {
+        use crate::mxs_decoder::MxsDecoder;
+
+        let data: Vec<u8> = (0..600u16).map(|i| (i % 251) as u8).collect();
+
+        for framing in [MxsFraming::Plain, MxsFraming::Stuffed] {
+            for (crc, seq) in [(false, false), (true, false), (false, true), (true, true)] {
+                let config = MxsConfig {
+                    crc,
+                    seq,
+                    ext_len: true,
+                    framing,
+                };
+                let mut encoder = MxsEncoder::new(config);
+
+                let mut wire = Vec::new();
+                for len in [0, 1, MAX_DATA_LEN, MAX_DATA_LEN + 1, data.len()] {
+                    encoder.write_data_package(MxsPacketType::Data, &data[..len], |bytes| {
+                        wire.extend_from_slice(bytes)
+                    });
+                }
+
+                let result = MxsDecoder::filter_buffer(&wire, &config);
+                assert!(result.corrupted.is_empty(), "{:?}", config);
+                assert_eq!(result.trim_index, wire.len(), "{:?}", config);
+
+                let lens: Vec<usize> = result.packets.iter().map(|p| p.data.len()).collect();
+                assert_eq!(lens, [0, 1, MAX_DATA_LEN, MAX_DATA_LEN + 1, data.len()]);
+
+                for (i, packet) in result.packets.iter().enumerate() {
+                    assert_eq!(packet.data[..], data[..packet.data.len()]);
+                    assert_eq!(packet.seq, seq.then_some(i as u8));
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,287 @@
+pub use crate::mxs_shared::*;
+
+use std::fmt;
+
+use anyhow::Result as AnyResult;
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                       MXS Data Processors
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+/// Whether the rest of the chain sees the record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MxsFlow {
+    Continue,
+    Stop,
+}
+
+/// Step of a `MxsProcessorChain`, called for every decoded record
+///
+/// `R` is the application's record type. Processors get it mutably, so earlier ones can annotate
+/// it for later ones, e.g. an alarm check before printing.
+///
+/// ```ignore
+/// struct Counter(u64);
+///
+/// impl MxsDataProcessor<Sample> for Counter {
+///     fn name(&self) -> &str {
+///         "Counter"
+///     }
+///
+///     fn process(&mut self, _sample: &mut Sample) -> AnyResult<MxsFlow> {
+///         self.0 += 1;
+///         Ok(MxsFlow::Continue)
+///     }
+/// }
+///
+/// chain.register(Counter(0));
+/// ```
+pub trait MxsDataProcessor<R>: Send {
+    /// Used in error reports
+    fn name(&self) -> &str;
+
+    /// Handle one record. An error removes the processor from the chain.
+    fn process(&mut self, record: &mut R) -> AnyResult<MxsFlow>;
+
+    /// Called regularly, with or without records, for periodic work like flushing
+    fn poll(&mut self) -> AnyResult<()> {
+        Ok(())
+    }
+
+    /// Handle a user command, returns whether it was understood
+    fn command(&mut self, _command: &str) -> AnyResult<bool> {
+        Ok(false)
+    }
+}
+
+/// Processor removed from the chain after an error
+#[derive(Debug)]
+pub struct MxsProcessorError {
+    pub processor: String,
+    pub error:     anyhow::Error,
+}
+
+impl fmt::Display for MxsProcessorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} disabled: {:#}", self.processor, self.error)
+    }
+}
+
+impl std::error::Error for MxsProcessorError {}
+
+/// Processors run in registration order
+pub struct MxsProcessorChain<R> {
+    processors: Vec<Box<dyn MxsDataProcessor<R>>>,
+}
+
+impl<R> Default for MxsProcessorChain<R> {
+    fn default() -> Self {
+        Self { processors: Vec::new() }
+    }
+}
+
+impl<R> fmt::Debug for MxsProcessorChain<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.processors.iter().map(|p| p.name()))
+            .finish()
+    }
+}
+
+impl<R> MxsProcessorChain<R> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a processor to the end of the chain
+    pub fn register(&mut self, processor: impl MxsDataProcessor<R> + 'static) {
+        self.processors.push(Box::new(processor));
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.processors.iter().map(|p| p.name())
+    }
+
+    /// The registered processors, in order
+    pub fn into_processors(self) -> Vec<Box<dyn MxsDataProcessor<R>>> {
+        self.processors
+    }
+
+    /// Run the record through the chain until a processor stops it
+    pub fn process(&mut self, record: &mut R) -> Vec<MxsProcessorError> {
+        let mut stopped = false;
+
+        self.run(|processor| {
+            if stopped {
+                return Ok(());
+            }
+            stopped = processor.process(record)? == MxsFlow::Stop;
+            Ok(())
+        })
+    }
+
+    pub fn poll(&mut self) -> Vec<MxsProcessorError> {
+        self.run(|processor| processor.poll())
+    }
+
+    /// Offer a command to the processors in order until one understands it
+    pub fn command(&mut self, command: &str) -> (bool, Vec<MxsProcessorError>) {
+        let mut handled = false;
+
+        let errors = self.run(|processor| {
+            if handled {
+                return Ok(());
+            }
+            handled = processor.command(command)?;
+            Ok(())
+        });
+
+        (handled, errors)
+    }
+
+    /// Call `f` on each processor, removing the ones that fail
+    fn run(
+        &mut self,
+        mut f: impl FnMut(&mut dyn MxsDataProcessor<R>) -> AnyResult<()>,
+    ) -> Vec<MxsProcessorError> {
+        let mut errors = Vec::new();
+
+        self.processors
+            .retain_mut(|processor| match f(processor.as_mut()) {
+                Ok(()) => true,
+                Err(error) => {
+                    errors.push(MxsProcessorError {
+                        processor: processor.name().to_string(),
+                        error,
+                    });
+                    false
+                }
+            });
+
+        errors
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Tests
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use anyhow::bail;
+
+    /// Appends its name to the record, fails on `fail_on`, handles `handles`
+    struct Step {
+        name:    &'static str,
+        fail_on: Option<u32>,
+        handles: &'static str,
+        flow:    MxsFlow,
+        /// Names of the processors offered a command
+        offered: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl Step {
+        fn new(name: &'static str) -> Self {
+            Self {
+                name,
+                fail_on: None,
+                handles: "",
+                flow: MxsFlow::Continue,
+                offered: Arc::default(),
+            }
+        }
+    }
+
+    struct Record {
+        count: u32,
+        seen:  Vec<&'static str>,
+    }
+
+    impl MxsDataProcessor<Record> for Step {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn process(&mut self, record: &mut Record) -> AnyResult<MxsFlow> {
+            if self.fail_on == Some(record.count) {
+                bail!("failed on {}", record.count);
+            }
+            record.seen.push(self.name);
+            Ok(self.flow)
+        }
+
+        fn command(&mut self, command: &str) -> AnyResult<bool> {
+            self.offered.lock().unwrap().push(self.name);
+            Ok(command == self.handles)
+        }
+    }
+
+    fn run(chain: &mut MxsProcessorChain<Record>, count: u32) -> (Vec<&'static str>, Vec<String>) {
+        let mut record = Record { count, seen: Vec::new() };
+        let errors = chain.process(&mut record);
+        (record.seen, errors.iter().map(|e| e.to_string()).collect())
+    }
+
+    #[test]
+    fn error_removes_only_that_processor() {
+        let mut chain = MxsProcessorChain::new();
+        chain.register(Step::new("a"));
+        chain.register(Step {
+            fail_on: Some(1),
+            ..Step::new("b")
+        });
+        chain.register(Step::new("c"));
+
+        assert_eq!(run(&mut chain, 0), (vec!["a", "b", "c"], vec![]));
+        assert_eq!(run(&mut chain, 1), (vec!["a", "c"], vec!["b disabled: failed on 1".into()]));
+        assert_eq!(chain.names().collect::<Vec<_>>(), ["a", "c"]);
+        assert_eq!(run(&mut chain, 2), (vec!["a", "c"], vec![]));
+    }
+
+    #[test]
+    fn stop_skips_the_rest() {
+        let mut chain = MxsProcessorChain::new();
+        chain.register(Step::new("a"));
+        chain.register(Step {
+            flow: MxsFlow::Stop,
+            ..Step::new("b")
+        });
+        chain.register(Step::new("c"));
+
+        assert_eq!(run(&mut chain, 0), (vec!["a", "b"], vec![]));
+        assert_eq!(chain.names().count(), 3);
+    }
+
+    #[test]
+    fn command_stops_at_first_handler() {
+        let offered = Arc::new(Mutex::new(Vec::new()));
+        let step = |name| Step {
+            offered: offered.clone(),
+            ..Step::new(name)
+        };
+
+        let mut chain = MxsProcessorChain::new();
+        chain.register(step("a"));
+        chain.register(Step {
+            handles: "reset",
+            ..step("b")
+        });
+        chain.register(Step {
+            handles: "reset",
+            ..step("c")
+        });
+
+        let (handled, errors) = chain.command("reset");
+        assert!(handled);
+        assert!(errors.is_empty());
+        assert_eq!(offered.lock().unwrap().drain(..).collect::<Vec<_>>(), ["a", "b"]);
+
+        let (handled, _) = chain.command("unknown");
+        assert!(!handled);
+        assert_eq!(*offered.lock().unwrap(), ["a", "b", "c"]);
+    }
+}
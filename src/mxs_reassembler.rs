@@ -0,0 +1,209 @@
+pub use crate::mxs_shared::*;
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                        MXS Reassembler
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+/// Largest message the reassembler accepts
+pub const MAX_MESSAGE_LEN: usize = 1 << 20;
+
+/// Why a message could not be reassembled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MxsIncompleteReason {
+    /// No fragment arrived within the timeout
+    Timeout,
+    /// A new message started with the same id
+    Restarted,
+    /// Message exceeded `MAX_MESSAGE_LEN`
+    Overflow,
+}
+
+/// Message dropped by the reassembler
+#[derive(Debug)]
+pub struct MxsIncompleteMessage {
+    pub message_id: u8,
+    pub fragments:  usize,
+    pub bytes:      usize,
+    pub reason:     MxsIncompleteReason,
+}
+
+struct MxsPendingMessage {
+    message_id: u8,
+    fragments:  BTreeMap<u16, Vec<u8>>,
+    last_index: Option<u16>,
+    bytes:      usize,
+    /// Arrival of the latest fragment
+    updated:    Instant,
+}
+
+impl MxsPendingMessage {
+    fn drop_as(self, reason: MxsIncompleteReason) -> MxsIncompleteMessage {
+        MxsIncompleteMessage {
+            message_id: self.message_id,
+            fragments: self.fragments.len(),
+            bytes: self.bytes,
+            reason,
+        }
+    }
+}
+
+/// Collects fragmented Data packets into complete messages
+///
+/// Fragments may arrive out of order. Incomplete messages are dropped once no fragment arrived
+/// for the timeout, so large messages aren't limited by it, and reported through `poll_incomplete`.
+pub struct MxsReassembler {
+    timeout:    Duration,
+    pending:    Vec<MxsPendingMessage>,
+    incomplete: Vec<MxsIncompleteMessage>,
+}
+
+impl MxsReassembler {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            pending: Vec::new(),
+            incomplete: Vec::new(),
+        }
+    }
+
+    /// Add a fragment, returning the message once all of its fragments were received
+    pub fn push(&mut self, fragment: &MxsFragment, data: &[u8]) -> Option<Vec<u8>> {
+        let pos = self
+            .pending
+            .iter()
+            .position(|m| m.message_id == fragment.message_id);
+
+        // A repeated fragment means the id was reused by a new message
+        let pos = match pos {
+            Some(pos) if self.pending[pos].fragments.contains_key(&fragment.index) => {
+                let dropped = self.pending.swap_remove(pos);
+                self.incomplete
+                    .push(dropped.drop_as(MxsIncompleteReason::Restarted));
+                None
+            }
+            pos => pos,
+        };
+
+        let pos = pos.unwrap_or_else(|| {
+            self.pending.push(MxsPendingMessage {
+                message_id: fragment.message_id,
+                fragments:  BTreeMap::new(),
+                last_index: None,
+                bytes:      0,
+                updated:    Instant::now(),
+            });
+            self.pending.len() - 1
+        });
+
+        let message = &mut self.pending[pos];
+        message.fragments.insert(fragment.index, data.to_vec());
+        message.bytes += data.len();
+        message.updated = Instant::now();
+
+        if fragment.last {
+            message.last_index = Some(fragment.index);
+        }
+
+        if message.bytes > MAX_MESSAGE_LEN {
+            let dropped = self.pending.swap_remove(pos);
+            self.incomplete
+                .push(dropped.drop_as(MxsIncompleteReason::Overflow));
+            return None;
+        }
+
+        // ---- Complete once every index up to the last one is present
+        let complete = message.last_index.is_some_and(|last| {
+            message.fragments.len() == last as usize + 1
+                && message.fragments.keys().next_back() == Some(&last)
+        });
+
+        if !complete {
+            return None;
+        }
+
+        let message = self.pending.swap_remove(pos);
+        let mut data = Vec::with_capacity(message.bytes);
+        for chunk in message.fragments.values() {
+            data.extend_from_slice(chunk);
+        }
+
+        Some(data)
+    }
+
+    /// Drop timed out messages and return every message dropped since the last call
+    pub fn poll_incomplete(&mut self) -> Vec<MxsIncompleteMessage> {
+        let timeout = self.timeout;
+
+        let mut i = 0;
+        while i < self.pending.len() {
+            if self.pending[i].updated.elapsed() >= timeout {
+                let dropped = self.pending.swap_remove(i);
+                self.incomplete
+                    .push(dropped.drop_as(MxsIncompleteReason::Timeout));
+            }
+            else {
+                i += 1;
+            }
+        }
+
+        std::mem::take(&mut self.incomplete)
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Tests
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fragment(message_id: u8, index: u16, last: bool) -> MxsFragment {
+        MxsFragment { message_id, index, last }
+    }
+
+    #[test]
+    fn reassemble_out_of_order() {
+        let mut reassembler = MxsReassembler::new(Duration::from_secs(1));
+
+        assert_eq!(reassembler.push(&fragment(1, 2, true), b"c"), None);
+        assert_eq!(reassembler.push(&fragment(1, 0, false), b"a"), None);
+        assert_eq!(reassembler.push(&fragment(1, 1, false), b"b"), Some(b"abc".to_vec()));
+        assert!(reassembler.poll_incomplete().is_empty());
+    }
+
+    #[test]
+    fn reassemble_restarted_message() {
+        let mut reassembler = MxsReassembler::new(Duration::from_secs(1));
+
+        reassembler.push(&fragment(1, 0, false), b"old");
+        reassembler.push(&fragment(1, 0, false), b"new");
+        assert_eq!(reassembler.push(&fragment(1, 1, true), b"!"), Some(b"new!".to_vec()));
+
+        let incomplete = reassembler.poll_incomplete();
+        assert_eq!(incomplete.len(), 1);
+        assert_eq!(incomplete[0].reason, MxsIncompleteReason::Restarted);
+        assert_eq!(incomplete[0].bytes, 3);
+    }
+
+    #[test]
+    fn reassembly_timeout_counts_from_latest_fragment() {
+        let mut reassembler = MxsReassembler::new(Duration::from_millis(100));
+
+        // Slower than the timeout overall, but never idle for that long
+        for index in 0..4 {
+            reassembler.push(&fragment(1, index, false), b"chunk");
+            std::thread::sleep(Duration::from_millis(40));
+            assert!(reassembler.poll_incomplete().is_empty(), "fragment {}", index);
+        }
+
+        std::thread::sleep(Duration::from_millis(100));
+        let incomplete = reassembler.poll_incomplete();
+        assert_eq!(incomplete.len(), 1);
+        assert_eq!(incomplete[0].reason, MxsIncompleteReason::Timeout);
+        assert_eq!(incomplete[0].fragments, 4);
+    }
+}
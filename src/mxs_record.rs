@@ -0,0 +1,260 @@
+pub use crate::mxs_shared::*;
+
+#[cfg(feature = "derive")]
+pub use mxs_derive::MxsRecord;
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                           MXS Records
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+/// Fixed layout payload sent as a tagged Data packet
+///
+/// Usually derived, so host and firmware share one definition:
+///
+/// ```ignore
+/// #[derive(MxsRecord)]
+/// #[mxs(id = 2, size = 5)]
+/// pub struct Battery {
+///     pub millivolts: u16,
+///     pub milliamps:  i16,
+///     pub charge:     u8,
+/// }
+/// ```
+///
+/// Fields are packed in declaration order, little-endian, without padding. The optional `size`
+/// is checked at compile time against the field sizes. A record must leave room for its id in the
+/// Data packet, larger ones fail to compile:
+///
+/// ```compile_fail,E0080
+/// use mxs_serial_link::mxs_record::MxsRecord;
+///
+/// #[derive(MxsRecord)]
+/// #[mxs(id = 1)]
+/// struct Oversized([u8; 255]);
+/// ```
+///
+/// Nothing checks record ids against each other, neither the derive nor `MxsTypeRegistry`, which
+/// only holds packet types. Records sent on the same link must have unique ids, assert it with
+/// `mxs_record_ids_unique`.
+pub trait MxsRecord: Sized {
+    /// Sent as the record id of the Data packet, unique among the records of a link
+    const RECORD_ID: u8;
+    /// Encoded size in bytes, at most `MAX_DATA_LEN - RECORD_ID_LEN`
+    const SIZE: usize;
+
+    /// Write the record into `buf`, which must be `SIZE` bytes long
+    fn encode(&self, buf: &mut [u8]);
+
+    /// Read a record, `None` when `buf` is not `SIZE` bytes long
+    fn decode(buf: &[u8]) -> Option<Self>;
+}
+
+/// Value that can be a field of a `MxsRecord`
+pub trait MxsField: Sized {
+    const SIZE: usize;
+
+    /// Write the value into `buf`, which is `SIZE` bytes long
+    fn write_le(&self, buf: &mut [u8]);
+
+    /// Read the value from `buf`, which is `SIZE` bytes long
+    fn read_le(buf: &[u8]) -> Self;
+}
+
+macro_rules! impl_mxs_field {
+    ($($ty:ty),*) => {
+        $(
+            impl MxsField for $ty {
+                const SIZE: usize = size_of::<$ty>();
+
+                #[inline]
+                fn write_le(&self, buf: &mut [u8]) {
+                    buf.copy_from_slice(&self.to_le_bytes());
+                }
+
+                #[inline]
+                fn read_le(buf: &[u8]) -> Self {
+                    Self::from_le_bytes(buf.try_into().unwrap())
+                }
+            }
+        )*
+    };
+}
+
+impl_mxs_field!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+impl MxsField for bool {
+    const SIZE: usize = 1;
+
+    #[inline]
+    fn write_le(&self, buf: &mut [u8]) {
+        buf[0] = *self as u8;
+    }
+
+    #[inline]
+    fn read_le(buf: &[u8]) -> Self {
+        buf[0] != 0
+    }
+}
+
+impl<T: MxsField, const N: usize> MxsField for [T; N] {
+    const SIZE: usize = T::SIZE * N;
+
+    fn write_le(&self, buf: &mut [u8]) {
+        for (value, chunk) in self.iter().zip(buf.chunks_exact_mut(T::SIZE)) {
+            value.write_le(chunk);
+        }
+    }
+
+    fn read_le(buf: &[u8]) -> Self {
+        core::array::from_fn(|i| T::read_le(&buf[i * T::SIZE..(i + 1) * T::SIZE]))
+    }
+}
+
+/// Compile time check for record ids used on the same link
+///
+/// Duplicate ids would decode one record as the other, list every record of the link:
+///
+/// ```ignore
+/// const _: () = assert!(mxs_record_ids_unique(&[Imu::RECORD_ID, Battery::RECORD_ID]));
+/// ```
+pub const fn mxs_record_ids_unique(ids: &[u8]) -> bool {
+    let mut i = 0;
+    while i < ids.len() {
+        let mut j = i + 1;
+        while j < ids.len() {
+            if ids[i] == ids[j] {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Tests
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[cfg(all(test, feature = "derive"))]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq, MxsRecord)]
+    #[mxs(id = 1, size = 35)]
+    struct Scalars {
+        a: u8,
+        b: u16,
+        c: u32,
+        d: u64,
+        e: i8,
+        f: i16,
+        g: i32,
+        h: i64,
+        i: f32,
+        j: bool,
+    }
+
+    #[derive(Debug, PartialEq, MxsRecord)]
+    #[mxs(id = 2, size = 37)]
+    struct Arrays([i16; 3], [[u8; 2]; 2], [f64; 2], [bool; 3], f64);
+
+    #[derive(Debug, PartialEq, MxsRecord)]
+    #[mxs(id = 3, size = 0)]
+    struct Empty;
+
+    /// Largest record that fits in a Data packet with its id
+    #[derive(Debug, PartialEq, MxsRecord)]
+    #[mxs(id = 4)]
+    struct Largest([u8; MAX_DATA_LEN - RECORD_ID_LEN]);
+
+    fn round_trip<R: MxsRecord + PartialEq + core::fmt::Debug>(record: &R) -> Vec<u8> {
+        let mut buf = vec![0u8; R::SIZE];
+        record.encode(&mut buf);
+        assert_eq!(R::decode(&buf).as_ref(), Some(record));
+        buf
+    }
+
+    #[test]
+    fn scalar_fields_round_trip() {
+        let record = Scalars {
+            a: 0xA1,
+            b: 0xB2B1,
+            c: 0xC4C3_C2C1,
+            d: 0xD8D7_D6D5_D4D3_D2D1,
+            e: -2,
+            f: -300,
+            g: -70_000,
+            h: i64::MIN,
+            i: 1.5,
+            j: true,
+        };
+        let buf = round_trip(&record);
+
+        // Declaration order, little-endian, no padding
+        assert_eq!(buf[..3], [0xA1, 0xB1, 0xB2]);
+        assert_eq!(buf[3..7], [0xC1, 0xC2, 0xC3, 0xC4]);
+        assert_eq!(buf[7..15], 0xD8D7_D6D5_D4D3_D2D1u64.to_le_bytes());
+        assert_eq!(buf[15], 0xFE);
+        assert_eq!(buf[16..18], (-300i16).to_le_bytes());
+        assert_eq!(buf[18..22], (-70_000i32).to_le_bytes());
+        assert_eq!(buf[22..30], i64::MIN.to_le_bytes());
+        assert_eq!(buf[30..34], 1.5f32.to_le_bytes());
+        assert_eq!(buf[34], 1);
+
+        round_trip(&Scalars::default());
+    }
+
+    #[test]
+    fn array_fields_round_trip() {
+        let record = Arrays(
+            [-1, 0, 1],
+            [[1, 2], [3, 4]],
+            [f64::MAX, -0.25],
+            [true, false, true],
+            f64::MIN_POSITIVE,
+        );
+        let buf = round_trip(&record);
+
+        assert_eq!(buf[..6], [0xFF, 0xFF, 0, 0, 1, 0]);
+        assert_eq!(buf[6..10], [1, 2, 3, 4]);
+        assert_eq!(buf[26..29], [1, 0, 1]);
+    }
+
+    #[test]
+    fn unit_record_round_trip() {
+        assert!(round_trip(&Empty).is_empty());
+    }
+
+    #[test]
+    fn decode_checks_length() {
+        assert_eq!(Scalars::decode(&[0; 34]), None);
+        assert_eq!(Scalars::decode(&[0; 36]), None);
+        assert_eq!(Empty::decode(&[0]), None);
+    }
+
+    #[test]
+    fn bool_reads_any_non_zero_byte() {
+        let mut buf = [0u8; 35];
+        buf[34] = 0x80;
+        assert!(Scalars::decode(&buf).unwrap().j);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn largest_record_through_decoder() {
+        use crate::mxs_decoder::MxsDecoder;
+        use crate::mxs_encoder::MxsEncoder;
+
+        let record = Largest(core::array::from_fn(|i| i as u8));
+        let config = MxsConfig::LEGACY;
+
+        let mut wire = Vec::new();
+        MxsEncoder::new(config).write_record(&record, |bytes| wire.extend_from_slice(bytes));
+
+        let result = MxsDecoder::filter_buffer(&wire, &config);
+        assert_eq!(result.packets.len(), 1);
+        assert_eq!(result.packets[0].record, Some(Largest::RECORD_ID));
+        assert_eq!(Largest::decode(&result.packets[0].data), Some(record));
+    }
+}
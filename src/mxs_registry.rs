@@ -0,0 +1,162 @@
+pub use crate::mxs_shared::*;
+
+use std::fmt;
+
+use anyhow::Result as AnyResult;
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                        MXS Type Registry
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+/// Turns the payload of a registered packet type into printable text
+pub type MxsPayloadDecoder = Box<dyn Fn(&[u8]) -> AnyResult<String> + Send + Sync>;
+
+/// Application defined packet type
+pub struct MxsPacketKind {
+    pub id:      u8,
+    pub name:    String,
+    pub decoder: MxsPayloadDecoder,
+}
+
+impl fmt::Debug for MxsPacketKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MxsPacketKind")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MxsRegistryError {
+    /// Id 0 or an id using the flags bit
+    Reserved(u8),
+    /// Id of a built-in packet type
+    Builtin(u8),
+    /// Id registered twice
+    Duplicate(u8),
+}
+
+impl fmt::Display for MxsRegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Reserved(id) => write!(f, "Packet type id {} is reserved", id),
+            Self::Builtin(id) => write!(f, "Packet type id {} is a built-in type", id),
+            Self::Duplicate(id) => write!(f, "Packet type id {} is already registered", id),
+        }
+    }
+}
+
+impl std::error::Error for MxsRegistryError {}
+
+/// Application packet types, decoded as `MxsPacketType::Unknown` by the MXS decoder
+#[derive(Debug, Default)]
+pub struct MxsTypeRegistry {
+    kinds: Vec<MxsPacketKind>,
+}
+
+impl MxsTypeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declare a packet type with a name and a payload decoder
+    pub fn register(
+        &mut self,
+        id: u8,
+        name: &str,
+        decoder: impl Fn(&[u8]) -> AnyResult<String> + Send + Sync + 'static,
+    ) -> Result<(), MxsRegistryError> {
+        if MxsPacketType::try_from(id).is_err() {
+            return Err(MxsRegistryError::Reserved(id));
+        }
+        if MxsPacketType::is_builtin(id) {
+            return Err(MxsRegistryError::Builtin(id));
+        }
+        if self.get(id).is_some() {
+            return Err(MxsRegistryError::Duplicate(id));
+        }
+
+        self.kinds.push(MxsPacketKind {
+            id,
+            name: name.to_string(),
+            decoder: Box::new(decoder),
+        });
+
+        Ok(())
+    }
+
+    pub fn get(&self, id: u8) -> Option<&MxsPacketKind> {
+        self.kinds.iter().find(|k| k.id == id)
+    }
+
+    /// Look up a type by name, for sending it
+    pub fn packet_type(&self, name: &str) -> Option<MxsPacketType> {
+        self.kinds
+            .iter()
+            .find(|k| k.name == name)
+            .map(|k| MxsPacketType::Unknown(k.id))
+    }
+
+    /// Registered ids, for the decoder
+    pub fn ids(&self) -> MxsTypeSet {
+        self.kinds
+            .iter()
+            .fold(MxsTypeSet::EMPTY, |set, k| set.with(k.id))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &MxsPacketKind> {
+        self.kinds.iter()
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Tests
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(payload: &[u8]) -> AnyResult<String> {
+        Ok(payload.iter().map(|b| format!("{:02X}", b)).collect())
+    }
+
+    #[test]
+    fn register_and_look_up() {
+        let mut registry = MxsTypeRegistry::new();
+        registry.register(0x10, "Log", hex).unwrap();
+        registry
+            .register(MxsPacketType::MAX_ID, "Last", hex)
+            .unwrap();
+
+        let kind = registry.get(0x10).unwrap();
+        assert_eq!(kind.name, "Log");
+        assert_eq!((kind.decoder)(&[0xAB, 0x01]).unwrap(), "AB01");
+
+        assert_eq!(
+            registry.packet_type("Last"),
+            Some(MxsPacketType::Unknown(MxsPacketType::MAX_ID))
+        );
+        assert_eq!(registry.packet_type("Missing"), None);
+
+        let ids = registry.ids();
+        assert!(ids.contains(0x10) && ids.contains(MxsPacketType::MAX_ID));
+        assert!(!ids.contains(0x11));
+    }
+
+    #[test]
+    fn register_rejects_bad_ids() {
+        let mut registry = MxsTypeRegistry::new();
+        registry.register(0x10, "Log", hex).unwrap();
+
+        assert_eq!(registry.register(0, "Zero", hex), Err(MxsRegistryError::Reserved(0)));
+        assert_eq!(registry.register(0x80, "High", hex), Err(MxsRegistryError::Reserved(0x80)));
+        assert_eq!(
+            registry.register(MxsPacketType::Ack.id(), "Ack", hex),
+            Err(MxsRegistryError::Builtin(MxsPacketType::Ack.id()))
+        );
+        assert_eq!(registry.register(0x10, "Again", hex), Err(MxsRegistryError::Duplicate(0x10)));
+        assert_eq!(registry.iter().count(), 1);
+    }
+}
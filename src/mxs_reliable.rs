@@ -0,0 +1,303 @@
+pub use crate::mxs_shared::*;
+
+use std::time::{Duration, Instant};
+
+use crate::mxs_decoder::MxsPacket;
+use crate::mxs_encoder::MxsEncoder;
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                          MXS Reliable
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+/// Retransmission settings for reliable commands
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MxsReliableConfig {
+    /// Time to wait for an Ack before retransmitting
+    pub timeout: Duration,
+    /// Retransmissions before giving up
+    pub retries: u8,
+}
+
+impl Default for MxsReliableConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_millis(300),
+            retries: 3,
+        }
+    }
+}
+
+/// Why a command was not delivered
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MxsDeliveryFailure {
+    /// No answer after all retries
+    Timeout,
+    /// Device rejected every attempt, with the last error code if provided
+    Nak(Option<u8>),
+}
+
+/// Outcome of a reliable command
+#[derive(Debug, Clone, PartialEq)]
+pub enum MxsDeliveryEvent {
+    /// Device acknowledged the command
+    Confirmed { seq: u8, attempts: u16 },
+    /// Command must be written again
+    Retransmit { seq: u8, packet: Vec<u8> },
+    /// Retries exhausted
+    Failed {
+        seq:      u8,
+        attempts: u16,
+        reason:   MxsDeliveryFailure,
+    },
+}
+
+struct MxsPendingCommand {
+    seq:      u8,
+    packet:   Vec<u8>,
+    /// Wider than the retries, so the last retry can't overflow it
+    attempts: u16,
+    sent:     Instant,
+    last_nak: Option<Option<u8>>,
+}
+
+/// Host side of the reliable command channel
+///
+/// Encodes Command packets with sequence numbers, matches the device Ack/Nak answers and decides
+/// when to retransmit. Writing the bytes to the port is left to the caller.
+pub struct MxsReliableSender {
+    config:  MxsReliableConfig,
+    encoder: MxsEncoder,
+    pending: Vec<MxsPendingCommand>,
+}
+
+impl MxsReliableSender {
+    /// Commands are always sent with sequence numbers, other settings follow the link
+    pub fn new(link_config: MxsConfig, config: MxsReliableConfig) -> Self {
+        let encoder = MxsEncoder::new(MxsConfig { seq: true, ..link_config });
+
+        Self {
+            config,
+            encoder,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Follow a renegotiated link, e.g. after the device sent a Start packet
+    pub fn set_link_config(&mut self, link_config: MxsConfig) {
+        self.encoder
+            .set_config(MxsConfig { seq: true, ..link_config });
+    }
+
+    /// Encode a command, returning its sequence number and the bytes to write
+    pub fn send(&mut self, command: &[u8]) -> (u8, Vec<u8>) {
+        let seq = self.encoder.next_seq();
+
+        let mut packet = Vec::new();
+        self.encoder
+            .write_data_package(MxsPacketType::Command, command, |bytes| {
+                packet.extend_from_slice(bytes)
+            });
+
+        self.pending.push(MxsPendingCommand {
+            seq,
+            packet: packet.clone(),
+            attempts: 1,
+            sent: Instant::now(),
+            last_nak: None,
+        });
+
+        (seq, packet)
+    }
+
+    /// Number of commands waiting for an answer
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// When the oldest unanswered command times out, `poll` should be called by then
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending
+            .iter()
+            .map(|c| c.sent + self.config.timeout)
+            .min()
+    }
+
+    /// Handle an Ack or Nak packet from the device
+    ///
+    /// Returns `None` for other packet types and for answers to unknown sequence numbers.
+    pub fn handle_packet(&mut self, packet: &MxsPacket) -> Option<MxsDeliveryEvent> {
+        if !matches!(packet.packet_type, MxsPacketType::Ack | MxsPacketType::Nak) {
+            return None;
+        }
+
+        let seq = *packet.data.first()?;
+        let pos = self.pending.iter().position(|c| c.seq == seq)?;
+
+        match packet.packet_type {
+            MxsPacketType::Ack => {
+                let command = self.pending.swap_remove(pos);
+
+                Some(MxsDeliveryEvent::Confirmed {
+                    seq,
+                    attempts: command.attempts,
+                })
+            }
+            MxsPacketType::Nak => {
+                let code = packet.data.get(1).copied();
+                self.pending[pos].last_nak = Some(code);
+
+                Some(self.retry(pos))
+            }
+            _ => None,
+        }
+    }
+
+    /// Check for commands that were not answered in time
+    pub fn poll(&mut self) -> Vec<MxsDeliveryEvent> {
+        let mut events = Vec::new();
+
+        let mut i = 0;
+        while i < self.pending.len() {
+            if self.pending[i].sent.elapsed() < self.config.timeout {
+                i += 1;
+                continue;
+            }
+
+            let event = self.retry(i);
+
+            // Failed commands were removed, retransmitted ones stay in place
+            if matches!(event, MxsDeliveryEvent::Retransmit { .. }) {
+                i += 1;
+            }
+            events.push(event);
+        }
+
+        events
+    }
+
+    /// Retransmit the command at `pos`, or give up once the retries are exhausted
+    fn retry(&mut self, pos: usize) -> MxsDeliveryEvent {
+        let command = &mut self.pending[pos];
+
+        if command.attempts > u16::from(self.config.retries) {
+            let command = self.pending.swap_remove(pos);
+            let reason = match command.last_nak {
+                Some(code) => MxsDeliveryFailure::Nak(code),
+                None => MxsDeliveryFailure::Timeout,
+            };
+
+            return MxsDeliveryEvent::Failed {
+                seq: command.seq,
+                attempts: command.attempts,
+                reason,
+            };
+        }
+
+        command.attempts += 1;
+        command.sent = Instant::now();
+
+        MxsDeliveryEvent::Retransmit {
+            seq:    command.seq,
+            packet: command.packet.clone(),
+        }
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Tests
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    fn answer(packet_type: MxsPacketType, data: &[u8]) -> MxsPacket<'_> {
+        MxsPacket {
+            packet_type,
+            seq: None,
+            fragment: None,
+            record: None,
+            data: Cow::Borrowed(data),
+        }
+    }
+
+    fn sender(timeout: Duration, retries: u8) -> MxsReliableSender {
+        MxsReliableSender::new(MxsConfig::LEGACY, MxsReliableConfig { timeout, retries })
+    }
+
+    #[test]
+    fn ack_confirms_command() {
+        let mut sender = sender(Duration::from_secs(60), 3);
+        let (seq, packet) = sender.send(b"led on");
+
+        assert_eq!(packet[2], MxsPacketType::Command.id() | TYPE_FLAGS_BIT);
+        assert_eq!(sender.pending(), 1);
+        assert!(sender.next_deadline().is_some());
+
+        // Answers to other sequence numbers are ignored
+        assert_eq!(sender.handle_packet(&answer(MxsPacketType::Ack, &[seq + 1])), None);
+
+        assert_eq!(
+            sender.handle_packet(&answer(MxsPacketType::Ack, &[seq])),
+            Some(MxsDeliveryEvent::Confirmed { seq, attempts: 1 })
+        );
+        assert_eq!(sender.pending(), 0);
+        assert_eq!(sender.next_deadline(), None);
+    }
+
+    #[test]
+    fn nak_retransmits_then_fails() {
+        let mut sender = sender(Duration::from_secs(60), 1);
+        let (seq, packet) = sender.send(b"reset");
+
+        assert_eq!(
+            sender.handle_packet(&answer(MxsPacketType::Nak, &[seq, 7])),
+            Some(MxsDeliveryEvent::Retransmit { seq, packet })
+        );
+        assert_eq!(
+            sender.handle_packet(&answer(MxsPacketType::Nak, &[seq, 9])),
+            Some(MxsDeliveryEvent::Failed {
+                seq,
+                attempts: 2,
+                reason: MxsDeliveryFailure::Nak(Some(9)),
+            })
+        );
+        assert_eq!(sender.pending(), 0);
+    }
+
+    #[test]
+    fn timeout_retransmits_then_fails() {
+        let mut sender = sender(Duration::ZERO, 2);
+        let (seq, _) = sender.send(b"ping");
+
+        for _ in 0..2 {
+            assert!(matches!(sender.poll()[..], [MxsDeliveryEvent::Retransmit { .. }]));
+        }
+        assert_eq!(sender.poll(), [MxsDeliveryEvent::Failed {
+            seq,
+            attempts: 3,
+            reason: MxsDeliveryFailure::Timeout,
+        }]);
+        assert!(sender.poll().is_empty());
+    }
+
+    #[test]
+    fn max_retries_terminate() {
+        let mut sender = sender(Duration::ZERO, u8::MAX);
+        let (seq, _) = sender.send(b"ping");
+
+        let mut retransmits = 0;
+        loop {
+            match sender.poll()[..] {
+                [MxsDeliveryEvent::Retransmit { .. }] => retransmits += 1,
+                [MxsDeliveryEvent::Failed { attempts, .. }] => {
+                    assert_eq!(attempts, 256);
+                    break;
+                }
+                ref other => panic!("unexpected events {:?} for #{}", other, seq),
+            }
+        }
+        assert_eq!(retransmits, 255);
+    }
+}
@@ -2,11 +2,45 @@
 //!
 //! A simple protocol for extracting structured packets from mixed ASCII/binary data streams.
 //! Commonly used for serial/USB communications where debug output and structured data coexist.
-//! No CRC performed
+//! Optional CRC-16/CCITT trailer, enabled per packet through the header flags
 //!
 //! Packet Structure:
 //! [MARKER:2][TYPE:1][LENGTH 0:1]
 //! [MARKER:2][TYPE:1][LENGTH N:1][DATA:N]
+//!
+//! Flagged Packet Structure (TYPE high bit set):
+//! [MARKER:2][TYPE|0x80:1][FLAGS:1][LENGTH N:1][DATA:N][CRC:2]
+//! [MARKER:2][TYPE|0x80:1][FLAGS:1][LENGTH N:2][DATA:N][CRC:2]            (FLAG_EXT_LEN)
+//! [MARKER:2][TYPE|0x80:1][FLAGS:1][SEQ:1][LENGTH N:1][DATA:N][CRC:2]     (FLAG_SEQ)
+//!
+//! Optional fields are only present when their flag is set.
+//!
+//! The CRC covers every byte after the marker. Multi-byte fields are little-endian.
+//! Packets without the flag bit are legacy packets and carry no CRC.
+//!
+//! Fragmented Data (FLAG_FRAGMENT), DATA starts with the fragment header:
+//! [MESSAGE ID:1][INDEX|LAST:2][CHUNK]
+//! The INDEX high bit marks the last fragment of the message.
+//!
+//! Start Handshake (device to host):
+//! Start - DATA: [VERSION:1][CAPABILITIES:1][IDENTITY:N]
+//! CAPABILITIES uses the header flag bits the device understands (CRC, EXT_LEN, SEQ). IDENTITY is
+//! UTF-8 text. An empty Start payload is sent by legacy devices.
+//!
+//! Tagged Records (FLAG_RECORD), DATA starts with the record id, after any fragment header:
+//! [RECORD ID:1][RECORD]
+//!
+//! Reliable Commands (host to device):
+//! Command packets carry a sequence number (FLAG_SEQ). The device answers with:
+//! Ack - DATA: [SEQ:1]
+//! Nak - DATA: [SEQ:1][CODE:1] (CODE optional)
+//!
+//! Framing:
+//! Plain   - bytes after the marker are sent as is
+//! Stuffed - every 0xAA after the marker is followed by a 0x00 fill byte, so the marker can never
+//!           appear inside a packet. Lengths and CRC refer to the unstuffed bytes.
+
+use core::fmt;
 
 // —————————————————————————————————————————————————————————————————————————————————————————————————
 //                                           MXS Protocol
@@ -16,21 +50,343 @@ pub const MARKER: &[u8] = &[0xAA, 0x55];
 
 pub const MARKER_LEN: usize = MARKER.len();
 pub const TYPE_LEN: usize = 1;
+pub const FLAGS_LEN: usize = 1;
+pub const SEQ_LEN: usize = 1;
 pub const SIZE_LEN: usize = 1;
+pub const EXT_SIZE_LEN: usize = 2;
+pub const CRC_LEN: usize = 2;
+pub const MAX_HEADER_LEN: usize = TYPE_LEN + FLAGS_LEN + SEQ_LEN + EXT_SIZE_LEN;
 
 pub const MAX_DATA_LEN: usize = (1usize << (SIZE_LEN * 8)) - 1;
 pub const MIN_PACKET_SIZE: usize = MARKER_LEN + TYPE_LEN + SIZE_LEN;
-pub const MAX_PACKET_SIZE: usize = MARKER_LEN + TYPE_LEN + SIZE_LEN + MAX_DATA_LEN;
+pub const MAX_PACKET_SIZE: usize =
+    MARKER_LEN + TYPE_LEN + FLAGS_LEN + SEQ_LEN + SIZE_LEN + MAX_DATA_LEN + CRC_LEN;
+
+/// Extended length packets
+pub const MAX_EXT_DATA_LEN: usize = (1usize << (EXT_SIZE_LEN * 8)) - 1;
+pub const MAX_EXT_PACKET_SIZE: usize = MARKER_LEN + MAX_HEADER_LEN + MAX_EXT_DATA_LEN + CRC_LEN;
+
+/// Worst case on the wire: every byte after the marker stuffed
+pub const MAX_WIRE_SIZE: usize = MARKER_LEN + (MAX_PACKET_SIZE - MARKER_LEN) * 2;
+pub const MAX_EXT_WIRE_SIZE: usize = MARKER_LEN + (MAX_EXT_PACKET_SIZE - MARKER_LEN) * 2;
+
+/// Stuffed framing escape byte (first marker byte)
+pub const STUFF_ESCAPE: u8 = MARKER[0];
+/// Stuffed framing fill byte, sent after every escape byte
+pub const STUFF_FILL: u8 = 0x00;
+
+/// Set on the TYPE byte when a FLAGS byte follows it
+pub const TYPE_FLAGS_BIT: u8 = 0x80;
+
+/// Packet carries a CRC-16 trailer
+pub const FLAG_CRC: u8 = 0x01;
+/// Packet uses a 2 byte length field
+pub const FLAG_EXT_LEN: u8 = 0x02;
+/// Payload starts with a fragment header
+pub const FLAG_FRAGMENT: u8 = 0x04;
+/// Header carries a sequence number
+pub const FLAG_SEQ: u8 = 0x08;
+/// Payload starts with a record id
+pub const FLAG_RECORD: u8 = 0x10;
+
+/// All flags understood by this implementation
+pub const KNOWN_FLAGS: u8 = FLAG_CRC | FLAG_EXT_LEN | FLAG_FRAGMENT | FLAG_SEQ | FLAG_RECORD;
+
+pub const RECORD_ID_LEN: usize = 1;
+
+/// Legacy protocol: no header flags, no CRC
+pub const MXS_LEGACY_VERSION: u8 = 1;
+/// Current protocol: flagged header with CRC and sequence numbers
+pub const MXS_PROTOCOL_VERSION: u8 = 2;
+
+// ———————————————————————————————————————————— Config —————————————————————————————————————————————
+
+/// Wire framing of the bytes following the marker
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum MxsFraming {
+    #[default]
+    Plain,
+    Stuffed,
+}
+
+/// Per-link protocol settings
+///
+/// The decoder detects the packet features from the header flags, so `crc`, `seq` and `ext_len`
+/// only affect what the encoder emits. The framing must match on both ends.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MxsConfig {
+    pub crc:     bool,
+    pub seq:     bool,
+    /// Allow payloads larger than `MAX_DATA_LEN`
+    pub ext_len: bool,
+    pub framing: MxsFraming,
+}
+
+impl MxsConfig {
+    pub const LEGACY: Self = Self {
+        crc:     false,
+        seq:     false,
+        ext_len: false,
+        framing: MxsFraming::Plain,
+    };
+
+    /// Settings for a negotiated protocol version
+    pub const fn for_version(version: u8, framing: MxsFraming) -> Self {
+        Self {
+            crc: version >= MXS_PROTOCOL_VERSION,
+            seq: version >= MXS_PROTOCOL_VERSION,
+            ext_len: version >= MXS_PROTOCOL_VERSION,
+            framing,
+        }
+    }
+
+    /// Capability bits advertised in the Start payload
+    pub const fn capabilities(self) -> u8 {
+        let mut caps = 0;
+        if self.crc {
+            caps |= FLAG_CRC;
+        }
+        if self.ext_len {
+            caps |= FLAG_EXT_LEN;
+        }
+        if self.seq {
+            caps |= FLAG_SEQ;
+        }
+        caps
+    }
+
+    /// Restrict these settings to the features the device advertised
+    pub fn negotiate(self, device: &MxsDeviceInfo) -> Result<Self, MxsHandshakeError> {
+        if device.version < MXS_LEGACY_VERSION || device.version > MXS_PROTOCOL_VERSION {
+            return Err(MxsHandshakeError::UnsupportedVersion(device.version));
+        }
+
+        Ok(Self {
+            crc:     self.crc && device.has(FLAG_CRC),
+            seq:     self.seq && device.has(FLAG_SEQ),
+            ext_len: self.ext_len && device.has(FLAG_EXT_LEN),
+            framing: self.framing,
+        })
+    }
+}
+
+// ————————————————————————————————————————————— CRC ———————————————————————————————————————————————
+
+pub const CRC16_INIT: u16 = 0xFFFF;
+const CRC16_POLY: u16 = 0x1021;
+
+/// CRC-16/CCITT-FALSE (poly 0x1021, init 0xFFFF)
+#[inline]
+pub const fn crc16_ccitt(data: &[u8]) -> u16 {
+    crc16_ccitt_update(CRC16_INIT, data)
+}
+
+/// Continue a CRC-16/CCITT computation over more data
+pub const fn crc16_ccitt_update(mut crc: u16, data: &[u8]) -> u16 {
+    let mut i = 0;
+    while i < data.len() {
+        crc ^= (data[i] as u16) << 8;
+
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ CRC16_POLY } else { crc << 1 };
+            bit += 1;
+        }
+        i += 1;
+    }
+    crc
+}
+
+// ——————————————————————————————————————————— Fragment ————————————————————————————————————————————
+
+pub const FRAGMENT_HEADER_LEN: usize = 3;
+pub const FRAGMENT_LAST_BIT: u16 = 0x8000;
+pub const MAX_FRAGMENT_INDEX: u16 = FRAGMENT_LAST_BIT - 1;
+
+/// Fragment header of a message split over several Data packets
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MxsFragment {
+    pub message_id: u8,
+    pub index:      u16,
+    pub last:       bool,
+}
+
+impl MxsFragment {
+    pub const fn to_bytes(self) -> [u8; FRAGMENT_HEADER_LEN] {
+        let index = if self.last { self.index | FRAGMENT_LAST_BIT } else { self.index };
+        let index = index.to_le_bytes();
+
+        [self.message_id, index[0], index[1]]
+    }
+
+    pub const fn from_bytes(bytes: [u8; FRAGMENT_HEADER_LEN]) -> Self {
+        let index = u16::from_le_bytes([bytes[1], bytes[2]]);
+
+        Self {
+            message_id: bytes[0],
+            index:      index & MAX_FRAGMENT_INDEX,
+            last:       index & FRAGMENT_LAST_BIT != 0,
+        }
+    }
+}
+
+// ——————————————————————————————————————————— Handshake ———————————————————————————————————————————
+
+pub const START_HEADER_LEN: usize = 2;
+/// Longest identity that fits a standard size Start packet
+pub const MAX_IDENTITY_LEN: usize = MAX_DATA_LEN - START_HEADER_LEN;
+
+/// Device description carried by the Start packet
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MxsDeviceInfo<'a> {
+    pub version:      u8,
+    pub capabilities: u8,
+    pub identity:     &'a str,
+}
+
+impl<'a> MxsDeviceInfo<'a> {
+    /// What an empty Start payload stands for
+    pub const LEGACY: MxsDeviceInfo<'static> = MxsDeviceInfo {
+        version:      MXS_LEGACY_VERSION,
+        capabilities: 0,
+        identity:     "",
+    };
+
+    pub fn from_payload(data: &'a [u8]) -> Result<Self, MxsHandshakeError> {
+        let [version, capabilities, identity @ ..] = data
+        else {
+            return match data.is_empty() {
+                true => Ok(MxsDeviceInfo::LEGACY),
+                false => Err(MxsHandshakeError::Truncated),
+            };
+        };
+
+        let identity =
+            core::str::from_utf8(identity).map_err(|_| MxsHandshakeError::InvalidIdentity)?;
+
+        Ok(Self {
+            version: *version,
+            capabilities: *capabilities,
+            identity,
+        })
+    }
+
+    /// Whether the device advertised a capability (one of the header `FLAG_*` bits)
+    #[inline]
+    pub const fn has(&self, capability: u8) -> bool {
+        self.capabilities & capability == capability
+    }
+}
+
+impl fmt::Display for MxsDeviceInfo<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let identity = if self.identity.is_empty() {
+            "Unnamed device"
+        }
+        else {
+            self.identity
+        };
+        write!(f, "{} - MXS v{}", identity, self.version)?;
+
+        for (flag, name) in [
+            (FLAG_CRC, "crc"),
+            (FLAG_EXT_LEN, "ext-len"),
+            (FLAG_SEQ, "seq"),
+        ] {
+            if self.has(flag) {
+                write!(f, " +{}", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MxsHandshakeError {
+    /// Start payload shorter than its header
+    Truncated,
+    /// Identity is not UTF-8
+    InvalidIdentity,
+    /// Device speaks a protocol version this implementation does not know
+    UnsupportedVersion(u8),
+}
+
+impl fmt::Display for MxsHandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "Start packet is truncated"),
+            Self::InvalidIdentity => write!(f, "Device identity is not valid UTF-8"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "Device uses MXS protocol v{}, supported versions are v{} to v{}",
+                version, MXS_LEGACY_VERSION, MXS_PROTOCOL_VERSION
+            ),
+        }
+    }
+}
+
+impl core::error::Error for MxsHandshakeError {}
+
+// ————————————————————————————————————————— Packet Types ——————————————————————————————————————————
 
 /// Protocol Packet Types
-#[derive(Debug, PartialEq)]
-#[repr(u8)]
+///
+/// Ids without a built-in meaning are decoded as `Unknown`. Applications can name them and attach
+/// a payload decoder through a `MxsTypeRegistry`.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MxsPacketType {
-    Start     = 1,
-    End       = 2,
-    Heartbeat = 3,
-    Data      = 4,
-    Error     = 5,
+    Start,
+    End,
+    Heartbeat,
+    Data,
+    Error,
+    Command,
+    Ack,
+    Nak,
+    Unknown(u8),
+}
+
+impl MxsPacketType {
+    /// Types with a built-in meaning
+    pub const BUILTIN: [Self; 8] = [
+        Self::Start,
+        Self::End,
+        Self::Heartbeat,
+        Self::Data,
+        Self::Error,
+        Self::Command,
+        Self::Ack,
+        Self::Nak,
+    ];
+
+    /// Largest id that fits next to the flags bit. Id 0 is reserved.
+    pub const MAX_ID: u8 = !TYPE_FLAGS_BIT;
+
+    /// Id sent in the TYPE byte
+    pub const fn id(self) -> u8 {
+        match self {
+            Self::Start => 1,
+            Self::End => 2,
+            Self::Heartbeat => 3,
+            Self::Data => 4,
+            Self::Error => 5,
+            Self::Command => 6,
+            Self::Ack => 7,
+            Self::Nak => 8,
+            Self::Unknown(id) => id,
+        }
+    }
+
+    /// Whether the id belongs to a built-in type
+    pub fn is_builtin(id: u8) -> bool {
+        Self::BUILTIN.iter().any(|t| t.id() == id)
+    }
+}
+
+impl From<MxsPacketType> for u8 {
+    fn from(value: MxsPacketType) -> Self {
+        value.id()
+    }
 }
 
 impl TryFrom<u8> for MxsPacketType {
@@ -38,12 +394,189 @@ impl TryFrom<u8> for MxsPacketType {
 
     fn try_from(value: u8) -> core::result::Result<Self, <MxsPacketType as TryFrom<u8>>::Error> {
         match value {
-            v if v == Self::Start as u8 => Ok(Self::Start),
-            v if v == Self::End as u8 => Ok(Self::End),
-            v if v == Self::Heartbeat as u8 => Ok(Self::Heartbeat),
-            v if v == Self::Data as u8 => Ok(Self::Data),
-            v if v == Self::Error as u8 => Ok(Self::Error),
-            _ => Err(()),
+            0 => Err(()),
+            v if v > Self::MAX_ID => Err(()),
+            v => Ok(Self::BUILTIN
+                .into_iter()
+                .find(|t| t.id() == v)
+                .unwrap_or(Self::Unknown(v))),
+        }
+    }
+}
+
+/// Set of application packet type ids
+///
+/// Without a CRC, the decoder only accepts `Unknown` packets whose id is in this set. Anything else
+/// is treated as a false marker, so plain text containing the marker bytes isn't swallowed.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MxsTypeSet(u128);
+
+impl MxsTypeSet {
+    pub const EMPTY: Self = Self(0);
+
+    /// Add an id, ids above `MxsPacketType::MAX_ID` are ignored
+    pub const fn with(self, id: u8) -> Self {
+        if id > MxsPacketType::MAX_ID {
+            return self;
         }
+        Self(self.0 | 1 << id)
+    }
+
+    pub const fn contains(self, id: u8) -> bool {
+        id <= MxsPacketType::MAX_ID && self.0 & 1 << id != 0
+    }
+}
+
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+//                                              Tests
+// —————————————————————————————————————————————————————————————————————————————————————————————————
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn type_set_contains() {
+        let set = MxsTypeSet::EMPTY
+            .with(0x10)
+            .with(MxsPacketType::MAX_ID)
+            .with(0xFF);
+
+        assert!(set.contains(0x10));
+        assert!(set.contains(MxsPacketType::MAX_ID));
+        assert!(!set.contains(0x11));
+        assert!(!set.contains(0xFF));
+        assert!(!MxsTypeSet::EMPTY.contains(0x10));
+    }
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(crc16_ccitt(b"123456789"), 0x29B1);
+        assert_eq!(crc16_ccitt(b""), CRC16_INIT);
+    }
+
+    #[test]
+    fn crc16_update_matches_whole() {
+        let crc = crc16_ccitt_update(crc16_ccitt(b"1234"), b"56789");
+        assert_eq!(crc, crc16_ccitt(b"123456789"));
+    }
+
+    #[test]
+    fn fragment_header_round_trip() {
+        for fragment in [
+            MxsFragment {
+                message_id: 0,
+                index:      0,
+                last:       false,
+            },
+            MxsFragment {
+                message_id: 7,
+                index:      0x1234,
+                last:       true,
+            },
+            MxsFragment {
+                message_id: 255,
+                index:      MAX_FRAGMENT_INDEX,
+                last:       true,
+            },
+        ] {
+            assert_eq!(MxsFragment::from_bytes(fragment.to_bytes()), fragment);
+        }
+    }
+
+    #[test]
+    fn start_payloads() {
+        let cases: [(&[u8], Result<MxsDeviceInfo, MxsHandshakeError>); 5] = [
+            (b"", Ok(MxsDeviceInfo::LEGACY)),
+            (&[2], Err(MxsHandshakeError::Truncated)),
+            (
+                &[2, FLAG_SEQ],
+                Ok(MxsDeviceInfo {
+                    version:      2,
+                    capabilities: FLAG_SEQ,
+                    identity:     "",
+                }),
+            ),
+            (
+                b"\x02\x01imu",
+                Ok(MxsDeviceInfo {
+                    version:      2,
+                    capabilities: FLAG_CRC,
+                    identity:     "imu",
+                }),
+            ),
+            (&[2, 0, 0xFF, 0xFE], Err(MxsHandshakeError::InvalidIdentity)),
+        ];
+
+        for (payload, expected) in cases {
+            assert_eq!(MxsDeviceInfo::from_payload(payload), expected, "{:?}", payload);
+        }
+    }
+
+    #[test]
+    fn negotiate() {
+        let host = MxsConfig::for_version(MXS_PROTOCOL_VERSION, MxsFraming::Stuffed);
+        let device = |version, capabilities| MxsDeviceInfo {
+            version,
+            capabilities,
+            identity: "",
+        };
+
+        // Only the features both sides have
+        let config = host.negotiate(&device(2, FLAG_SEQ)).unwrap();
+        assert_eq!(config, MxsConfig {
+            crc:     false,
+            seq:     true,
+            ext_len: false,
+            framing: MxsFraming::Stuffed,
+        });
+
+        let config = host
+            .negotiate(&device(2, FLAG_CRC | FLAG_SEQ | FLAG_EXT_LEN))
+            .unwrap();
+        assert_eq!(config, host);
+
+        // The device can't enable what the host doesn't use
+        let host = MxsConfig { seq: false, ..host };
+        let config = host.negotiate(&device(2, FLAG_CRC | FLAG_SEQ)).unwrap();
+        assert!(config.crc && !config.seq);
+
+        let config = host.negotiate(&MxsDeviceInfo::LEGACY).unwrap();
+        assert_eq!(config, MxsConfig {
+            framing: MxsFraming::Stuffed,
+            ..MxsConfig::LEGACY
+        });
+
+        for version in [0, MXS_PROTOCOL_VERSION + 1] {
+            assert_eq!(
+                host.negotiate(&device(version, 0)),
+                Err(MxsHandshakeError::UnsupportedVersion(version))
+            );
+        }
+    }
+
+    #[test]
+    fn start_packet_round_trip() {
+        let config = MxsConfig {
+            crc:     true,
+            seq:     true,
+            ext_len: false,
+            framing: MxsFraming::Plain,
+        };
+        let mut encoder = crate::mxs_encoder::MxsEncoder::new(config);
+
+        let mut packet = Vec::new();
+        encoder.write_start("imu-board", |bytes| packet.extend_from_slice(bytes));
+
+        // Marker, type, flags, sequence and size before the payload, CRC after it
+        let payload = &packet[MARKER_LEN + 4..packet.len() - CRC_LEN];
+        assert_eq!(
+            MxsDeviceInfo::from_payload(payload),
+            Ok(MxsDeviceInfo {
+                version:      MXS_PROTOCOL_VERSION,
+                capabilities: FLAG_CRC | FLAG_SEQ,
+                identity:     "imu-board",
+            })
+        );
     }
 }
@@ -26,6 +26,9 @@ use termios::{ECHO, ICANON, TCSADRAIN, Termios};
 pub const DEBUG: bool = false;
 pub const TERM_PADDED_LINES: u16 = 2;
 
+/// Lines reserved by `set_panel_height`
+static mut PANEL_HEIGHT: u16 = 0;
+
 // —————————————————————————————————————————————————————————————————————————————————————————————————
 //                                             Macros
 // —————————————————————————————————————————————————————————————————————————————————————————————————
@@ -69,25 +72,34 @@ macro_rules! ctrl_c_init {
 // —————————————————————————————————————————————————————————————————————————————————————————————————
 
 /// Handling raw STD input with history
+///
+/// Returns the key presses the input bar does not use (function keys, Tab, ...), so the caller can
+/// bind them.
+///
 /// Example:
-/// ```
+/// ```no_run
+/// # use mxs_serial_link::stdio_helper::read_raw_stdin_input;
+/// # fn main() -> std::io::Result<()> {
 /// let mut std_input = String::new();
-/// ...
 ///
 /// // In a loop:
-/// read_stdin_input(&mut std_input)?;
+/// read_raw_stdin_input(&mut std_input)?;
 ///
 /// // Detect new line in input buffer
 /// if std_input.ends_with('\n') {
-///     println!("{}", std_input) // Print the input line
+///     println!("{}", std_input); // Print the input line
 ///     std_input.clear();
 /// }
+/// # Ok(())
+/// # }
 /// ```
 ///  
-pub fn read_raw_stdin_input(input: &mut String) -> Result<(), io::Error> {
+pub fn read_raw_stdin_input(input: &mut String) -> Result<Vec<event::KeyEvent>, io::Error> {
     //
     const CTRL: event::KeyModifiers = event::KeyModifiers::CONTROL;
 
+    let mut unused_keys = Vec::new();
+
     static mut HISTORY: VecDeque<String> = VecDeque::<String>::new();
     static mut SCROLL_POS: usize = 0;
 
@@ -157,23 +169,25 @@ pub fn read_raw_stdin_input(input: &mut String) -> Result<(), io::Error> {
                         input.push(char);
                     }
                     // Any
-                    _ => {}
+                    _ => {
+                        unused_keys.push(key_event);
+                    }
                 }
             }
         }
     }
 
-    Ok(())
+    Ok(unused_keys)
 }
 
 // —————————————————————————————————————————— Input Bar ————————————————————————————————————————————
 
 /// Example:
-/// ```
+/// ```no_run
+/// # use crossterm::style::Stylize;
+/// # use mxs_serial_link::stdio_helper::print_input_bar;
 /// let input_prefix = "INPUT";
-/// let mut input = String::new();
-///
-/// input = "Test Message".into();
+/// let input = String::from("Test Message");
 ///
 /// let status_bar_msg =
 ///     format_args!("{} {} {}", input_prefix.red(), ">>:".green(), input.clone().blue())
@@ -195,6 +209,65 @@ pub fn print_input_bar(status_bar_msg: &str) {
     stdout.execute(cursor::RestorePosition);
 }
 
+// ————————————————————————————————————————————— Panel —————————————————————————————————————————————
+
+/// Reserve `height` lines between the scrolling output and the input bar
+///
+/// Output above is scrolled up to make room, so the panel doesn't cover it.
+pub fn set_panel_height(height: u16) {
+    let panel_height = unsafe { &mut *&raw mut PANEL_HEIGHT };
+
+    let mut stdout = std::io::stdout();
+    let (_cols, rows) = terminal::size().unwrap();
+
+    // Keep at least one line of output, the panel is hidden when the terminal is too small.
+    // The old height is clamped too, the terminal may have shrunk since it was set
+    let max_height = rows.saturating_sub(TERM_PADDED_LINES + 1);
+    let height = height.min(max_height);
+    let old_height = (*panel_height).min(max_height);
+    if height == *panel_height {
+        return;
+    }
+
+    // Push output up out of the growing panel, cursor is at the bottom of the scroll region
+    if height > old_height {
+        print!("{}", "\n".repeat((height - old_height) as usize));
+    }
+
+    // Clear the old and new panel area
+    let top = rows.saturating_sub(TERM_PADDED_LINES + height.max(old_height));
+    for row in top..rows.saturating_sub(1) {
+        stdout.queue(cursor::MoveTo(0, row));
+        stdout.queue(terminal::Clear(terminal::ClearType::CurrentLine));
+    }
+
+    let bottom = rows.saturating_sub(TERM_PADDED_LINES + height);
+    print!("\x1b[{};{}r", 0, bottom); // Set scrollable region, moves the cursor home
+    stdout.execute(cursor::MoveTo(0, bottom.saturating_sub(1))); // Move back to the bottom of the region
+
+    *panel_height = height;
+}
+
+/// Draw the panel reserved by `set_panel_height`, one entry per line
+///
+/// Lines that don't fit the reserved height are left out.
+pub fn print_panel(lines: &[String]) {
+    let panel_height = unsafe { *&raw const PANEL_HEIGHT };
+
+    let mut stdout = std::io::stdout();
+    let (_cols, rows) = terminal::size().unwrap();
+
+    let top = rows.saturating_sub(TERM_PADDED_LINES + panel_height);
+
+    stdout.queue(cursor::SavePosition);
+    for (row, line) in (top..).zip(lines.iter().take(panel_height as usize)) {
+        stdout.queue(cursor::MoveTo(0, row));
+        stdout.queue(terminal::Clear(terminal::ClearType::CurrentLine));
+        stdout.write_all(line.as_bytes());
+    }
+    stdout.execute(cursor::RestorePosition);
+}
+
 // ———————————————————————————————————————————— Init ———————————————————————————————————————————————
 
 /// Init Terminal
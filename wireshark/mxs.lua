@@ -0,0 +1,348 @@
+-- MXS Protocol dissector for captures exported with `mxs export`
+--
+-- Install: copy to the Wireshark personal Lua plugins folder
+-- (Help > About Wireshark > Folders), then reload with Ctrl+Shift+L.
+--
+-- Packets are read from the LINKTYPE_USER0 (DLT 147) interface. Each frame is one chunk read from
+-- or written to the serial port, so it can hold several packets, debug text between them, or the
+-- start of a packet continued in the next frame. The framing is taken from the interface
+-- description written by the export, or forced with the "Framing" preference.
+--
+-- Packet layout, see `mxs_shared.rs`:
+--   [MARKER:2][TYPE:1][LENGTH:1][DATA]
+--   [MARKER:2][TYPE|0x80:1][FLAGS:1][SEQ:1]?[LENGTH:1|2][DATA][CRC:2]?
+
+local mxs = Proto("mxs", "MXS Protocol")
+
+local MARKER_0 = 0xAA
+local MARKER_1 = 0x55
+local STUFF_ESCAPE = 0xAA
+
+local TYPE_FLAGS_BIT = 0x80
+
+local FLAG_CRC = 0x01
+local FLAG_EXT_LEN = 0x02
+local FLAG_FRAGMENT = 0x04
+local FLAG_SEQ = 0x08
+local FLAG_RECORD = 0x10
+
+local TYPE_START = 1
+local TYPE_ERROR = 5
+local TYPE_COMMAND = 6
+local TYPE_ACK = 7
+local TYPE_NAK = 8
+
+local packet_types = {
+    [1] = "Start",
+    [2] = "End",
+    [3] = "Heartbeat",
+    [4] = "Data",
+    [5] = "Error",
+    [6] = "Command",
+    [7] = "Ack",
+    [8] = "Nak",
+}
+
+-- ————————————————————————————————————————————— Fields ——————————————————————————————————————————————
+
+local f = {
+    packet       = ProtoField.bytes("mxs.packet", "Packet"),
+    marker       = ProtoField.bytes("mxs.marker", "Marker"),
+    type         = ProtoField.uint8("mxs.type", "Type", base.DEC, packet_types, 0x7F),
+    flagged      = ProtoField.bool("mxs.type.flagged", "Flags present", 8, nil, TYPE_FLAGS_BIT),
+    flags        = ProtoField.uint8("mxs.flags", "Flags", base.HEX),
+    flag_crc     = ProtoField.bool("mxs.flags.crc", "CRC", 8, nil, FLAG_CRC),
+    flag_ext_len = ProtoField.bool("mxs.flags.ext_len", "Extended length", 8, nil, FLAG_EXT_LEN),
+    flag_frag    = ProtoField.bool("mxs.flags.fragment", "Fragment", 8, nil, FLAG_FRAGMENT),
+    flag_seq     = ProtoField.bool("mxs.flags.seq", "Sequence number", 8, nil, FLAG_SEQ),
+    flag_record  = ProtoField.bool("mxs.flags.record", "Record", 8, nil, FLAG_RECORD),
+    seq          = ProtoField.uint8("mxs.seq", "Sequence", base.DEC),
+    length       = ProtoField.uint16("mxs.length", "Length", base.DEC),
+    payload      = ProtoField.bytes("mxs.payload", "Payload"),
+    message_id   = ProtoField.uint8("mxs.fragment.message_id", "Message id", base.DEC),
+    frag_index   = ProtoField.uint16("mxs.fragment.index", "Fragment index", base.DEC, nil, 0x7FFF),
+    frag_last    = ProtoField.bool("mxs.fragment.last", "Last fragment", 16, nil, 0x8000),
+    record_id    = ProtoField.uint8("mxs.record_id", "Record id", base.DEC),
+    version      = ProtoField.uint8("mxs.start.version", "Protocol version", base.DEC),
+    capabilities = ProtoField.uint8("mxs.start.capabilities", "Capabilities", base.HEX),
+    identity     = ProtoField.string("mxs.start.identity", "Identity"),
+    ack_seq      = ProtoField.uint8("mxs.ack.seq", "Acknowledged sequence", base.DEC),
+    nak_code     = ProtoField.uint8("mxs.nak.code", "Nak code", base.DEC),
+    text         = ProtoField.string("mxs.text", "Text"),
+    crc          = ProtoField.uint16("mxs.crc", "CRC", base.HEX),
+    stream       = ProtoField.string("mxs.stream", "Stream text"),
+}
+
+local e = {
+    truncated = ProtoExpert.new("mxs.truncated", "Packet continues in the next frame",
+        expert.group.REASSEMBLE, expert.severity.NOTE),
+    bad_crc = ProtoExpert.new("mxs.crc.bad", "Bad CRC",
+        expert.group.CHECKSUM, expert.severity.ERROR),
+}
+
+mxs.fields = f
+mxs.experts = e
+
+local FRAMING_AUTO = 0
+local FRAMING_PLAIN = 1
+local FRAMING_STUFFED = 2
+
+mxs.prefs.framing = Pref.enum("Framing", FRAMING_AUTO, "Framing of the bytes after the marker", {
+    { 1, "Auto (interface description)", FRAMING_AUTO },
+    { 2, "Plain", FRAMING_PLAIN },
+    { 3, "Stuffed", FRAMING_STUFFED },
+}, false)
+
+local interface_description = Field.new("frame.interface_description")
+
+-- ———————————————————————————————————————————— Helpers —————————————————————————————————————————————
+
+local function is_stuffed()
+    if mxs.prefs.framing ~= FRAMING_AUTO then
+        return mxs.prefs.framing == FRAMING_STUFFED
+    end
+    local description = interface_description()
+    return description ~= nil and string.find(tostring(description.value), "stuffed", 1, true) ~= nil
+end
+
+-- CRC-16/CCITT-FALSE (poly 0x1021, init 0xFFFF)
+local function crc16_ccitt(bytes, offset, len)
+    local crc = 0xFFFF
+    for i = offset, offset + len - 1 do
+        crc = bit.bxor(crc, bit.lshift(bytes:get_index(i), 8))
+        for _ = 1, 8 do
+            if bit.band(crc, 0x8000) ~= 0 then
+                crc = bit.band(bit.bxor(bit.lshift(crc, 1), 0x1021), 0xFFFF)
+            else
+                crc = bit.band(bit.lshift(crc, 1), 0xFFFF)
+            end
+        end
+    end
+    return crc
+end
+
+local function find_marker(wire, from)
+    for i = from, wire:len() - 2 do
+        if wire:get_index(i) == MARKER_0 and wire:get_index(i + 1) == MARKER_1 then
+            return i
+        end
+    end
+    return nil
+end
+
+-- Read `count` packet bytes from the wire at `pos`, removing the stuffing fill bytes.
+-- Returns the bytes and the wire position after them, nil when the frame ends first.
+local function read_body(wire, pos, count, stuffed)
+    local out = ByteArray.new()
+    if count > 0 then
+        out:set_size(count)
+    end
+
+    for i = 0, count - 1 do
+        if pos >= wire:len() then
+            return nil
+        end
+        local byte = wire:get_index(pos)
+        pos = pos + 1
+        if stuffed and byte == STUFF_ESCAPE then
+            if pos >= wire:len() then
+                return nil
+            end
+            pos = pos + 1
+        end
+        out:set_index(i, byte)
+    end
+    return out, pos
+end
+
+-- Unstuffed packet starting at the marker in `start`, and the wire position after it.
+-- Returns nil when the packet continues in the next frame.
+local function read_packet(wire, start, stuffed)
+    local packet = wire:subset(start, 2)
+    local pos = start + 2
+
+    local function take(count)
+        local bytes, next_pos = read_body(wire, pos, count, stuffed)
+        if bytes == nil then
+            return nil
+        end
+        packet:append(bytes)
+        pos = next_pos
+        return bytes
+    end
+
+    local type_byte = take(1)
+    if type_byte == nil then
+        return nil
+    end
+
+    local flags = 0
+    if bit.band(type_byte:get_index(0), TYPE_FLAGS_BIT) ~= 0 then
+        local flags_byte = take(1)
+        if flags_byte == nil then
+            return nil
+        end
+        flags = flags_byte:get_index(0)
+    end
+
+    if bit.band(flags, FLAG_SEQ) ~= 0 and take(1) == nil then
+        return nil
+    end
+
+    local size_len = bit.band(flags, FLAG_EXT_LEN) ~= 0 and 2 or 1
+    local size = take(size_len)
+    if size == nil then
+        return nil
+    end
+    local length = size:get_index(0)
+    if size_len == 2 then
+        length = length + size:get_index(1) * 256
+    end
+
+    if length > 0 and take(length) == nil then
+        return nil
+    end
+    if bit.band(flags, FLAG_CRC) ~= 0 and take(2) == nil then
+        return nil
+    end
+
+    return packet, pos
+end
+
+-- ——————————————————————————————————————————— Dissector ————————————————————————————————————————————
+
+local function dissect_payload(ptvb, tree, type_id, flags, offset, length)
+    local item = tree:add(f.payload, ptvb(offset, length))
+    local pos = offset
+    local stop = offset + length
+
+    if bit.band(flags, FLAG_FRAGMENT) ~= 0 and stop - pos >= 3 then
+        item:add(f.message_id, ptvb(pos, 1))
+        item:add_le(f.frag_index, ptvb(pos + 1, 2))
+        item:add_le(f.frag_last, ptvb(pos + 1, 2))
+        -- Only the message as a whole has a meaning
+        return
+    end
+
+    if bit.band(flags, FLAG_RECORD) ~= 0 and stop - pos >= 1 then
+        item:add(f.record_id, ptvb(pos, 1))
+        pos = pos + 1
+    end
+
+    if type_id == TYPE_START and stop - pos >= 2 then
+        item:add(f.version, ptvb(pos, 1))
+        item:add(f.capabilities, ptvb(pos + 1, 1))
+        if stop - pos > 2 then
+            item:add(f.identity, ptvb(pos + 2, stop - pos - 2))
+        end
+    elseif (type_id == TYPE_ACK or type_id == TYPE_NAK) and stop - pos >= 1 then
+        item:add(f.ack_seq, ptvb(pos, 1))
+        if type_id == TYPE_NAK and stop - pos >= 2 then
+            item:add(f.nak_code, ptvb(pos + 1, 1))
+        end
+    elseif (type_id == TYPE_ERROR or type_id == TYPE_COMMAND) and stop > pos then
+        item:add(f.text, ptvb(pos, stop - pos))
+    end
+end
+
+-- Returns the type name for the Info column
+local function dissect_packet(ptvb, tree)
+    tree:add(f.marker, ptvb(0, 2))
+
+    local type_byte = ptvb(2, 1):uint()
+    local type_id = bit.band(type_byte, 0x7F)
+    tree:add(f.type, ptvb(2, 1))
+    tree:add(f.flagged, ptvb(2, 1))
+    local offset = 3
+
+    local flags = 0
+    if bit.band(type_byte, TYPE_FLAGS_BIT) ~= 0 then
+        flags = ptvb(offset, 1):uint()
+        local flags_item = tree:add(f.flags, ptvb(offset, 1))
+        for _, field in ipairs({ f.flag_crc, f.flag_ext_len, f.flag_frag, f.flag_seq, f.flag_record }) do
+            flags_item:add(field, ptvb(offset, 1))
+        end
+        offset = offset + 1
+    end
+
+    if bit.band(flags, FLAG_SEQ) ~= 0 then
+        tree:add(f.seq, ptvb(offset, 1))
+        offset = offset + 1
+    end
+
+    local length
+    if bit.band(flags, FLAG_EXT_LEN) ~= 0 then
+        length = ptvb(offset, 2):le_uint()
+        tree:add_le(f.length, ptvb(offset, 2))
+        offset = offset + 2
+    else
+        length = ptvb(offset, 1):uint()
+        tree:add(f.length, ptvb(offset, 1))
+        offset = offset + 1
+    end
+
+    if length > 0 then
+        dissect_payload(ptvb, tree, type_id, flags, offset, length)
+        offset = offset + length
+    end
+
+    if bit.band(flags, FLAG_CRC) ~= 0 then
+        local expected = crc16_ccitt(ptvb():bytes(), 2, offset - 2)
+        local item = tree:add_le(f.crc, ptvb(offset, 2))
+        if ptvb(offset, 2):le_uint() ~= expected then
+            item:append_text(string.format(" [incorrect, should be 0x%04x]", expected))
+            item:add_proto_expert_info(e.bad_crc)
+        end
+    end
+
+    return packet_types[type_id] or string.format("Type %d", type_id)
+end
+
+function mxs.dissector(tvb, pinfo, tree)
+    pinfo.cols.protocol = "MXS"
+    if pinfo.p2p_dir == P2P_DIR_RECV then
+        pinfo.cols.src = "device"
+        pinfo.cols.dst = "host"
+    elseif pinfo.p2p_dir == P2P_DIR_SENT then
+        pinfo.cols.src = "host"
+        pinfo.cols.dst = "device"
+    end
+
+    local stuffed = is_stuffed()
+    local wire = tvb():bytes()
+    local root = tree:add(mxs, tvb())
+    local summary = {}
+    local pos = 0
+
+    while pos < wire:len() do
+        local start = find_marker(wire, pos) or wire:len()
+
+        -- Debug output and the tail of a packet from the previous frame
+        if start > pos then
+            root:add(f.stream, tvb(pos, start - pos))
+            table.insert(summary, "Text")
+        end
+        if start >= wire:len() then
+            break
+        end
+
+        local packet, next_pos = read_packet(wire, start, stuffed)
+        if packet == nil then
+            local item = root:add(f.packet, tvb(start))
+            item:add_proto_expert_info(e.truncated)
+            table.insert(summary, "Partial packet")
+            break
+        end
+
+        local ptvb = stuffed and packet:tvb("Unstuffed packet") or tvb(start, next_pos - start):tvb()
+        local packet_tree = root:add(f.packet, tvb(start, next_pos - start))
+        local name = dissect_packet(ptvb, packet_tree)
+        packet_tree:set_text("MXS " .. name)
+        table.insert(summary, name)
+        pos = next_pos
+    end
+
+    pinfo.cols.info = table.concat(summary, ", ")
+end
+
+DissectorTable.get("wtap_encap"):add(wtap.USER0, mxs)